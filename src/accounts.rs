@@ -0,0 +1,321 @@
+use crate::dirs;
+use crate::mode::{Match, Stats, Summary};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// The server's accounts, kept in one JSON file: each name with its password's Argon2
+// hash (never the password), the matches it played, its best run in each solo mode
+// and running totals. The whole file is rewritten after every change, through a
+// temporary file so a crash can't leave it half written.
+
+/// How many of each account's matches are kept, newest last.
+pub const HISTORY_KEPT: usize = 100;
+
+/// How many matches a history query answers with.
+pub const HISTORY_SHOWN: usize = 10;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Accounts {
+    accounts: BTreeMap<String, Account>,
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Account {
+    // A PHC string, salt and parameters included
+    password: String,
+    pub history: Vec<Match>,
+    /// The best run in each solo mode, by the mode's name.
+    pub bests: BTreeMap<String, Summary>,
+    pub stats: Stats,
+}
+
+impl Accounts {
+    /// Reads the accounts kept at `path`, which later changes are saved back to. A
+    /// missing file just means none yet.
+    pub fn open(path: &Path) -> io::Result<Accounts> {
+        let mut accounts = match fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Accounts::default(),
+            Err(e) => return Err(e),
+        };
+        accounts.path = Some(path.to_path_buf());
+        Ok(accounts)
+    }
+
+    /// `$XDG_DATA_HOME/tetriszero/accounts.json`, falling back to `~/.local/share`.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::data_dir()?.join("accounts.json"))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Account> {
+        self.accounts.get(name)
+    }
+
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.accounts.contains_key(name)
+    }
+
+    /// Checks that `name` and `password` would make a new account, before the slow part
+    /// of registering, `hash_password`.
+    pub fn check_new(&self, name: &str, password: &str) -> Result<(), String> {
+        check_name(name)?;
+        if password.is_empty() {
+            return Err(String::from("the password can't be empty"));
+        }
+        // Lines are trimmed on the way in, which would quietly drop these
+        if password.trim() != password {
+            return Err(String::from("the password can't start or end with a space"));
+        }
+        if self.is_registered(name) {
+            return Err(format!("{} is already taken", name));
+        }
+        Ok(())
+    }
+
+    /// Makes an account for `name` with a hash from `hash_password`, keeping it only if
+    /// it could be saved.
+    pub fn add(&mut self, name: &str, password: String) -> Result<(), String> {
+        // Another registration may have got there while this one was hashing
+        if self.is_registered(name) {
+            return Err(format!("{} is already taken", name));
+        }
+        let account = Account {
+            password,
+            history: Vec::new(),
+            bests: BTreeMap::new(),
+            stats: Stats::default(),
+        };
+        self.accounts.insert(name.to_string(), account);
+        if let Err(e) = self.save() {
+            self.accounts.remove(name);
+            return Err(format!("couldn't save the account: {}", e));
+        }
+        Ok(())
+    }
+
+    /// The hash `name`'s password is checked against with `verify_password`.
+    pub fn password(&self, name: &str) -> Result<String, String> {
+        self.get(name)
+            .map(|account| account.password.clone())
+            .ok_or_else(wrong_login)
+    }
+
+    /// Adds a match to `name`'s history and totals, keeping its summary as a personal
+    /// best if it beats the one for its mode. Returns whether it did.
+    pub fn record(&mut self, name: &str, entry: Match) -> io::Result<bool> {
+        let Some(account) = self.accounts.get_mut(name) else {
+            return Ok(false);
+        };
+        let summary = entry.summary;
+        account.stats.add(&summary);
+        account.history.push(entry);
+        if account.history.len() > HISTORY_KEPT {
+            account.history.remove(0);
+        }
+        let best = match summary.mode.record(&summary) {
+            Some(record) => match account
+                .bests
+                .get(&summary.mode.encode())
+                .and_then(|best| summary.mode.record(best))
+            {
+                Some(best) => summary.mode.beats(record, best),
+                None => true,
+            },
+            None => false,
+        };
+        if best {
+            account.bests.insert(summary.mode.encode(), summary);
+        }
+        self.save()?;
+        Ok(best)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let text = serde_json::to_string_pretty(self).expect("accounts are always representable");
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, text)?;
+        fs::rename(&temporary, path)
+    }
+}
+
+/// A PHC string for `password`, salt and parameters included. Hashing is deliberately
+/// slow, so this is best kept off an async runtime's threads, and outside any lock.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| e.to_string())?;
+    Ok(hash.to_string())
+}
+
+/// Checks `password` against a hash from `hash_password`. As slow as hashing it.
+pub fn verify_password(hash: &str, password: &str) -> Result<(), String> {
+    let hash = PasswordHash::new(hash).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .map_err(|_| wrong_login())
+}
+
+// The same for a name with no account as for a wrong password
+fn wrong_login() -> String {
+    String::from("wrong name or password")
+}
+
+/// Account names are single words, so they can go anywhere in a protocol line.
+pub fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.chars().count() > 16 {
+        return Err(String::from("names are 1 to 16 characters"));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(String::from(
+            "names are letters, digits, underscores and dashes",
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mode::GameMode;
+    use crate::protocol::Request;
+
+    fn sprint(frames: u64, completed: bool) -> Match {
+        Match::new(
+            1,
+            Summary {
+                mode: GameMode::Sprint { lines: 40 },
+                completed,
+                frames,
+                pieces: 100,
+                lines: 40,
+                keys: 300,
+                attack: 0,
+                score: 0,
+                faults: 0,
+            },
+        )
+    }
+
+    #[test]
+    fn registered_passwords_log_in() {
+        let mut accounts = Accounts::default();
+        accounts.check_new("alice", "hunter two").unwrap();
+        let hash = hash_password("hunter two").unwrap();
+        assert!(hash.starts_with("$argon2"));
+        assert!(!hash.contains("hunter"));
+        accounts.add("alice", hash.clone()).unwrap();
+
+        assert_eq!(accounts.password("alice").unwrap(), hash);
+        verify_password(&accounts.password("alice").unwrap(), "hunter two").unwrap();
+        assert!(accounts.check_new("alice", "other").is_err());
+        assert!(accounts.add("alice", hash).is_err());
+    }
+
+    #[test]
+    fn wrong_passwords_are_refused() {
+        let mut accounts = Accounts::default();
+        accounts
+            .add("alice", hash_password("hunter2").unwrap())
+            .unwrap();
+        let hash = accounts.password("alice").unwrap();
+        assert_eq!(verify_password(&hash, "hunter3"), Err(wrong_login()));
+        assert_eq!(verify_password(&hash, ""), Err(wrong_login()));
+        // An unknown name says no more than a wrong password
+        assert_eq!(accounts.password("bob"), Err(wrong_login()));
+
+        assert!(accounts.check_new("bob", "").is_err());
+        assert!(accounts.check_new("bob", " hunter2").is_err());
+        assert!(accounts.check_new("bob", "hunter2 ").is_err());
+        assert!(accounts.check_new("bob smith", "hunter2").is_err());
+    }
+
+    #[test]
+    fn history_keeps_the_newest() {
+        let mut accounts = Accounts::default();
+        accounts.add("alice", String::new()).unwrap();
+        for frames in 0..HISTORY_KEPT as u64 + 5 {
+            accounts.record("alice", sprint(frames, false)).unwrap();
+        }
+        let account = accounts.get("alice").unwrap();
+        assert_eq!(account.history.len(), HISTORY_KEPT);
+        assert_eq!(account.history[0].summary.frames, 5);
+        assert_eq!(
+            account.history.last().unwrap().summary.frames,
+            HISTORY_KEPT as u64 + 4
+        );
+        // Every match still counts toward the totals
+        assert_eq!(account.stats.played, HISTORY_KEPT + 5);
+    }
+
+    #[test]
+    fn bests_are_only_replaced_by_better_runs() {
+        let mut accounts = Accounts::default();
+        accounts.add("alice", String::new()).unwrap();
+        let best = |accounts: &Accounts| accounts.get("alice").unwrap().bests["sprint:40"].frames;
+
+        // An unfinished sprint has no record to keep
+        assert!(!accounts.record("alice", sprint(1000, false)).unwrap());
+        assert!(accounts.get("alice").unwrap().bests.is_empty());
+
+        assert!(accounts.record("alice", sprint(3000, true)).unwrap());
+        assert_eq!(best(&accounts), 3000);
+        assert!(!accounts.record("alice", sprint(3500, true)).unwrap());
+        assert!(!accounts.record("alice", sprint(3000, true)).unwrap());
+        assert_eq!(best(&accounts), 3000);
+        assert!(accounts.record("alice", sprint(2500, true)).unwrap());
+        assert_eq!(best(&accounts), 2500);
+
+        // Nobody's account, nothing recorded
+        assert!(!accounts.record("bob", sprint(100, true)).unwrap());
+    }
+
+    #[test]
+    fn malformed_sign_ins_are_refused() {
+        assert_eq!(
+            Request::decode("Login alice hunter two"),
+            Some(Request::Login {
+                name: String::from("alice"),
+                password: String::from("hunter two"),
+            })
+        );
+        let request = Request::Register {
+            name: String::from("alice"),
+            password: String::from("hunter two"),
+        };
+        assert_eq!(Request::decode(&request.encode()), Some(request));
+        for line in [
+            "Login",
+            "Login alice",
+            "Login alice ",
+            "Login  hunter2",
+            "Register",
+            "Register alice",
+            "Register alice ",
+            "Register  hunter2",
+        ] {
+            assert_eq!(Request::decode(line), None, "{:?}", line);
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::board::Board;
+use crate::bot::{BotState, Plan};
+use crate::eval::{Features, Weights};
+use crate::tetromino::Kind;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// Looks ahead through the next queue and hold, keeping the `width` best-looking
+/// positions at each depth, and plays towards whichever was best at the end.
+#[derive(Debug, Clone)]
+pub struct BeamSearch {
+    pub width: usize,
+    /// Most pieces to look ahead, including the current one. The search also stops
+    /// when the known queue runs out.
+    pub depth: usize,
+    /// Deeper levels aren't started after this, and one running over it is thrown away.
+    pub think_time: Duration,
+    pub weights: Weights,
+}
+
+impl Default for BeamSearch {
+    fn default() -> Self {
+        BeamSearch {
+            width: 64,
+            depth: 6,
+            think_time: Duration::from_millis(200),
+            weights: Weights::default(),
+        }
+    }
+}
+
+struct Node {
+    state: BotState,
+    // Rewards collected along the way
+    reward: f32,
+    // What we rank by: rewards so far plus how the board looks now
+    value: f32,
+    // Which of the first plans this line started with
+    root: usize,
+}
+
+impl BeamSearch {
+    /// The best plan for the next piece, or None if every option tops out.
+    pub fn search(&self, state: &BotState) -> Option<Plan> {
+        let deadline = Instant::now() + self.think_time;
+        let roots = state.plans();
+        let mut beam = Vec::new();
+        for (i, plan) in roots.iter().enumerate() {
+            if let Some(node) = self.child(state, plan, 0.0, i) {
+                beam.push(node);
+            }
+        }
+        self.prune(&mut beam);
+
+        for _ in 1..self.depth {
+            let mut children = Vec::new();
+            for node in &beam {
+                if Instant::now() >= deadline {
+                    return Some(roots[beam.first()?.root].clone());
+                }
+                for plan in node.state.plans() {
+                    if let Some(child) = self.child(&node.state, &plan, node.reward, node.root) {
+                        children.push(child);
+                    }
+                }
+            }
+            if children.is_empty() {
+                break;
+            }
+            self.prune(&mut children);
+            beam = children;
+        }
+        Some(roots[beam.first()?.root].clone())
+    }
+
+    fn child(&self, state: &BotState, plan: &Plan, reward: f32, root: usize) -> Option<Node> {
+        let (next, result) = state.play(plan);
+        if next.dead() {
+            return None;
+        }
+        let reward = reward + self.weights.reward(&result);
+        Some(Node {
+            value: reward + self.weights.board(&Features::of(&next.board)),
+            reward,
+            state: next,
+            root,
+        })
+    }
+
+    // Best first, one of each position, at most `width` of them
+    fn prune(&self, nodes: &mut Vec<Node>) {
+        nodes.sort_by(|a, b| b.value.total_cmp(&a.value));
+        let mut seen: HashSet<(Board, Option<Kind>, Option<Kind>)> = HashSet::new();
+        nodes.retain(|node| {
+            let current = node.state.piece.as_ref().map(|(t, _)| t.kind);
+            seen.insert((node.state.board.clone(), node.state.hold, current))
+        });
+        nodes.truncate(self.width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::{BOARD_HEIGHT, BOARD_WIDTH};
+    use crate::rules::Chain;
+    use crate::tetromino::Color;
+
+    // `rows` full lines at the bottom, but for a well down the right
+    fn well(rows: usize, kinds: &[Kind]) -> BotState {
+        let mut board = Board::new();
+        for y in BOARD_HEIGHT - rows..BOARD_HEIGHT {
+            for x in 0..BOARD_WIDTH - 1 {
+                board.set_cell(x, y, Some(Color::Gray));
+            }
+        }
+        let mut state = BotState {
+            board,
+            chain: Chain::default(),
+            piece: None,
+            hold: None,
+            hold_enabled: true,
+            can_hold: true,
+            queue: Vec::new(),
+            bag: Vec::new(),
+        };
+        for kind in kinds {
+            state.deal(*kind);
+        }
+        state
+    }
+
+    fn search(depth: usize) -> BeamSearch {
+        BeamSearch {
+            depth,
+            // Only the depth may end it, so every run looks just as far
+            think_time: Duration::from_secs(3600),
+            ..BeamSearch::default()
+        }
+    }
+
+    #[test]
+    fn takes_the_tetris() {
+        let state = well(4, &[Kind::I]);
+        let plan = search(1).search(&state).unwrap();
+        assert!(!plan.hold);
+        assert_eq!(plan.placement.kind, Kind::I);
+        let (next, result) = state.play(&plan);
+        assert_eq!(result.lines, 4);
+        assert!(next.board.is_empty());
+    }
+
+    #[test]
+    fn keeps_the_well_open_for_the_i() {
+        // An O first mustn't go in the well with the I coming next
+        let state = well(4, &[Kind::O, Kind::I, Kind::O]);
+        let search = search(3);
+        let plan = search.search(&state).unwrap();
+        let (next, _) = state.play(&plan);
+        let plan = search.search(&next).unwrap();
+        assert_eq!(plan.placement.kind, Kind::I);
+        assert_eq!(next.play(&plan).1.lines, 4);
+        // The same every time
+        assert_eq!(search.search(&next), Some(plan));
+    }
+}
@@ -0,0 +1,220 @@
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use tetris_zero::client;
+use tetris_zero::config::ClientConfig;
+use tetris_zero::mode::GameMode;
+use tetris_zero::protocol::Request;
+use tetris_zero::puzzle::Puzzle;
+use tetris_zero::save;
+use tetris_zero::session::Session;
+
+const USAGE: &str =
+    "Usage: tetris-client [--config FILE] [--das N] [--arr N] [--sdf N] [--dcd N] [--local MODE] [--puzzle FILE] [--save FILE] [--resume FILE] [--snapshot FILE] [--login | --register] [address] [name]\n\
+     --local plays a solo MODE (sprint, ultra, marathon, cheese or survival; see tetris-2) offline instead of joining a server,\n\
+     and --puzzle the puzzle in FILE. --save keeps an offline game left unfinished in FILE, and --resume carries on from one.\n\
+     --snapshot saves the server's match as it stands in FILE, without joining it.\n\
+     --login signs in to the server's account for name, and --register makes one, with the password in\n\
+     $TETRISZERO_PASSWORD or asked for";
+
+fn main() {
+    // Handling flags are applied over whatever the config file says
+    let mut overrides: Vec<(String, u32)> = Vec::new();
+    let mut config_path = None;
+    let mut local = None;
+    let mut save_path = None;
+    let mut snapshot_path = None;
+    // Whether to register rather than log in, if signing in at all
+    let mut sign_in = None;
+    let mut positional = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(path) => config_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--local" => match args.next().as_deref().and_then(GameMode::parse) {
+                Some(mode) if mode.is_solo() && !matches!(mode, GameMode::Puzzle { .. }) => {
+                    local = Some(Session::new(rand::random(), mode))
+                }
+                _ => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--puzzle" => match args.next().map(|path| Puzzle::load(path.as_ref())) {
+                Some(Ok(puzzle)) => local = Some(Session::with_puzzle(rand::random(), puzzle)),
+                Some(Err(e)) => {
+                    eprintln!("Bad puzzle: {}", e);
+                    return;
+                }
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--save" => match args.next() {
+                Some(path) => save_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--snapshot" => match args.next() {
+                Some(path) => snapshot_path = Some(PathBuf::from(path)),
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--resume" => match args.next().map(|path| save::load(path.as_ref())) {
+                Some(Ok(session)) => local = Some(session),
+                Some(Err(e)) => {
+                    eprintln!("Bad save: {}", e);
+                    return;
+                }
+                None => {
+                    eprintln!("{}", USAGE);
+                    return;
+                }
+            },
+            "--login" => sign_in = Some(false),
+            "--register" => sign_in = Some(true),
+            "--das" | "--arr" | "--sdf" | "--dcd" => {
+                match args.next().and_then(|v| v.parse().ok()) {
+                    Some(value) => overrides.push((arg, value)),
+                    None => {
+                        eprintln!("{}", USAGE);
+                        return;
+                    }
+                }
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    let mut config = match config_path.or_else(ClientConfig::default_path) {
+        Some(path) => match ClientConfig::load(&path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Bad config: {}", e);
+                return;
+            }
+        },
+        None => ClientConfig::default(),
+    };
+    for (flag, value) in overrides {
+        let field = match flag.as_str() {
+            "--das" => &mut config.handling.das,
+            "--arr" => &mut config.handling.arr,
+            "--sdf" => &mut config.handling.sdf,
+            _ => &mut config.handling.dcd,
+        };
+        *field = value;
+    }
+
+    let mut positional = positional.into_iter();
+    // Playing locally there's no address, just a name
+    let addr = match &local {
+        Some(_) => String::new(),
+        None => positional
+            .next()
+            .unwrap_or_else(|| String::from("127.0.0.1:7878")),
+    };
+    let name = positional
+        .next()
+        .or_else(|| std::env::var("USER").ok())
+        .unwrap_or_else(|| String::from("player"));
+
+    if let Some(path) = snapshot_path {
+        if local.is_some() {
+            eprintln!("{}", USAGE);
+            return;
+        }
+        if let Err(e) = client::snapshot(&addr).and_then(|session| save::save(&path, &session)) {
+            eprintln!("Couldn't take a snapshot: {}", e);
+        }
+        return;
+    }
+
+    let join = match (&local, sign_in) {
+        (None, Some(register)) => {
+            let password = match std::env::var("TETRISZERO_PASSWORD") {
+                Ok(password) => password,
+                Err(_) => match ask_password() {
+                    Ok(password) => password,
+                    Err(e) => {
+                        eprintln!("Couldn't read the password: {}", e);
+                        return;
+                    }
+                },
+            };
+            let request = match register {
+                true => Request::Register {
+                    name: name.clone(),
+                    password,
+                },
+                false => Request::Login {
+                    name: name.clone(),
+                    password,
+                },
+            };
+            request.encode()
+        }
+        _ => name.clone(),
+    };
+
+    let result = match local {
+        Some(session) => client::run_local(session, &name, config, save_path.as_deref()),
+        None => client::run(&addr, &join, config),
+    };
+    if let Err(e) = result {
+        eprintln!("Client error: {}", e);
+    }
+}
+
+// Typed in at a terminal the password isn't echoed. Piped in, it's the first line.
+fn ask_password() -> io::Result<String> {
+    if !io::stdin().is_terminal() {
+        let mut password = String::new();
+        io::stdin().lock().read_line(&mut password)?;
+        return Ok(password.trim_end_matches(['\r', '\n']).to_string());
+    }
+    print!("Password: ");
+    io::stdout().flush()?;
+    terminal::enable_raw_mode()?;
+    let password = read_hidden();
+    let _ = terminal::disable_raw_mode();
+    println!();
+    password
+}
+
+// Keys up to Enter, in raw mode so nothing shows. Escape or Ctrl-C gives up.
+fn read_hidden() -> io::Result<String> {
+    let mut password = String::new();
+    loop {
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        match key.code {
+            KeyCode::Enter => return Ok(password),
+            KeyCode::Backspace => {
+                password.pop();
+            }
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+            }
+            KeyCode::Esc => return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled")),
+            KeyCode::Char(c) => password.push(c),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::fs::File;
+use std::io::BufReader;
+use tetris_zero::board::{BOARD_HEIGHT, BOARD_WIDTH};
+use tetris_zero::fumen::{self, Page};
+use tetris_zero::protocol::color_char;
+use tetris_zero::puzzle::Puzzle;
+use tetris_zero::selfplay::Reader;
+
+const USAGE: &str =
+    "Usage: tetris-fumen show FUMEN | puzzle FILE | selfplay FILE [--game N] [--player N]\n\
+     show prints each page of a v115 fumen, puzzle turns a puzzle's field into one,\n\
+     and selfplay turns a game from tetris-selfplay into one, a page per piece played";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["show", text] => match fumen::decode(text) {
+            Ok(pages) => show(&pages),
+            Err(e) => eprintln!("Bad fumen: {}", e),
+        },
+        ["puzzle", path] => match Puzzle::load(path.as_ref()) {
+            Ok(puzzle) => {
+                let mut page = Page::new(puzzle.board.clone());
+                page.comment = puzzle.name;
+                println!("{}", fumen::encode(&[page]));
+            }
+            Err(e) => eprintln!("Bad puzzle: {}", e),
+        },
+        ["selfplay", path, options @ ..] => {
+            let (mut game, mut player) = (0, 0);
+            for option in options.chunks(2) {
+                match option {
+                    ["--game", n] if n.parse::<usize>().is_ok() => game = n.parse().unwrap(),
+                    ["--player", n] if n.parse::<u8>().is_ok() => player = n.parse().unwrap(),
+                    _ => return eprintln!("{}", USAGE),
+                }
+            }
+            match selfplay(path, game, player) {
+                Ok(text) => println!("{}", text),
+                Err(e) => eprintln!("Couldn't read the game: {}", e),
+            }
+        }
+        _ => eprintln!("{}", USAGE),
+    }
+}
+
+fn show(pages: &[Page]) {
+    for (n, page) in pages.iter().enumerate() {
+        if n > 0 {
+            println!();
+        }
+        match page.comment.as_str() {
+            "" => println!("{}:", n + 1),
+            comment => println!("{}: {}", n + 1, comment),
+        }
+        // The piece in lowercase over the field, from the highest row anything is in
+        let mut field: Vec<Vec<char>> = (0..BOARD_HEIGHT)
+            .map(|y| {
+                (0..BOARD_WIDTH)
+                    .map(|x| color_char(page.board.cell(x, y)))
+                    .collect()
+            })
+            .collect();
+        if let Some((tetromino, position)) = &page.piece {
+            let letter = tetromino.kind.letter().to_ascii_lowercase();
+            tetromino.each_point(&mut |row, col| {
+                field[(position.y + row) as usize][(position.x + col) as usize] = letter;
+            });
+        }
+        let top = field
+            .iter()
+            .position(|row| row.iter().any(|c| *c != '.'))
+            .unwrap_or(BOARD_HEIGHT - 1);
+        for row in &field[top..] {
+            println!("{}", row.iter().collect::<String>());
+        }
+    }
+}
+
+// One player's pieces in a game, each on the field it was played on
+fn selfplay(path: &str, game: usize, player: u8) -> Result<String, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let mut reader = Reader::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+    let record = reader
+        .nth(game)
+        .ok_or_else(|| format!("there's no game {}", game))?
+        .map_err(|e| e.to_string())?;
+    let pages: Vec<Page> = record
+        .moves
+        .iter()
+        .filter(|m| m.player == player)
+        .filter_map(|m| {
+            let visit = m.visits.get(m.chosen?)?;
+            let mut page = Page::new(m.board.clone());
+            page.piece = Some((visit.placement.tetromino(), visit.placement.position));
+            let queue: String = m.queue.iter().map(|k| k.letter()).collect();
+            page.comment = match m.hold {
+                Some(kind) => format!("Next {}, hold {}", queue, kind.letter()),
+                None => format!("Next {}", queue),
+            };
+            Some(page)
+        })
+        .collect();
+    if pages.is_empty() {
+        return Err(format!("player {} has no moves in game {}", player, game));
+    }
+    Ok(fumen::encode(&pages))
+}
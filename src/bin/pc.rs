@@ -0,0 +1,82 @@
+use tetris_zero::board::{Board, BOARD_HEIGHT, BOARD_WIDTH};
+use tetris_zero::pc::{Solver, MAX_HEIGHT};
+use tetris_zero::protocol::color_char;
+use tetris_zero::puzzle::Puzzle;
+use tetris_zero::tetromino::Kind;
+
+const USAGE: &str = "Usage: tetris-pc [--queue PIECES] [--hold PIECE] [--height N] [--limit N] [--no-hold] [PUZZLE]\n\
+     Lists every perfect clear within N lines (default 4) playing PIECES, the piece in play first.\n\
+     The field, pieces and hold can come from a puzzle file instead; the options override it";
+
+fn main() {
+    let mut solver = Solver::default();
+    let mut queue = None;
+    let mut hold = None;
+    let mut path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--no-hold" {
+            solver.hold = false;
+            continue;
+        }
+        if !arg.starts_with("--") && path.is_none() {
+            path = Some(arg);
+            continue;
+        }
+        let Some(value) = args.next() else {
+            return eprintln!("{}", USAGE);
+        };
+        let number = value.parse::<usize>().ok();
+        match (arg.as_str(), number) {
+            ("--queue", _) => match kinds(&value) {
+                Some(kinds) if !kinds.is_empty() => queue = Some(kinds),
+                _ => return eprintln!("{}", USAGE),
+            },
+            ("--hold", _) => match kinds(&value).as_deref() {
+                Some([kind]) => hold = Some(*kind),
+                _ => return eprintln!("{}", USAGE),
+            },
+            ("--height", Some(n)) if (1..=MAX_HEIGHT).contains(&n) => solver.height = n,
+            ("--limit", Some(n)) if n > 0 => solver.limit = Some(n),
+            _ => return eprintln!("{}", USAGE),
+        }
+    }
+
+    let puzzle = match path.map(|path| Puzzle::load(path.as_ref())) {
+        Some(Ok(puzzle)) => Some(puzzle),
+        Some(Err(e)) => return eprintln!("Bad puzzle: {}", e),
+        None => None,
+    };
+    let board = puzzle.as_ref().map_or_else(Board::new, |p| p.board.clone());
+    let hold = hold.or(puzzle.as_ref().and_then(|p| p.hold));
+    let Some(pieces) = queue.or(puzzle.map(|p| p.pieces)) else {
+        return eprintln!("{}", USAGE);
+    };
+    let Some((current, queue)) = pieces.split_first() else {
+        return eprintln!("No pieces to play");
+    };
+
+    let solutions = solver.solve(&board, *current, hold, queue);
+    match solutions.len() {
+        0 => println!("No perfect clear within {} lines", solver.height),
+        1 => println!("1 perfect clear (* = played from hold):"),
+        n => println!("{} perfect clears (* = played from hold):", n),
+    }
+    for (n, solution) in solutions.iter().enumerate() {
+        println!();
+        println!("{}: {}", n + 1, solution.describe());
+        let field = solution.field(&board);
+        for y in BOARD_HEIGHT - solution.lines..BOARD_HEIGHT {
+            let row: String = (0..BOARD_WIDTH)
+                .map(|x| color_char(field.cell(x, y)))
+                .collect();
+            println!("{}", row);
+        }
+    }
+}
+
+fn kinds(text: &str) -> Option<Vec<Kind>> {
+    text.chars()
+        .map(|c| Kind::from_letter(c.to_ascii_uppercase()))
+        .collect()
+}
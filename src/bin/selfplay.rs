@@ -0,0 +1,111 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+use tetris_zero::mcts::Evaluator;
+use tetris_zero::nn::Network;
+use tetris_zero::selfplay::{self, SelfPlay};
+
+const USAGE: &str = "Usage: tetris-selfplay [--games N] [--seed S] [--players N] [--iterations N]\n\
+     [--max-pieces N] [--sample-moves N] [--threads N] [--network FILE] [--out FILE]\n\
+     Plays games from seeds S, S+1, ... and writes them to FILE (default selfplay.tzsp, - for stdout).\n\
+     The search uses the heuristic evaluator unless given a network";
+
+fn main() {
+    let mut games: u64 = 100;
+    let mut seed: u64 = 0;
+    let mut threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut out = String::from("selfplay.tzsp");
+    let mut network = None;
+    let mut config = SelfPlay::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(value) = args.next() else {
+            return eprintln!("{}", USAGE);
+        };
+        let number = value.parse::<u64>().ok();
+        match (arg.as_str(), number) {
+            ("--games", Some(n)) => games = n,
+            ("--seed", Some(n)) => seed = n,
+            ("--players", Some(n)) if n > 0 => config.players = n as usize,
+            ("--iterations", Some(n)) => config.mcts.iterations = Some(n as u32),
+            ("--max-pieces", Some(n)) => config.max_pieces = n as usize,
+            ("--sample-moves", Some(n)) => config.sample_moves = n as usize,
+            ("--threads", Some(n)) if n > 0 => threads = n as usize,
+            ("--network", _) => network = Some(PathBuf::from(value)),
+            ("--out", _) => out = value,
+            _ => return eprintln!("{}", USAGE),
+        }
+    }
+    // Parallelism comes from running games side by side, and searches stop on their
+    // iteration count rather than the clock
+    config.mcts.threads = 1;
+    config.mcts.think_time = Duration::from_secs(u32::MAX as u64);
+
+    let result = match network.map(|path| Network::load(&path)) {
+        None => run(&config, seed, games, threads, &out),
+        Some(Ok(network)) => {
+            let config = SelfPlay {
+                mcts: config.mcts.with_evaluator(network),
+                rules: config.rules,
+                players: config.players,
+                max_pieces: config.max_pieces,
+                sample_moves: config.sample_moves,
+            };
+            run(&config, seed, games, threads, &out)
+        }
+        Some(Err(e)) => Err(e),
+    };
+    if let Err(e) = result {
+        eprintln!("Self-play failed: {}", e);
+    }
+}
+
+fn run<E: Evaluator>(
+    config: &SelfPlay<E>,
+    seed: u64,
+    games: u64,
+    threads: usize,
+    out: &str,
+) -> io::Result<()> {
+    let mut output: Box<dyn Write> = if out == "-" {
+        Box::new(BufWriter::new(io::stdout().lock()))
+    } else {
+        Box::new(BufWriter::new(File::create(out)?))
+    };
+    selfplay::write_header(&mut output)?;
+
+    let next = AtomicU64::new(0);
+    let (tx, rx) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let next = &next;
+            scope.spawn(move || loop {
+                let game = next.fetch_add(1, Ordering::Relaxed);
+                if game >= games || tx.send(config.play(seed + game)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+        // Games are written as they finish, so the order depends on timing
+        let mut written = 0;
+        for record in rx {
+            record.write(&mut output)?;
+            written += 1;
+            let pieces: u32 = record.outcomes.iter().map(|o| o.pieces).sum();
+            eprintln!(
+                "[+] game {}/{} (seed {}): {} moves, {} pieces",
+                written,
+                games,
+                record.seed,
+                record.moves.len(),
+                pieces
+            );
+        }
+        output.flush()
+    })
+}
@@ -0,0 +1,51 @@
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use tetris_zero::bot::{Bot, Difficulty};
+use tetris_zero::eval::Weights;
+use tetris_zero::tbp;
+
+const USAGE: &str = "Usage: tetris-tbp [--level LEVEL] [--think-ms N] [--weights FILE]\n\
+     Speaks the Tetris Bot Protocol on stdin/stdout. LEVEL is easy, medium, hard or\n\
+     <pps>:<depth>:<mistake rate>";
+
+fn main() {
+    let mut difficulty = Difficulty::HARD;
+    let mut think_time = None;
+    let mut weights_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--level" => match args.next().as_deref().and_then(Difficulty::parse) {
+                Some(level) => difficulty = level,
+                None => return eprintln!("{}", USAGE),
+            },
+            "--think-ms" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(ms) => think_time = Some(Duration::from_millis(ms)),
+                None => return eprintln!("{}", USAGE),
+            },
+            "--weights" => match args.next() {
+                Some(path) => weights_path = Some(PathBuf::from(path)),
+                None => return eprintln!("{}", USAGE),
+            },
+            _ => return eprintln!("{}", USAGE),
+        }
+    }
+    let weights = match weights_path.or_else(Weights::default_path) {
+        Some(path) => match Weights::load(&path) {
+            Ok(weights) => weights,
+            Err(e) => return eprintln!("Bad weights: {}", e),
+        },
+        None => Weights::default(),
+    };
+
+    // The frontend decides when to ask, so there's no pacing here beyond thinking time
+    let mut bot = Bot::new(difficulty, rand::random());
+    bot.search.weights = weights;
+    if let Some(think_time) = think_time {
+        bot.search.think_time = think_time;
+    }
+    if let Err(e) = tbp::serve(&mut bot, io::stdin().lock(), io::stdout().lock()) {
+        eprintln!("TBP error: {}", e);
+    }
+}
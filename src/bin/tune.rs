@@ -0,0 +1,136 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use tetris_zero::eval::Weights;
+use tetris_zero::tune::{Cmaes, Genetic, Objective, Optimizer, Trial};
+
+const USAGE: &str =
+    "Usage: tetris-tune [--method cmaes|genetic] [--objective survival|attack|versus]\n\
+     [--generations N] [--population N] [--games N] [--max-pieces N] [--seed S] [--threads N]\n\
+     [--start FILE] [--out FILE]\n\
+     Evolves evaluator weights from START (default OUT) and writes the best found to OUT\n\
+     (default ~/.config/tetriszero/weights.conf) after every generation";
+
+fn main() {
+    let mut method = String::from("cmaes");
+    let mut trial = Trial::default();
+    let mut generations = 50;
+    let mut population = 0;
+    let mut games: u64 = 8;
+    let mut seed: u64 = 0;
+    let mut threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut start_path = None;
+    let mut out = Weights::default_path();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let Some(value) = args.next() else {
+            return eprintln!("{}", USAGE);
+        };
+        let number = value.parse::<u64>().ok();
+        match (arg.as_str(), number) {
+            ("--method", _) if value == "cmaes" || value == "genetic" => method = value,
+            ("--objective", _) => match Objective::parse(&value) {
+                Some(objective) => trial.objective = objective,
+                None => return eprintln!("{}", USAGE),
+            },
+            ("--generations", Some(n)) => generations = n,
+            ("--population", Some(n)) => population = n as usize,
+            ("--games", Some(n)) if n > 0 => games = n,
+            ("--max-pieces", Some(n)) if n > 0 => trial.max_pieces = n as usize,
+            ("--seed", Some(n)) => seed = n,
+            ("--threads", Some(n)) if n > 0 => threads = n as usize,
+            ("--start", _) => start_path = Some(PathBuf::from(value)),
+            ("--out", _) => out = Some(PathBuf::from(value)),
+            _ => return eprintln!("{}", USAGE),
+        }
+    }
+    let Some(out) = out else {
+        return eprintln!("No config directory to write to; pass --out");
+    };
+    let start = match Weights::load(start_path.as_ref().unwrap_or(&out)) {
+        Ok(weights) => weights,
+        Err(e) => return eprintln!("Bad weights: {}", e),
+    };
+    // Versus games are against wherever tuning started
+    trial.baseline = start;
+
+    let mut optimizer: Box<dyn Optimizer> = match method.as_str() {
+        "genetic" => Box::new(Genetic::new(
+            &start.to_vec(),
+            if population == 0 { 16 } else { population },
+            seed,
+        )),
+        _ => Box::new(Cmaes::new(&start.to_vec(), population, seed)),
+    };
+
+    let mut best: Option<(f32, Weights)> = None;
+    for generation in 0..generations {
+        let candidates: Vec<Weights> = optimizer
+            .ask()
+            .iter()
+            .map(|values| Weights::from_slice(values))
+            .collect();
+        // Everyone in a generation plays the same seeds, so luck evens out between them
+        let seeds: Vec<u64> = (0..games).map(|g| seed + generation * games + g).collect();
+        let scores = evaluate(&trial, &candidates, &seeds, threads);
+        optimizer.tell(&scores);
+
+        let (i, score) = scores
+            .iter()
+            .copied()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap();
+        let mean = scores.iter().sum::<f32>() / scores.len() as f32;
+        eprintln!(
+            "[+] generation {}: best {:.3}, mean {:.3}",
+            generation + 1,
+            score,
+            mean
+        );
+        if best.as_ref().is_none_or(|(b, _)| score > *b) {
+            best = Some((score, candidates[i]));
+            if let Err(e) = save(&out, &candidates[i]) {
+                return eprintln!("Couldn't write {}: {}", out.display(), e);
+            }
+        }
+    }
+    if let Some((score, _)) = best {
+        println!("Best score {:.3} written to {}", score, out.display());
+    }
+}
+
+// Mean score of each candidate over the seeds, with every game a separate job
+fn evaluate(trial: &Trial, candidates: &[Weights], seeds: &[u64], threads: usize) -> Vec<f32> {
+    let jobs = candidates.len() * seeds.len();
+    let next = AtomicUsize::new(0);
+    let scores = Mutex::new(vec![0.0; jobs]);
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let job = next.fetch_add(1, Ordering::Relaxed);
+                if job >= jobs {
+                    break;
+                }
+                let (candidate, game) = (job / seeds.len(), job % seeds.len());
+                let score = trial.score(&candidates[candidate], seeds[game]);
+                scores.lock().unwrap()[job] = score;
+            });
+        }
+    });
+    // Summed in order so the result doesn't depend on which thread finished first
+    let scores = scores.into_inner().unwrap();
+    scores
+        .chunks(seeds.len())
+        .map(|games| games.iter().sum::<f32>() / seeds.len() as f32)
+        .collect()
+}
+
+fn save(path: &PathBuf, weights: &Weights) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let header = "# Written by tetris-tune\n";
+    fs::write(path, format!("{}{}", header, weights.encode()))
+}
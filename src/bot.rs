@@ -0,0 +1,286 @@
+use crate::beam::BeamSearch;
+use crate::board::{Board, Point};
+use crate::eval::{self, Features};
+use crate::movegen::{self, Placement};
+use crate::rules::{Chain, LockResult};
+use crate::simulation::{spawn_position, Input, PlayerState, Rules, FRAMES_PER_SECOND};
+use crate::tetromino::{Kind, Tetromino};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::time::Duration;
+
+/// What a bot knows about its own game: what a player would see on screen.
+#[derive(Debug, Clone)]
+pub struct BotState {
+    pub board: Board,
+    pub chain: Chain,
+    /// The piece in play and where it is; None once the known queue has run out.
+    pub piece: Option<(Tetromino, Point)>,
+    pub hold: Option<Kind>,
+    /// Whether the rules allow holding at all.
+    pub hold_enabled: bool,
+    /// Whether the piece in play may still be held.
+    pub can_hold: bool,
+    /// The next pieces, as far as the previews show.
+    pub queue: Vec<Kind>,
+    /// Kinds left in the 7-bag after the known queue; empty if a new bag starts there.
+    pub bag: Vec<Kind>,
+}
+
+/// One piece's worth of play: whether to hold first, where the piece goes and the
+/// inputs that put it there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub hold: bool,
+    pub placement: Placement,
+    pub inputs: Vec<Input>,
+}
+
+impl BotState {
+    pub fn from_player(state: &PlayerState, rules: &Rules) -> Self {
+        BotState {
+            board: state.board.clone(),
+            chain: state.chain,
+            piece: state
+                .alive
+                .then(|| (state.tetromino.clone(), state.position)),
+            hold: state.hold.as_ref().map(|t| t.kind),
+            hold_enabled: rules.hold,
+            can_hold: rules.hold && !state.hold_used,
+            queue: state
+                .queue
+                .preview(rules.previews)
+                .iter()
+                .map(|t| t.kind)
+                .collect(),
+            bag: state.queue.bag_after(rules.previews),
+        }
+    }
+
+    /// Every way to play the next piece, holding or not.
+    pub fn plans(&self) -> Vec<Plan> {
+        let Some((piece, position)) = &self.piece else {
+            return Vec::new();
+        };
+        let mut plans: Vec<Plan> = movegen::moves(&self.board, piece, *position)
+            .into_iter()
+            .map(|m| Plan {
+                hold: false,
+                placement: m.placement,
+                inputs: m.inputs,
+            })
+            .collect();
+        // Holding the same kind as we'd get back only wastes the hold
+        let swapped = self.hold.or(self.queue.first().copied());
+        if let Some(kind) = swapped.filter(|k| self.can_hold && *k != piece.kind) {
+            let tetromino = Tetromino::new(kind);
+            let spawn = spawn_position(&tetromino);
+            plans.extend(
+                movegen::moves(&self.board, &tetromino, spawn)
+                    .into_iter()
+                    .map(|m| {
+                        let mut inputs = vec![Input::Hold];
+                        inputs.extend(m.inputs);
+                        Plan {
+                            hold: true,
+                            placement: m.placement,
+                            inputs,
+                        }
+                    }),
+            );
+        }
+        plans
+    }
+
+    /// The state after carrying out `plan`, and what the lock scored. Garbage isn't
+    /// modelled; the next piece comes from the known queue.
+    pub fn play(&self, plan: &Plan) -> (BotState, LockResult) {
+        let mut chain = self.chain;
+        let (board, result) = eval::place(&self.board, &plan.placement, &mut chain);
+        let current = self.piece.as_ref().map(|(t, _)| t.kind);
+        let mut queue = self.queue.as_slice();
+        let mut hold = self.hold;
+        if plan.hold {
+            if hold.is_none() {
+                queue = queue.get(1..).unwrap_or_default();
+            }
+            hold = current;
+        }
+        let piece = queue.first().map(|kind| {
+            let tetromino = Tetromino::new(*kind);
+            let spawn = spawn_position(&tetromino);
+            (tetromino, spawn)
+        });
+        let next = BotState {
+            board,
+            chain,
+            piece,
+            hold,
+            hold_enabled: self.hold_enabled,
+            can_hold: self.hold_enabled,
+            queue: queue.get(1..).unwrap_or_default().to_vec(),
+            bag: self.bag.clone(),
+        };
+        (next, result)
+    }
+
+    /// Adds a piece dealt from the bag after the known queue, making it the piece in
+    /// play if there isn't one.
+    pub fn deal(&mut self, kind: Kind) {
+        if self.bag.is_empty() {
+            self.bag = Kind::ALL.to_vec();
+        }
+        self.bag.retain(|k| *k != kind);
+        if self.piece.is_none() {
+            let tetromino = Tetromino::new(kind);
+            let spawn = spawn_position(&tetromino);
+            self.piece = Some((tetromino, spawn));
+        } else {
+            self.queue.push(kind);
+        }
+    }
+
+    /// Kinds the next dealt piece could be.
+    pub fn possible_next(&self) -> &[Kind] {
+        if self.bag.is_empty() {
+            &Kind::ALL
+        } else {
+            &self.bag
+        }
+    }
+
+    /// Whether the game would be over: the stack reaches the hidden rows or the
+    /// next piece has nowhere to spawn.
+    pub fn dead(&self) -> bool {
+        self.board.lost()
+            || self
+                .piece
+                .as_ref()
+                .is_some_and(|(t, p)| self.board.collision_test(t, *p))
+    }
+}
+
+/// Anything that can decide where pieces go.
+pub trait Brain: Send {
+    /// What to do with the next piece, or None if nothing avoids topping out.
+    fn think(&mut self, state: &BotState) -> Option<Plan>;
+
+    /// Frames to wait between pieces, to keep to a pace.
+    fn frames_per_piece(&self) -> u64 {
+        0
+    }
+}
+
+/// How strong a bot plays.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Difficulty {
+    /// Pieces per second it places at, at most.
+    pub pps: f32,
+    /// Pieces it looks ahead, including the current one.
+    pub depth: usize,
+    /// Chance of settling for one of the next best placements instead of the best.
+    pub mistake_rate: f32,
+}
+
+impl Difficulty {
+    pub const EASY: Difficulty = Difficulty {
+        pps: 1.0,
+        depth: 1,
+        mistake_rate: 0.25,
+    };
+    pub const MEDIUM: Difficulty = Difficulty {
+        pps: 2.0,
+        depth: 3,
+        mistake_rate: 0.05,
+    };
+    pub const HARD: Difficulty = Difficulty {
+        pps: 3.5,
+        depth: 6,
+        mistake_rate: 0.0,
+    };
+
+    /// `easy`, `medium` or `hard`, or `<pps>:<depth>:<mistake rate>` for anything else,
+    /// with pps from 0.01 to 100 and the mistake rate from 0 to 1.
+    pub fn parse(s: &str) -> Option<Difficulty> {
+        match s {
+            "easy" => Some(Difficulty::EASY),
+            "medium" => Some(Difficulty::MEDIUM),
+            "hard" => Some(Difficulty::HARD),
+            _ => {
+                let mut parts = s.split(':');
+                let difficulty = Difficulty {
+                    // Paces far outside this overflow the time a bot gets per piece
+                    pps: parts
+                        .next()?
+                        .parse()
+                        .ok()
+                        .filter(|pps| (0.01..=100.0).contains(pps))?,
+                    depth: parts.next()?.parse().ok()?,
+                    mistake_rate: parts
+                        .next()?
+                        .parse()
+                        .ok()
+                        .filter(|rate| (0.0..=1.0).contains(rate))?,
+                };
+                parts.next().is_none().then_some(difficulty)
+            }
+        }
+    }
+}
+
+// How many of the next best placements a mistake picks from
+pub(crate) const MISTAKE_CHOICES: usize = 5;
+
+/// A bot player: a search plus the pacing and mistakes of its difficulty.
+#[derive(Debug, Clone)]
+pub struct Bot {
+    pub difficulty: Difficulty,
+    pub search: BeamSearch,
+    rng: Pcg32,
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty, seed: u64) -> Self {
+        // Thinking shouldn't eat more than most of the time it has per piece
+        let per_piece = Duration::from_secs_f32(0.8 / difficulty.pps);
+        Bot {
+            difficulty,
+            search: BeamSearch {
+                depth: difficulty.depth.max(1),
+                think_time: per_piece.min(Duration::from_millis(200)),
+                ..BeamSearch::default()
+            },
+            rng: Pcg32::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Brain for Bot {
+    fn frames_per_piece(&self) -> u64 {
+        (FRAMES_PER_SECOND as f32 / self.difficulty.pps).ceil() as u64
+    }
+
+    fn think(&mut self, state: &BotState) -> Option<Plan> {
+        if self.rng.gen::<f32>() >= self.difficulty.mistake_rate {
+            return self.search.search(state);
+        }
+        // A mistake: something that looks decent at a glance, not necessarily the best
+        let weights = &self.search.weights;
+        let mut scored: Vec<(f32, Plan)> = state
+            .plans()
+            .into_iter()
+            .filter_map(|plan| {
+                let (next, result) = state.play(&plan);
+                let score = weights.reward(&result) + weights.board(&Features::of(&next.board));
+                (!next.dead()).then_some((score, plan))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(MISTAKE_CHOICES);
+        if scored.is_empty() {
+            return None;
+        }
+        let i = self.rng.gen_range(0..scored.len());
+        Some(scored.swap_remove(i).1)
+    }
+}
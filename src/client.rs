@@ -0,0 +1,449 @@
+use crate::board::{Board, Point};
+use crate::config::Bindings;
+use crate::config::{Action, ClientConfig};
+use crate::handling::Autoshift;
+use crate::mode::{PersonalBests, Summary};
+use crate::protocol::{ClientMessage, Request, ServerMessage};
+use crate::render;
+use crate::save;
+use crate::session::{self, Session};
+use crate::simulation::{Input, FRAMES_PER_SECOND};
+use crate::tetromino::{Direction, Kind, Tetromino};
+use crossterm::{
+    cursor,
+    event::{
+        self, KeyCode, KeyEvent, KeyEventKind, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, BufRead, BufReader, Write},
+    net::TcpStream,
+    path::Path,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+    time::{Duration, Instant},
+};
+
+// Terminals without key-up events repeat held keys instead. The first repeat can't come
+// sooner than this after the press...
+const FIRST_REPEAT_MIN: Duration = Duration::from_millis(200);
+// ...a key that hasn't repeated by this long after the press was a tap...
+const FIRST_REPEAT_MAX: Duration = Duration::from_millis(600);
+// ...and a repeating key that stops repeating for this long has been let go.
+const REPEAT_GAP: Duration = Duration::from_millis(120);
+
+/// What the client knows about one player, rebuilt from server messages.
+#[derive(Debug, Default, Clone)]
+pub struct PlayerView {
+    pub name: String,
+    pub board: Board,
+    pub piece: Option<(Tetromino, Point)>,
+    pub queue: Vec<Kind>,
+    pub hold: Option<Kind>,
+    pub garbage: usize,
+    pub target: Option<usize>,
+    pub gravity: u32,
+    pub topped_out: bool,
+    /// Timer and stats, in solo modes.
+    pub run: Option<Summary>,
+}
+
+impl PlayerView {
+    /// Where the current piece would land if hard dropped.
+    pub fn ghost(&self) -> Option<Point> {
+        let (tetromino, position) = self.piece.as_ref()?;
+        let mut ghost = *position;
+        while !self.board.collision_test(
+            tetromino,
+            Point {
+                x: ghost.x,
+                y: ghost.y + 1,
+            },
+        ) {
+            ghost.y += 1;
+        }
+        Some(ghost)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct View {
+    /// The player this client controls, once the server has said so.
+    pub id: Option<usize>,
+    pub players: BTreeMap<usize, PlayerView>,
+    pub status: String,
+    /// Our records, shown next to solo runs.
+    pub bests: PersonalBests,
+}
+
+impl View {
+    pub fn apply(&mut self, msg: ServerMessage) {
+        match msg {
+            ServerMessage::Welcome { id } => self.id = Some(id),
+            ServerMessage::Player { id, name } => self.players.entry(id).or_default().name = name,
+            ServerMessage::Board { id, board } => {
+                self.players.entry(id).or_default().board = *board
+            }
+            ServerMessage::Piece {
+                id,
+                kind,
+                rotation,
+                position,
+            } => {
+                let mut tetromino = Tetromino::new(kind);
+                for _ in 0..rotation {
+                    tetromino.rotate(Direction::CW);
+                }
+                self.players.entry(id).or_default().piece = Some((tetromino, position));
+            }
+            ServerMessage::Queue { id, kinds } => self.players.entry(id).or_default().queue = kinds,
+            ServerMessage::Hold { id, kind } => self.players.entry(id).or_default().hold = kind,
+            ServerMessage::Garbage { id, lines } => {
+                self.players.entry(id).or_default().garbage = lines
+            }
+            ServerMessage::Target { id, target } => {
+                self.players.entry(id).or_default().target = target
+            }
+            ServerMessage::Gravity { id, frames } => {
+                self.players.entry(id).or_default().gravity = frames
+            }
+            ServerMessage::ToppedOut { id } => {
+                let player = self.players.entry(id).or_default();
+                player.topped_out = true;
+                player.piece = None;
+            }
+            ServerMessage::Run { id, summary } => {
+                self.players.entry(id).or_default().run = Some(summary)
+            }
+            ServerMessage::Result { id, summary } => {
+                self.players.entry(id).or_default().run = Some(summary);
+                if Some(id) == self.id {
+                    self.status = summary.describe();
+                }
+            }
+            // Only the pieces that could have taken fewer keys are worth a mention
+            ServerMessage::Finesse { id, finesse } => {
+                if Some(id) == self.id && finesse.faults() > 0 {
+                    self.status = format!("Finesse: {}", finesse.describe());
+                }
+            }
+            ServerMessage::Opener { id, name } => {
+                let player = self.players.entry(id).or_default();
+                self.status = format!("{} built {}", player.name, name);
+            }
+            ServerMessage::Account { name, .. } => self.status = format!("Signed in as {}", name),
+            ServerMessage::Match { name, entry } => {
+                self.status = format!("{}: {}", name, entry.describe())
+            }
+            ServerMessage::Best { summary, .. } => {
+                self.status = format!("Best {}", summary.describe())
+            }
+            ServerMessage::Stats { name, stats } => {
+                self.status = format!("{}: {}", name, stats.describe())
+            }
+            // Only asked for by `snapshot`, never while playing
+            ServerMessage::Snapshot(_) => {}
+            ServerMessage::Text(text) => self.status = text,
+        }
+    }
+}
+
+// Raw mode and the alternate screen for as long as this is alive. Where the terminal
+// supports it, key-up events are turned on too.
+struct RawTerminal {
+    key_releases: bool,
+}
+impl RawTerminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, cursor::Hide)?;
+        let key_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if key_releases {
+            execute!(
+                io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+        }
+        Ok(RawTerminal { key_releases })
+    }
+}
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        if self.key_releases {
+            let _ = execute!(io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// A key that is down, as far as we can tell
+struct HeldKey {
+    input: Input,
+    pressed: Instant,
+    last_seen: Instant,
+    repeating: bool,
+}
+
+// Tracks which keys are down and feeds presses and releases to the autoshift
+struct Keys {
+    bindings: Bindings,
+    key_releases: bool,
+    held: HashMap<KeyCode, HeldKey>,
+    autoshift: Autoshift,
+}
+
+impl Keys {
+    fn is_quit(&self, key: &KeyEvent) -> bool {
+        key.kind != KeyEventKind::Release && self.bindings.action(key) == Some(Action::Quit)
+    }
+
+    fn key_event(&mut self, key: KeyEvent, now: Instant) -> Vec<ClientMessage> {
+        if self.key_releases && key.kind == KeyEventKind::Release {
+            // Modifiers may have changed since the press, so go by what the key did then
+            return match self.held.remove(&key.code) {
+                Some(held) => self.autoshift.release(held.input),
+                None => Vec::new(),
+            };
+        }
+        let Some(input) = self.bindings.action(&key).and_then(Action::input) else {
+            return Vec::new();
+        };
+        if self.key_releases {
+            return match key.kind {
+                KeyEventKind::Press => {
+                    self.held.insert(
+                        key.code,
+                        HeldKey {
+                            input,
+                            pressed: now,
+                            last_seen: now,
+                            repeating: true,
+                        },
+                    );
+                    self.autoshift.press(input, true)
+                }
+                KeyEventKind::Release | KeyEventKind::Repeat => Vec::new(),
+            };
+        }
+
+        // Without key-up events, a press soon after the last one is the terminal repeating
+        let mut messages = Vec::new();
+        if let Some(held) = self.held.get_mut(&key.code) {
+            if held.repeating || now.duration_since(held.pressed) >= FIRST_REPEAT_MIN {
+                held.repeating = true;
+                held.last_seen = now;
+                self.autoshift.confirm(input);
+                return messages;
+            }
+            // Tapped again quickly: let go of the first press
+            messages.extend(self.autoshift.release(input));
+        }
+        self.held.insert(
+            key.code,
+            HeldKey {
+                input,
+                pressed: now,
+                last_seen: now,
+                repeating: false,
+            },
+        );
+        messages.extend(self.autoshift.press(input, false));
+        messages
+    }
+
+    // Releases keys the terminal has stopped repeating
+    fn expire(&mut self, now: Instant) -> Vec<ClientMessage> {
+        if self.key_releases {
+            return Vec::new();
+        }
+        let expired: Vec<KeyCode> = self
+            .held
+            .iter()
+            .filter(|(_, held)| {
+                if held.repeating {
+                    now.duration_since(held.last_seen) > REPEAT_GAP
+                } else {
+                    now.duration_since(held.pressed) > FIRST_REPEAT_MAX
+                }
+            })
+            .map(|(code, _)| *code)
+            .collect();
+        let mut messages = Vec::new();
+        for code in expired {
+            if let Some(held) = self.held.remove(&code) {
+                messages.extend(self.autoshift.release(held.input));
+            }
+        }
+        messages
+    }
+}
+
+fn send(writer: &mut impl Write, messages: Vec<ClientMessage>) -> io::Result<()> {
+    for msg in messages {
+        writeln!(writer, "{}", msg.encode())?;
+    }
+    Ok(())
+}
+
+/// Connects to a server, joins with `join` (a name, or a `Login` or `Register` request)
+/// and plays until the quit key or the server hangs up.
+pub fn run(addr: &str, join: &str, config: ClientConfig) -> io::Result<()> {
+    let stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+    let mut writer = stream.try_clone()?;
+    writeln!(writer, "{}", join)?;
+
+    // Server messages are read on their own thread so input never waits on the network
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            if tx.send(ServerMessage::decode(line.trim_end())).is_err() {
+                break;
+            }
+        }
+    });
+    play(config, rx, |messages| send(&mut writer, messages))
+}
+
+/// Asks the server at `addr` for a snapshot of its match, without joining it.
+pub fn snapshot(addr: &str) -> io::Result<Session> {
+    let stream = TcpStream::connect(addr)?;
+    let mut writer = stream.try_clone()?;
+    writeln!(writer, "{}", Request::Snapshot.encode())?;
+    for line in BufReader::new(stream).lines() {
+        if let ServerMessage::Snapshot(text) = ServerMessage::decode(line?.trim_end()) {
+            return save::decode(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the server hung up without a snapshot",
+    ))
+}
+
+/// Plays a solo session against the engine in this process, no server needed. If
+/// it's left unfinished, it's saved to `save` to be picked up again.
+pub fn run_local(
+    session: Session,
+    name: &str,
+    config: ClientConfig,
+    save: Option<&Path>,
+) -> io::Result<()> {
+    let (input_tx, rx, handle) = session::spawn_local(session, name);
+    play(config, rx, |messages| {
+        for msg in messages {
+            // The game only stops listening once we quit
+            let _ = input_tx.send(msg);
+        }
+        Ok(())
+    })?;
+    drop(input_tx);
+    let Ok(session) = handle.join() else {
+        return Err(io::Error::other("the local game crashed"));
+    };
+    match save {
+        Some(path) if !session.is_over() => save::save(path, &session),
+        _ => Ok(()),
+    }
+}
+
+fn play(
+    config: ClientConfig,
+    rx: Receiver<ServerMessage>,
+    mut send: impl FnMut(Vec<ClientMessage>) -> io::Result<()>,
+) -> io::Result<()> {
+    let ClientConfig { bindings, handling } = config;
+    send(vec![ClientMessage::Handling(handling)])?;
+    let bests_path = PersonalBests::default_path();
+    let bests = match &bests_path {
+        Some(path) => PersonalBests::load(path)?,
+        None => PersonalBests::default(),
+    };
+
+    let terminal = RawTerminal::enter()?;
+    let mut keys = Keys {
+        bindings,
+        key_releases: terminal.key_releases,
+        held: HashMap::new(),
+        autoshift: Autoshift::new(handling),
+    };
+    let mut stdout = io::stdout();
+    let mut view = View {
+        bests,
+        ..View::default()
+    };
+    let mut dirty = true;
+    // Held keys repeat on our own 60 Hz clock, the same rate the server steps at
+    let frame_time = Duration::from_secs(1) / FRAMES_PER_SECOND;
+    let mut next_frame = Instant::now() + frame_time;
+    'play: loop {
+        let timeout = next_frame.saturating_duration_since(Instant::now());
+        if event::poll(timeout)? {
+            match event::read()? {
+                event::Event::Key(key) => {
+                    if keys.is_quit(&key) {
+                        break 'play;
+                    }
+                    let messages = keys.key_event(key, Instant::now());
+                    send(messages)?;
+                }
+                event::Event::Resize(_, _) => dirty = true,
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        while now >= next_frame {
+            next_frame += frame_time;
+            let gravity = view
+                .id
+                .and_then(|id| view.players.get(&id))
+                .map_or(0, |p| p.gravity);
+            let mut messages = keys.expire(now);
+            messages.extend(keys.autoshift.tick(gravity));
+            send(messages)?;
+        }
+
+        loop {
+            match rx.try_recv() {
+                Ok(msg) => {
+                    let result = match &msg {
+                        ServerMessage::Result { id, summary } if Some(*id) == view.id => {
+                            Some(*summary)
+                        }
+                        _ => None,
+                    };
+                    view.apply(msg);
+                    if let Some(summary) = result {
+                        if view.bests.submit(&summary) {
+                            view.status.push_str(" (new personal best!)");
+                            if let Some(path) = &bests_path {
+                                view.bests.save(path)?;
+                            }
+                        }
+                    }
+                    dirty = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    view.status = String::from("Disconnected from server (quit key to leave)");
+                    render::draw(&mut stdout, &view)?;
+                    while !matches!(event::read()?, event::Event::Key(key) if keys.is_quit(&key)) {}
+                    break 'play;
+                }
+            }
+        }
+
+        if dirty {
+            render::draw(&mut stdout, &view)?;
+            dirty = false;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,361 @@
+use crate::dirs;
+use crate::handling::Handling;
+use crate::simulation::Input;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// The client config is a flat list of `name = value` lines:
+//
+//     # Keys are names (Left, Space, F1, ...) or single characters, optionally with
+//     # ctrl+ or alt+ in front. An action can have several keys.
+//     left = Left, j
+//     rotate_cw = Up, x
+//     forfeit = ctrl+f
+//     das = 8
+//
+// Actions that aren't listed keep their default keys, bar any the file gives to
+// another action. A key listed for two actions is an error.
+
+/// Something a key can be bound to.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Action {
+    Left,
+    Right,
+    SoftDrop,
+    HardDrop,
+    RotateCW,
+    RotateCCW,
+    Rotate180,
+    Hold,
+    Forfeit,
+    Target,
+    Quit,
+}
+
+impl Action {
+    pub const ALL: [Action; 11] = [
+        Action::Left,
+        Action::Right,
+        Action::SoftDrop,
+        Action::HardDrop,
+        Action::RotateCW,
+        Action::RotateCCW,
+        Action::Rotate180,
+        Action::Hold,
+        Action::Forfeit,
+        Action::Target,
+        Action::Quit,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::SoftDrop => "soft_drop",
+            Action::HardDrop => "hard_drop",
+            Action::RotateCW => "rotate_cw",
+            Action::RotateCCW => "rotate_ccw",
+            Action::Rotate180 => "rotate_180",
+            Action::Hold => "hold",
+            Action::Forfeit => "forfeit",
+            Action::Target => "target",
+            Action::Quit => "quit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|a| a.name() == name)
+    }
+
+    /// The input sent to the server, if any. Quitting only concerns the client.
+    pub fn input(self) -> Option<Input> {
+        match self {
+            Action::Left => Some(Input::Left),
+            Action::Right => Some(Input::Right),
+            Action::SoftDrop => Some(Input::SoftDrop),
+            Action::HardDrop => Some(Input::HardDrop),
+            Action::RotateCW => Some(Input::RotateCW),
+            Action::RotateCCW => Some(Input::RotateCCW),
+            Action::Rotate180 => Some(Input::Rotate180),
+            Action::Hold => Some(Input::Hold),
+            Action::Forfeit => Some(Input::Forfeit),
+            Action::Target => Some(Input::NextTarget),
+            Action::Quit => None,
+        }
+    }
+
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Left => &["Left", "a"],
+            Action::Right => &["Right", "d"],
+            Action::SoftDrop => &["Down", "s"],
+            Action::HardDrop => &["Space"],
+            Action::RotateCW => &["Up", "x", "e", "w"],
+            Action::RotateCCW => &["z", "q"],
+            Action::Rotate180 => &["r"],
+            Action::Hold => &["c"],
+            Action::Forfeit => &["ctrl+f"],
+            Action::Target => &["t", "Tab"],
+            Action::Quit => &["Esc", "ctrl+c"],
+        }
+    }
+}
+
+/// A key together with the modifiers that have to be down with it.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct Binding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl Binding {
+    // Shift is left out: it's already part of the character for letters and symbols
+    fn from_event(key: &KeyEvent) -> Binding {
+        let code = match key.code {
+            KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+            code => code,
+        };
+        Binding {
+            code,
+            modifiers: key.modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT),
+        }
+    }
+
+    /// Parses `ctrl+f`, `Space`, `F5`, `x`, ...; names are case-insensitive.
+    pub fn parse(s: &str) -> Option<Binding> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s.trim();
+        loop {
+            let lower = rest.to_ascii_lowercase();
+            if lower.starts_with("ctrl+") {
+                modifiers |= KeyModifiers::CONTROL;
+                rest = &rest[5..];
+            } else if lower.starts_with("alt+") {
+                modifiers |= KeyModifiers::ALT;
+                rest = &rest[4..];
+            } else {
+                break;
+            }
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c.to_ascii_lowercase()),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "space" => KeyCode::Char(' '),
+                "enter" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backspace" => KeyCode::Backspace,
+                // Commas separate keys in the config file
+                "comma" => KeyCode::Char(','),
+                name => {
+                    let n: u8 = name.strip_prefix('f')?.parse().ok()?;
+                    if !(1..=12).contains(&n) {
+                        return None;
+                    }
+                    KeyCode::F(n)
+                }
+            },
+        };
+        Some(Binding { code, modifiers })
+    }
+}
+
+/// Which action each key does. One action can have any number of keys.
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    keys: HashMap<Binding, Action>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let mut bindings = Bindings {
+            keys: HashMap::new(),
+        };
+        for action in Action::ALL {
+            for key in action.default_keys() {
+                bindings.bind(Binding::parse(key).unwrap(), action);
+            }
+        }
+        bindings
+    }
+}
+
+impl Bindings {
+    pub fn bind(&mut self, binding: Binding, action: Action) {
+        self.keys.insert(binding, action);
+    }
+
+    /// Removes every key bound to `action`.
+    pub fn clear(&mut self, action: Action) {
+        self.keys.retain(|_, a| *a != action);
+    }
+
+    pub fn action(&self, key: &KeyEvent) -> Option<Action> {
+        self.keys.get(&Binding::from_event(key)).copied()
+    }
+}
+
+/// Everything the client reads from its config file.
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    pub bindings: Bindings,
+    pub handling: Handling,
+}
+
+impl ClientConfig {
+    /// `$XDG_CONFIG_HOME/tetriszero/client.conf`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("client.conf"))
+    }
+
+    /// Reads the config at `path`. A missing file just means the defaults.
+    pub fn load(path: &Path) -> io::Result<ClientConfig> {
+        match fs::read_to_string(path) {
+            Ok(text) => ClientConfig::parse(&text).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ClientConfig::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<ClientConfig, String> {
+        let mut config = ClientConfig::default();
+        // Keys the file itself binds, which can only do one thing
+        let mut listed: HashMap<Binding, Action> = HashMap::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", n + 1, msg);
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(err(format!("expected `name = value`, got `{}`", line)));
+            };
+            let (name, value) = (name.trim(), value.trim());
+            let field = match name {
+                "das" => Some(&mut config.handling.das),
+                "arr" => Some(&mut config.handling.arr),
+                "sdf" => Some(&mut config.handling.sdf),
+                "dcd" => Some(&mut config.handling.dcd),
+                _ => None,
+            };
+            if let Some(field) = field {
+                *field = value
+                    .parse()
+                    .map_err(|_| err(format!("`{}` is not a number", value)))?;
+                continue;
+            }
+            let action =
+                Action::from_name(name).ok_or_else(|| err(format!("unknown action `{}`", name)))?;
+            // Listing an action replaces its default keys, and taking a key from another
+            // action's defaults is fine
+            config.bindings.clear(action);
+            listed.retain(|_, a| *a != action);
+            for key in value.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                let binding =
+                    Binding::parse(key).ok_or_else(|| err(format!("unknown key `{}`", key)))?;
+                if let Some(other) = listed.insert(binding, action).filter(|a| *a != action) {
+                    return Err(err(format!(
+                        "`{}` is already bound to {}",
+                        key,
+                        other.name()
+                    )));
+                }
+                config.bindings.bind(binding, action);
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(config: &ClientConfig, key: &str) -> Option<Action> {
+        let binding = Binding::parse(key).unwrap();
+        let event = KeyEvent::new(binding.code, binding.modifiers);
+        config.bindings.action(&event)
+    }
+
+    #[test]
+    fn defaults_without_a_config() {
+        let config = ClientConfig::parse("").unwrap();
+        assert_eq!(config.handling, Handling::default());
+        for action in Action::ALL {
+            for key in action.default_keys() {
+                assert_eq!(self::action(&config, key), Some(action), "{}", key);
+            }
+        }
+        assert_eq!(action(&config, "ctrl+f"), Some(Action::Forfeit));
+        assert_eq!(action(&config, "f"), None);
+        // Shifted letters are the same key
+        let shifted = KeyEvent::new(KeyCode::Char('X'), KeyModifiers::SHIFT);
+        assert_eq!(config.bindings.action(&shifted), Some(Action::RotateCW));
+    }
+
+    #[test]
+    fn listed_actions_replace_their_keys() {
+        let config = ClientConfig::parse(
+            "# vim keys
+             left = h, ctrl+Left
+             right = l   # and nothing else
+             rotate_180 = x, comma
+             das = 7
+             arr = 0",
+        )
+        .unwrap();
+        assert_eq!(action(&config, "h"), Some(Action::Left));
+        assert_eq!(action(&config, "ctrl+left"), Some(Action::Left));
+        assert_eq!(action(&config, "Left"), None);
+        assert_eq!(action(&config, "a"), None);
+        assert_eq!(action(&config, "l"), Some(Action::Right));
+        assert_eq!(action(&config, "Right"), None);
+        // Taken from rotate_cw, which keeps its other keys
+        assert_eq!(action(&config, "x"), Some(Action::Rotate180));
+        assert_eq!(action(&config, "Up"), Some(Action::RotateCW));
+        assert_eq!(action(&config, "comma"), Some(Action::Rotate180));
+        assert_eq!(action(&config, "Space"), Some(Action::HardDrop));
+        assert_eq!(config.handling.das, 7);
+        assert_eq!(config.handling.arr, 0);
+        assert_eq!(config.handling.sdf, Handling::default().sdf);
+    }
+
+    #[test]
+    fn bad_lines_are_refused() {
+        for (text, error) in [
+            ("left = Left\nleft Right", "line 2: expected `name = value`"),
+            ("jump = Space", "line 1: unknown action `jump`"),
+            ("left = Lft", "line 1: unknown key `Lft`"),
+            ("left = F13", "line 1: unknown key `F13`"),
+            ("left = super+a", "line 1: unknown key `super+a`"),
+            ("das = fast", "line 1: `fast` is not a number"),
+        ] {
+            let message = ClientConfig::parse(text).unwrap_err();
+            assert!(message.starts_with(error), "{}: {}", text, message);
+        }
+    }
+
+    #[test]
+    fn keys_do_one_thing() {
+        let message = ClientConfig::parse("hold = c\nquit = Esc, C").unwrap_err();
+        assert_eq!(message, "line 2: `C` is already bound to hold");
+        // Listing the same key twice for one action, or an action again, is harmless
+        let config = ClientConfig::parse("hold = c, c\nhold = v\nquit = c").unwrap();
+        assert_eq!(action(&config, "c"), Some(Action::Quit));
+        assert_eq!(action(&config, "v"), Some(Action::Hold));
+    }
+}
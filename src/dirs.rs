@@ -0,0 +1,23 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+// Where our files go, following the XDG base directory spec: settings and records under
+// the config directory, and what a server accumulates under the data directory.
+
+/// `$XDG_CONFIG_HOME/tetriszero`, falling back to `~/.config`.
+pub fn config_dir() -> Option<PathBuf> {
+    dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `$XDG_DATA_HOME/tetriszero`, falling back to `~/.local/share`.
+pub fn data_dir() -> Option<PathBuf> {
+    dir("XDG_DATA_HOME", ".local/share")
+}
+
+// Our directory under `var`, or under `fallback` in the home directory if it's unset
+fn dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    let base = env::var_os(var)
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(fallback)))?;
+    Some(base.join("tetriszero"))
+}
@@ -0,0 +1,550 @@
+use crate::board::{Point, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::bot::{BotState, Plan};
+use crate::rules::LockResult;
+use crate::simulation::{Event, Input, PlayerState, Rules, Simulation};
+use crate::tetromino::{Kind, Tetromino};
+
+// The agent is the only player in its simulation
+const PLAYER: usize = 1;
+
+/// What the number passed to `Env::step` means.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ActionSpace {
+    /// One frame per step, pressing the input at that index of `INPUTS`.
+    Inputs,
+    /// One piece per step, played the way the plan at that index of
+    /// `Env::placements` says.
+    Placements,
+}
+
+/// Everything a step can press in the input action space, starting with nothing at all.
+pub const INPUTS: [Option<Input>; 12] = [
+    None,
+    Some(Input::Left),
+    Some(Input::Right),
+    Some(Input::DasLeft),
+    Some(Input::DasRight),
+    Some(Input::SoftDrop),
+    Some(Input::SonicDrop),
+    Some(Input::HardDrop),
+    Some(Input::RotateCW),
+    Some(Input::RotateCCW),
+    Some(Input::Rotate180),
+    Some(Input::Hold),
+];
+
+/// The most previews an encoding can have: as many as the queue always shows.
+pub const MAX_PREVIEWS: usize = 7;
+
+/// The longest garbage meter an encoding can have, a board's height.
+pub const MAX_GARBAGE_METER: usize = BOARD_HEIGHT;
+
+/// Which parts of the game go into an observation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Encoding {
+    /// Planes for the falling piece and its ghost after the plane of filled cells.
+    pub piece_planes: bool,
+    /// How many previews get a one-hot, up to `MAX_PREVIEWS`. Previews the rules don't
+    /// show stay zero.
+    pub previews: usize,
+    /// Length of the garbage meter, up to `MAX_GARBAGE_METER`; cell i is 1 when more
+    /// than i lines are pending.
+    pub garbage_meter: usize,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding {
+            piece_planes: true,
+            previews: 5,
+            garbage_meter: 20,
+        }
+    }
+}
+
+impl Encoding {
+    pub fn planes(&self) -> usize {
+        if self.piece_planes {
+            3
+        } else {
+            1
+        }
+    }
+
+    pub fn board_len(&self) -> usize {
+        self.planes() * BOARD_HEIGHT * BOARD_WIDTH
+    }
+
+    /// Current piece, hold and previews as one-hots, whether hold is available, the
+    /// garbage meter, then combo and back-to-back.
+    pub fn features_len(&self) -> usize {
+        7 * (2 + self.previews) + 1 + self.garbage_meter + 2
+    }
+}
+
+/// What the agent sees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Observation {
+    /// Bitplanes, each a row-major grid of the whole board with the top row first.
+    pub board: Vec<f32>,
+    pub features: Vec<f32>,
+}
+
+impl Observation {
+    /// The board followed by the features.
+    pub fn flat(&self) -> Vec<f32> {
+        let mut flat = self.board.clone();
+        flat.extend_from_slice(&self.features);
+        flat
+    }
+}
+
+/// How much each thing that happens in a step is worth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reward {
+    pub attack: f32,
+    pub lines: f32,
+    /// For every piece locked, to reward staying alive.
+    pub piece: f32,
+    pub death: f32,
+}
+
+impl Default for Reward {
+    fn default() -> Self {
+        Reward {
+            attack: 1.0,
+            lines: 0.0,
+            piece: 0.0,
+            death: -1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvConfig {
+    pub rules: Rules,
+    pub action_space: ActionSpace,
+    pub encoding: Encoding,
+    pub reward: Reward,
+    /// Episodes are cut off after this many pieces.
+    pub max_pieces: Option<usize>,
+    /// Queue `garbage_lines` of garbage every this many pieces, so the meter means
+    /// something without an opponent.
+    pub garbage_every: Option<usize>,
+    pub garbage_lines: usize,
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        EnvConfig {
+            rules: Rules::default(),
+            action_space: ActionSpace::Placements,
+            encoding: Encoding::default(),
+            reward: Reward::default(),
+            max_pieces: Some(1000),
+            garbage_every: None,
+            garbage_lines: 1,
+        }
+    }
+}
+
+/// Extra detail about a step that isn't part of the observation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Info {
+    pub frame: u64,
+    pub pieces: usize,
+    pub lines: usize,
+    pub attack: usize,
+    /// The lock this step, if a piece locked.
+    pub locked: Option<LockResult>,
+    /// The episode ended by hitting `max_pieces` rather than topping out.
+    pub truncated: bool,
+}
+
+/// A single-player game stepped by an agent, gym style.
+#[derive(Debug, Clone)]
+pub struct Env {
+    pub config: EnvConfig,
+    simulation: Simulation,
+    placements: Vec<Plan>,
+    done: bool,
+}
+
+impl Env {
+    pub fn new(config: EnvConfig) -> Self {
+        let mut env = Env {
+            simulation: Simulation::new(0, config.rules.clone()),
+            config,
+            placements: Vec::new(),
+            done: false,
+        };
+        env.reset(0);
+        env
+    }
+
+    /// Starts a new episode. The same seed deals the same pieces and garbage.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.simulation = Simulation::new(seed, self.config.rules.clone());
+        self.simulation.add_player(PLAYER);
+        self.done = false;
+        self.refresh_placements();
+        self.observe()
+    }
+
+    /// Plays one action. Out of range actions press nothing in the input space, and
+    /// hard drop in the placement space. Once done, steps do nothing until a reset.
+    pub fn step(&mut self, action: usize) -> (Observation, f32, bool, Info) {
+        if self.done {
+            return (self.observe(), 0.0, true, self.info(None, false));
+        }
+        let inputs: Vec<Input> = match self.config.action_space {
+            ActionSpace::Inputs => INPUTS.get(action).copied().flatten().into_iter().collect(),
+            ActionSpace::Placements => self
+                .placements
+                .get(action)
+                .map_or(vec![Input::HardDrop], |plan| plan.inputs.clone()),
+        };
+        let inputs: Vec<(usize, Input)> = inputs.into_iter().map(|i| (PLAYER, i)).collect();
+
+        let reward = &self.config.reward;
+        let mut total = 0.0;
+        let mut locked = None;
+        for event in self.simulation.step(&inputs) {
+            match event {
+                Event::PieceLocked { result, .. } => {
+                    total += reward.attack * result.attack as f32
+                        + reward.lines * result.lines as f32
+                        + reward.piece;
+                    locked = Some(result);
+                }
+                Event::ToppedOut { .. } => total += reward.death,
+                _ => {}
+            }
+        }
+        let player = self.player();
+        let pieces = player.pieces;
+        let alive = player.alive;
+        if locked.is_some() {
+            let every = self.config.garbage_every.filter(|n| *n > 0);
+            if every.is_some_and(|every| pieces.is_multiple_of(every)) {
+                self.simulation
+                    .queue_garbage(PLAYER, self.config.garbage_lines);
+            }
+            self.refresh_placements();
+        }
+        let truncated = alive && self.config.max_pieces.is_some_and(|max| pieces >= max);
+        self.done = !alive || truncated;
+        (
+            self.observe(),
+            total,
+            self.done,
+            self.info(locked, truncated),
+        )
+    }
+
+    /// The plans the placement action space picks from, for the piece in play.
+    pub fn placements(&self) -> &[Plan] {
+        &self.placements
+    }
+
+    /// How many actions there are to choose from right now.
+    pub fn action_count(&self) -> usize {
+        match self.config.action_space {
+            ActionSpace::Inputs => INPUTS.len(),
+            ActionSpace::Placements => self.placements.len(),
+        }
+    }
+
+    pub fn simulation(&self) -> &Simulation {
+        &self.simulation
+    }
+
+    pub fn observe(&self) -> Observation {
+        encode(self.player(), &self.config.rules, &self.config.encoding)
+    }
+
+    fn player(&self) -> &PlayerState {
+        self.simulation.player(PLAYER).unwrap()
+    }
+
+    fn refresh_placements(&mut self) {
+        self.placements = match self.config.action_space {
+            ActionSpace::Placements => {
+                BotState::from_player(self.player(), &self.config.rules).plans()
+            }
+            ActionSpace::Inputs => Vec::new(),
+        };
+    }
+
+    fn info(&self, locked: Option<LockResult>, truncated: bool) -> Info {
+        let player = self.player();
+        Info {
+            frame: self.simulation.frame(),
+            pieces: player.pieces,
+            lines: player.lines,
+            attack: player.attack,
+            locked,
+            truncated,
+        }
+    }
+}
+
+/// An observation of `state` under `rules`.
+pub fn encode(state: &PlayerState, rules: &Rules, encoding: &Encoding) -> Observation {
+    let view = BotState::from_player(state, rules);
+    encode_bot(&view, state.pending_lines(), encoding)
+}
+
+/// An observation of a bot's view of its game, which doesn't know about garbage, so
+/// `pending` lines are passed in.
+pub fn encode_bot(state: &BotState, pending: usize, encoding: &Encoding) -> Observation {
+    let cells = BOARD_HEIGHT * BOARD_WIDTH;
+    let mut board = vec![0.0; encoding.board_len()];
+    for y in 0..BOARD_HEIGHT {
+        for x in 0..BOARD_WIDTH {
+            if state.board.cell(x, y).is_some() {
+                board[y * BOARD_WIDTH + x] = 1.0;
+            }
+        }
+    }
+    if let Some((tetromino, position)) = state.piece.as_ref().filter(|_| encoding.piece_planes) {
+        let mut ghost = *position;
+        while !state.board.collision_test(
+            tetromino,
+            Point {
+                x: ghost.x,
+                y: ghost.y + 1,
+            },
+        ) {
+            ghost.y += 1;
+        }
+        for (plane, position) in [(1, *position), (2, ghost)] {
+            for p in piece_cells(tetromino, position) {
+                if (0..BOARD_WIDTH as i32).contains(&p.x) && (0..BOARD_HEIGHT as i32).contains(&p.y)
+                {
+                    board[plane * cells + p.y as usize * BOARD_WIDTH + p.x as usize] = 1.0;
+                }
+            }
+        }
+    }
+
+    let mut features = vec![0.0; encoding.features_len()];
+    let mut one_hot = |slot: usize, kind: Kind| features[slot * 7 + kind as usize] = 1.0;
+    if let Some((tetromino, _)) = &state.piece {
+        one_hot(0, tetromino.kind);
+    }
+    if let Some(held) = state.hold {
+        one_hot(1, held);
+    }
+    for (i, kind) in state.queue.iter().take(encoding.previews).enumerate() {
+        one_hot(2 + i, *kind);
+    }
+    let mut i = 7 * (2 + encoding.previews);
+    features[i] = state.can_hold as u8 as f32;
+    i += 1;
+    for cell in 0..encoding.garbage_meter {
+        features[i + cell] = (pending > cell) as u8 as f32;
+    }
+    i += encoding.garbage_meter;
+    features[i] = state.chain.combo as f32 / 10.0;
+    features[i + 1] = state.chain.back_to_back as u8 as f32;
+    Observation { board, features }
+}
+
+fn piece_cells(tetromino: &Tetromino, position: Point) -> Vec<Point> {
+    let mut cells = Vec::with_capacity(4);
+    tetromino.each_point(&mut |row, col| {
+        cells.push(Point {
+            x: position.x + col,
+            y: position.y + row,
+        })
+    });
+    cells
+}
+
+/// Several environments stepped together, spread over threads. An environment that
+/// finishes is reset straight away with the next seed in line, so the observation
+/// returned alongside `done` is the first of its new episode.
+#[derive(Debug, Clone)]
+pub struct VecEnv {
+    pub envs: Vec<Env>,
+    pub threads: usize,
+    next_seed: u64,
+}
+
+impl VecEnv {
+    pub fn new(count: usize, config: EnvConfig) -> Self {
+        VecEnv {
+            envs: (0..count).map(|_| Env::new(config.clone())).collect(),
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            next_seed: 0,
+        }
+    }
+
+    /// Resets environment i with `seed + i`.
+    pub fn reset(&mut self, seed: u64) -> Vec<Observation> {
+        self.next_seed = seed.wrapping_add(self.envs.len() as u64);
+        self.envs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, env)| env.reset(seed.wrapping_add(i as u64)))
+            .collect()
+    }
+
+    /// Steps environment i with `actions[i]`.
+    pub fn step(&mut self, actions: &[usize]) -> Vec<(Observation, f32, bool, Info)> {
+        assert_eq!(actions.len(), self.envs.len(), "one action per environment");
+        let chunk = self.envs.len().div_ceil(self.threads.max(1)).max(1);
+        let mut steps: Vec<(Observation, f32, bool, Info)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .envs
+                .chunks_mut(chunk)
+                .zip(actions.chunks(chunk))
+                .map(|(envs, actions)| {
+                    scope.spawn(move || {
+                        envs.iter_mut()
+                            .zip(actions)
+                            .map(|(env, action)| env.step(*action))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        // Seeds are handed out in order so runs repeat regardless of thread count
+        for (env, step) in self.envs.iter_mut().zip(&mut steps) {
+            if step.2 {
+                step.0 = env.reset(self.next_seed);
+                self.next_seed = self.next_seed.wrapping_add(1);
+            }
+        }
+        steps
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ones(values: &[f32]) -> usize {
+        values.iter().filter(|v| **v == 1.0).count()
+    }
+
+    #[test]
+    fn observations_are_the_encoding_long() {
+        for encoding in [
+            Encoding::default(),
+            Encoding {
+                piece_planes: false,
+                previews: 0,
+                garbage_meter: 0,
+            },
+            Encoding {
+                piece_planes: true,
+                previews: MAX_PREVIEWS,
+                garbage_meter: MAX_GARBAGE_METER,
+            },
+        ] {
+            let mut env = Env::new(EnvConfig {
+                encoding: encoding.clone(),
+                ..EnvConfig::default()
+            });
+            let cells = BOARD_HEIGHT * BOARD_WIDTH;
+            for observation in [env.reset(3), env.step(0).0] {
+                assert_eq!(observation.board.len(), encoding.board_len());
+                assert_eq!(observation.features.len(), encoding.features_len());
+                assert_eq!(observation.flat().len(), env.observe().flat().len());
+                if encoding.piece_planes {
+                    // The piece and its ghost
+                    assert_eq!(ones(&observation.board[cells..2 * cells]), 4);
+                    assert_eq!(ones(&observation.board[2 * cells..]), 4);
+                }
+                // One current piece, previews as far as the rules show, and hold
+                let shown = encoding.previews.min(env.config.rules.previews);
+                assert_eq!(ones(&observation.features), 1 + shown + 1);
+            }
+        }
+    }
+
+    #[test]
+    fn steps_play_a_piece_or_a_frame() {
+        let mut env = Env::new(EnvConfig::default());
+        let empty = env.reset(1);
+        assert_eq!(ones(&empty.board[..BOARD_HEIGHT * BOARD_WIDTH]), 0);
+        assert_eq!(env.action_count(), env.placements().len());
+        let (observation, reward, done, info) = env.step(0);
+        assert!(!done);
+        assert_eq!(reward, 0.0);
+        assert_eq!(info.pieces, 1);
+        assert!(info.locked.is_some());
+        assert_eq!(ones(&observation.board[..BOARD_HEIGHT * BOARD_WIDTH]), 4);
+        // Out of range hard drops
+        assert_eq!(env.step(usize::MAX).3.pieces, 2);
+
+        let mut env = Env::new(EnvConfig {
+            action_space: ActionSpace::Inputs,
+            ..EnvConfig::default()
+        });
+        assert_eq!(env.action_count(), INPUTS.len());
+        let (_, _, _, info) = env.step(0);
+        assert_eq!((info.frame, info.pieces), (1, 0));
+        let hard_drop = INPUTS.iter().position(|i| *i == Some(Input::HardDrop));
+        let (_, _, _, info) = env.step(hard_drop.unwrap());
+        assert_eq!((info.frame, info.pieces), (2, 1));
+    }
+
+    #[test]
+    fn episodes_end_and_stay_ended() {
+        let mut env = Env::new(EnvConfig {
+            max_pieces: Some(2),
+            ..EnvConfig::default()
+        });
+        assert!(!env.step(0).2);
+        let (_, _, done, info) = env.step(0);
+        assert!(done && info.truncated);
+        let (_, reward, done, info) = env.step(0);
+        assert!(done);
+        assert_eq!((reward, info.pieces), (0.0, 2));
+        assert_eq!(env.reset(0), Env::new(EnvConfig::default()).observe());
+    }
+
+    #[test]
+    fn batches_keep_their_order() {
+        let config = EnvConfig {
+            max_pieces: Some(3),
+            ..EnvConfig::default()
+        };
+        let run = |threads: usize| {
+            let mut envs = VecEnv::new(5, config.clone());
+            envs.threads = threads;
+            let mut observations = envs.reset(10);
+            for step in 0..4 {
+                let actions: Vec<usize> = (0..5).map(|i| i + step).collect();
+                observations.extend(envs.step(&actions).into_iter().map(|s| s.0));
+            }
+            observations
+        };
+        let batched = run(2);
+        assert_eq!(run(1), batched);
+        assert_eq!(run(8), batched);
+
+        // The same as each environment on its own, with finished ones starting over on
+        // the next seeds in line
+        for i in 0..5 {
+            let mut env = Env::new(config.clone());
+            let mut alone = vec![env.reset(10 + i as u64)];
+            for step in 0..4 {
+                let (observation, _, done, _) = env.step(i + step);
+                alone.push(match done {
+                    true => env.reset(15 + i as u64),
+                    false => observation,
+                });
+            }
+            let together: Vec<Observation> = batched.iter().skip(i).step_by(5).cloned().collect();
+            assert_eq!(together, alone, "environment {}", i);
+        }
+    }
+}
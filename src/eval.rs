@@ -0,0 +1,389 @@
+use crate::board::{Board, Point, BOARD_HEIGHT, BOARD_WIDTH, HIDDEN_ROWS};
+use crate::dirs;
+use crate::movegen::Placement;
+use crate::rules::{self, Chain, LockResult, TSpin};
+use crate::tetromino::{Direction, Kind, Tetromino};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+// Weights are read from a flat `name = value` file, one weight per line, `#` for
+// comments. Weights that aren't listed keep their defaults.
+
+/// What the evaluator measures on a board. Heights are counted from the floor.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Features {
+    /// Sum of the column heights.
+    pub height: f32,
+    pub max_height: f32,
+    /// Sum of height differences between neighbouring columns.
+    pub bumpiness: f32,
+    /// Empty cells with something above them in the same column.
+    pub holes: f32,
+    /// Filled cells sitting above a hole.
+    pub covered: f32,
+    /// Filled/empty changes along each row, with the walls counting as filled.
+    pub row_transitions: f32,
+    /// Filled/empty changes down each column, with the floor counting as filled.
+    pub column_transitions: f32,
+    /// Total depth of the wells (columns lower than both neighbours), except the deepest,
+    /// which is kept open for tetrises.
+    pub wells: f32,
+    /// Lines a t-spin double slot on the board would clear, for the best slot.
+    pub tslot: f32,
+    /// 1 if an I piece dropped into a well would clear four lines.
+    pub tetris_ready: f32,
+}
+
+impl Features {
+    pub fn of(board: &Board) -> Features {
+        let heights: Vec<usize> = (0..BOARD_WIDTH).map(|x| column_height(board, x)).collect();
+        let mut features = Features {
+            height: heights.iter().sum::<usize>() as f32,
+            max_height: *heights.iter().max().unwrap() as f32,
+            bumpiness: heights
+                .windows(2)
+                .map(|w| w[0].abs_diff(w[1]))
+                .sum::<usize>() as f32,
+            ..Features::default()
+        };
+
+        for (x, height) in heights.iter().enumerate() {
+            let mut covering = 0;
+            for y in (BOARD_HEIGHT - height)..BOARD_HEIGHT {
+                if board.cell(x, y).is_some() {
+                    covering += 1;
+                } else {
+                    features.holes += 1.0;
+                    features.covered += covering as f32;
+                    covering = 0;
+                }
+            }
+        }
+
+        for y in HIDDEN_ROWS..BOARD_HEIGHT {
+            let mut last = true;
+            for x in 0..=BOARD_WIDTH {
+                let filled = x == BOARD_WIDTH || board.cell(x, y).is_some();
+                if filled != last {
+                    features.row_transitions += 1.0;
+                }
+                last = filled;
+            }
+        }
+        for x in 0..BOARD_WIDTH {
+            let mut last = false;
+            for y in 0..=BOARD_HEIGHT {
+                let filled = y == BOARD_HEIGHT || board.cell(x, y).is_some();
+                if filled != last {
+                    features.column_transitions += 1.0;
+                }
+                last = filled;
+            }
+        }
+
+        // The walls count as infinitely high neighbours
+        let mut wells: Vec<usize> = (0..BOARD_WIDTH)
+            .map(|x| {
+                let left = if x == 0 { usize::MAX } else { heights[x - 1] };
+                let right = heights.get(x + 1).copied().unwrap_or(usize::MAX);
+                left.min(right).saturating_sub(heights[x])
+            })
+            .collect();
+        let deepest = wells.iter().enumerate().max_by_key(|(_, d)| **d).unwrap().0;
+        let ready = tetris_ready(board, deepest, heights[deepest]);
+        wells[deepest] = 0;
+        features.wells = wells.iter().sum::<usize>() as f32;
+        features.tetris_ready = if ready { 1.0 } else { 0.0 };
+        features.tslot = tslot_lines(board) as f32;
+        features
+    }
+}
+
+fn column_height(board: &Board, x: usize) -> usize {
+    (0..BOARD_HEIGHT)
+        .find(|y| board.cell(x, *y).is_some())
+        .map_or(0, |y| BOARD_HEIGHT - y)
+}
+
+fn full_except(board: &Board, y: usize, hole: usize) -> bool {
+    (0..BOARD_WIDTH).all(|x| (x == hole) != board.cell(x, y).is_some())
+}
+
+// Whether the four rows at the bottom of the well in column `x` are full apart from it
+fn tetris_ready(board: &Board, x: usize, height: usize) -> bool {
+    let bottom = BOARD_HEIGHT - height;
+    bottom >= 4 && (bottom - 4..bottom).all(|y| full_except(board, y, x))
+}
+
+// The most lines a T dropped upside down into an overhung slot would clear
+fn tslot_lines(board: &Board) -> usize {
+    let mut t = Tetromino::new(Kind::T);
+    t.rotate(Direction::Half);
+    let mut best = 0;
+    for y in HIDDEN_ROWS as i32..BOARD_HEIGHT as i32 - 2 {
+        for x in 0..BOARD_WIDTH as i32 - 2 {
+            let origin = Point { x, y };
+            let below = Point { x, y: y + 1 };
+            if board.collision_test(&t, origin)
+                || !board.collision_test(&t, below)
+                || rules::detect_tspin(board, &t, origin, Some(0)) != TSpin::Full
+            {
+                continue;
+            }
+            let mut filled = board.clone();
+            filled.lock_tetromino(&t, origin);
+            let lines = (y as usize + 1..y as usize + 3)
+                .filter(|row| (0..BOARD_WIDTH).all(|col| filled.cell(col, *row).is_some()))
+                .count();
+            best = best.max(lines);
+        }
+    }
+    best
+}
+
+/// How much each feature and each kind of clear is worth. Penalties are negative.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Weights {
+    pub height: f32,
+    pub max_height: f32,
+    pub bumpiness: f32,
+    pub holes: f32,
+    pub covered: f32,
+    pub row_transitions: f32,
+    pub column_transitions: f32,
+    pub wells: f32,
+    pub tslot: f32,
+    pub tetris_ready: f32,
+    // Rewards for the lock that produced the board
+    pub clear1: f32,
+    pub clear2: f32,
+    pub clear3: f32,
+    pub clear4: f32,
+    pub tspin_mini: f32,
+    pub tspin1: f32,
+    pub tspin2: f32,
+    pub tspin3: f32,
+    pub perfect_clear: f32,
+    pub back_to_back: f32,
+    pub combo: f32,
+    pub attack: f32,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            height: -0.4,
+            max_height: -0.6,
+            bumpiness: -0.3,
+            holes: -4.0,
+            covered: -0.6,
+            row_transitions: -0.4,
+            column_transitions: -0.6,
+            wells: -0.3,
+            tslot: 1.5,
+            tetris_ready: 2.0,
+            clear1: -2.0,
+            clear2: -1.5,
+            clear3: -1.0,
+            clear4: 4.0,
+            tspin_mini: -1.0,
+            tspin1: 1.5,
+            tspin2: 5.0,
+            tspin3: 7.0,
+            perfect_clear: 20.0,
+            back_to_back: 1.5,
+            combo: 0.8,
+            attack: 1.0,
+        }
+    }
+}
+
+impl Weights {
+    /// The name of each weight, in the order `to_vec` and `from_slice` use.
+    pub const NAMES: [&'static str; 22] = [
+        "height",
+        "max_height",
+        "bumpiness",
+        "holes",
+        "covered",
+        "row_transitions",
+        "column_transitions",
+        "wells",
+        "tslot",
+        "tetris_ready",
+        "clear1",
+        "clear2",
+        "clear3",
+        "clear4",
+        "tspin_mini",
+        "tspin1",
+        "tspin2",
+        "tspin3",
+        "perfect_clear",
+        "back_to_back",
+        "combo",
+        "attack",
+    ];
+
+    fn fields_mut(&mut self) -> [&mut f32; 22] {
+        [
+            &mut self.height,
+            &mut self.max_height,
+            &mut self.bumpiness,
+            &mut self.holes,
+            &mut self.covered,
+            &mut self.row_transitions,
+            &mut self.column_transitions,
+            &mut self.wells,
+            &mut self.tslot,
+            &mut self.tetris_ready,
+            &mut self.clear1,
+            &mut self.clear2,
+            &mut self.clear3,
+            &mut self.clear4,
+            &mut self.tspin_mini,
+            &mut self.tspin1,
+            &mut self.tspin2,
+            &mut self.tspin3,
+            &mut self.perfect_clear,
+            &mut self.back_to_back,
+            &mut self.combo,
+            &mut self.attack,
+        ]
+    }
+
+    pub fn to_vec(&self) -> Vec<f32> {
+        let mut copy = *self;
+        copy.fields_mut().iter().map(|w| **w).collect()
+    }
+
+    /// Weights in `NAMES` order. Missing values keep their defaults.
+    pub fn from_slice(values: &[f32]) -> Weights {
+        let mut weights = Weights::default();
+        for (field, value) in weights.fields_mut().into_iter().zip(values) {
+            *field = *value;
+        }
+        weights
+    }
+
+    pub fn parse(text: &str) -> Result<Weights, String> {
+        let mut weights = Weights::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", n + 1, msg);
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(err(format!("expected `name = value`, got `{}`", line)));
+            };
+            let (name, value) = (name.trim(), value.trim());
+            let i = Weights::NAMES
+                .iter()
+                .position(|w| *w == name)
+                .ok_or_else(|| err(format!("unknown weight `{}`", name)))?;
+            *weights.fields_mut()[i] = value
+                .parse()
+                .map_err(|_| err(format!("`{}` is not a number", value)))?;
+        }
+        Ok(weights)
+    }
+
+    /// `$XDG_CONFIG_HOME/tetriszero/weights.conf`, falling back to `~/.config`. The
+    /// server's bots and `tetris-tbp` read their weights from here, and `tetris-tune`
+    /// writes here.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("weights.conf"))
+    }
+
+    /// Reads weights from `path`. A missing file just means the defaults.
+    pub fn load(path: &Path) -> io::Result<Weights> {
+        match fs::read_to_string(path) {
+            Ok(text) => Weights::parse(&text).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Weights::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// The weights in the format `parse` reads.
+    pub fn encode(&self) -> String {
+        Weights::NAMES
+            .iter()
+            .zip(self.to_vec())
+            .map(|(name, value)| format!("{} = {}\n", name, value))
+            .collect()
+    }
+
+    /// How good a board looks, regardless of how it came about.
+    pub fn board(&self, features: &Features) -> f32 {
+        self.height * features.height
+            + self.max_height * features.max_height
+            + self.bumpiness * features.bumpiness
+            + self.holes * features.holes
+            + self.covered * features.covered
+            + self.row_transitions * features.row_transitions
+            + self.column_transitions * features.column_transitions
+            + self.wells * features.wells
+            + self.tslot * features.tslot
+            + self.tetris_ready * features.tetris_ready
+    }
+
+    /// How good a lock was: the kind of clear and the garbage it sent.
+    pub fn reward(&self, result: &LockResult) -> f32 {
+        let clear = match (result.tspin, result.lines) {
+            (_, 0) => 0.0,
+            (TSpin::Mini, _) => self.tspin_mini,
+            (TSpin::Full, 1) => self.tspin1,
+            (TSpin::Full, 2) => self.tspin2,
+            (TSpin::Full, _) => self.tspin3,
+            (TSpin::None, 1) => self.clear1,
+            (TSpin::None, 2) => self.clear2,
+            (TSpin::None, 3) => self.clear3,
+            (TSpin::None, _) => self.clear4,
+        };
+        let mut reward = clear + self.attack * result.attack as f32;
+        if result.perfect_clear {
+            reward += self.perfect_clear;
+        }
+        if result.back_to_back {
+            reward += self.back_to_back;
+        }
+        if result.combo > 1 {
+            reward += self.combo * (result.combo - 1) as f32;
+        }
+        reward
+    }
+}
+
+/// Locks `placement` into a copy of `board`, returning the new board and what the lock
+/// scored, the same way the simulation would.
+pub fn place(board: &Board, placement: &Placement, chain: &mut Chain) -> (Board, LockResult) {
+    let mut board = board.clone();
+    let result = rules::lock(
+        &mut board,
+        &placement.tetromino(),
+        placement.position,
+        placement.last_kick,
+        chain,
+    );
+    (board, result)
+}
+
+/// Board value plus lock reward for putting `placement` on `board`.
+pub fn evaluate(
+    board: &Board,
+    placement: &Placement,
+    chain: &mut Chain,
+    weights: &Weights,
+) -> (Board, LockResult, f32) {
+    let (board, result) = place(board, placement, chain);
+    let score = weights.board(&Features::of(&board)) + weights.reward(&result);
+    (board, result, score)
+}
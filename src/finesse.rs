@@ -0,0 +1,197 @@
+use crate::board::Board;
+use crate::movegen::{self, sorted_cells, Placement};
+use crate::simulation::{spawn_position, Input};
+use crate::tetromino::{Kind, Tetromino};
+#[cfg(feature = "save")]
+use serde::{Deserialize, Serialize};
+
+// Finesse counts key presses. Holding a direction or soft drop is one press however far
+// it takes the piece, which is what the movegen's DAS and sonic drop inputs stand for,
+// so the shortest movegen path from spawn is the fewest presses a placement needs.
+
+/// How one piece was played: the keys pressed on it against the fewest that would
+/// have put it in the same place.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Finesse {
+    pub kind: Kind,
+    pub keys: usize,
+    pub optimal: usize,
+}
+
+impl Finesse {
+    /// Judges a piece locked at `placement` on `board` after `keys` presses.
+    pub fn judge(board: &Board, placement: &Placement, keys: usize) -> Finesse {
+        Finesse {
+            kind: placement.kind,
+            keys,
+            // A placement the movegen can't reach isn't held against the player
+            optimal: optimal(board, placement).unwrap_or(keys),
+        }
+    }
+
+    /// Presses beyond the fewest needed.
+    pub fn faults(&self) -> usize {
+        self.keys.saturating_sub(self.optimal)
+    }
+
+    /// "<kind> <keys> <optimal>"
+    pub fn encode(&self) -> String {
+        format!("{} {} {}", self.kind.letter(), self.keys, self.optimal)
+    }
+
+    pub fn decode(text: &str) -> Option<Finesse> {
+        let fields: Vec<&str> = text.split(' ').collect();
+        let [kind, keys, optimal] = fields.as_slice() else {
+            return None;
+        };
+        let mut letters = kind.chars();
+        let kind = Kind::from_letter(letters.next()?).filter(|_| letters.next().is_none())?;
+        Some(Finesse {
+            kind,
+            keys: keys.parse().ok()?,
+            optimal: optimal.parse().ok()?,
+        })
+    }
+
+    /// e.g. "T took 5 keys, 3 needed".
+    pub fn describe(&self) -> String {
+        format!(
+            "{} took {} keys, {} needed",
+            self.kind.letter(),
+            self.keys,
+            self.optimal
+        )
+    }
+}
+
+/// The fewest key presses, hard drop included, that take a new piece from its spawn
+/// position to lock on the cells `placement` covers. None if it can't get there.
+pub fn optimal(board: &Board, placement: &Placement) -> Option<usize> {
+    let tetromino = Tetromino::new(placement.kind);
+    let cells = sorted_cells(placement.cells());
+    movegen::moves(board, &tetromino, spawn_position(&tetromino))
+        .iter()
+        .filter(|m| sorted_cells(m.placement.cells()) == cells)
+        .map(|m| m.inputs.len())
+        .min()
+}
+
+/// Counts key presses toward the piece in play. Holding starts the count over for the
+/// piece that comes out.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Tally {
+    keys: usize,
+    faults: usize,
+    /// Every piece judged so far, in the order they locked. Kept with the run, so a
+    /// save of the session carries each piece's judgement as well as the totals.
+    pub pieces: Vec<Finesse>,
+}
+
+impl Tally {
+    /// Counts one frame's inputs for the player, in the order they were applied, each
+    /// with whether it was a key press rather than a held key repeating. `can_hold` is
+    /// whether holding was allowed as the frame started. If the piece locked this frame,
+    /// at `placement` on `board`, it's judged and the count starts over.
+    pub fn frame(
+        &mut self,
+        inputs: &[(Input, bool)],
+        mut can_hold: bool,
+        locked: Option<(&Board, &Placement)>,
+    ) -> Option<Finesse> {
+        for (input, pressed) in inputs {
+            self.keys += *pressed as usize;
+            match input {
+                Input::Hold if can_hold => {
+                    self.keys = 0;
+                    can_hold = false;
+                }
+                // Anything after a hard drop is dropped with it
+                Input::HardDrop => break,
+                _ => {}
+            }
+        }
+        let (board, placement) = locked?;
+        let finesse = Finesse::judge(board, placement, std::mem::take(&mut self.keys));
+        self.faults += finesse.faults();
+        self.pieces.push(finesse);
+        Some(finesse)
+    }
+
+    /// Faults over every piece judged.
+    pub fn faults(&self) -> usize {
+        self.faults
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::spawn_position;
+
+    // Where `inputs`, the shortest way there, put a new piece on an empty board
+    fn placement(kind: Kind, inputs: &[Input]) -> Placement {
+        let tetromino = Tetromino::new(kind);
+        movegen::moves(&Board::new(), &tetromino, spawn_position(&tetromino))
+            .into_iter()
+            .find(|m| m.inputs == inputs)
+            .expect("a shortest path")
+            .placement
+    }
+
+    #[test]
+    fn counts_the_fewest_presses() {
+        let board = Board::new();
+        let dropped = placement(Kind::O, &[Input::HardDrop]);
+        assert_eq!(optimal(&board, &dropped), Some(1));
+        let walled = placement(Kind::O, &[Input::DasLeft, Input::HardDrop]);
+        assert_eq!(optimal(&board, &walled), Some(2));
+    }
+
+    #[test]
+    fn wasted_presses_are_faults() {
+        let board = Board::new();
+        let dropped = placement(Kind::O, &[Input::HardDrop]);
+        let mut tally = Tally::default();
+        // Held keys repeating aren't presses
+        let moves = [
+            (Input::Left, true),
+            (Input::Left, false),
+            (Input::Right, true),
+        ];
+        assert_eq!(tally.frame(&moves, true, None), None);
+        let finesse = tally.frame(&[(Input::HardDrop, true)], true, Some((&board, &dropped)));
+        assert_eq!(
+            finesse,
+            Some(Finesse {
+                kind: Kind::O,
+                keys: 3,
+                optimal: 1
+            })
+        );
+        assert_eq!(tally.faults(), 2);
+        assert_eq!(tally.pieces.len(), 1);
+    }
+
+    #[test]
+    fn holding_starts_the_count_over() {
+        let board = Board::new();
+        let dropped = placement(Kind::O, &[Input::HardDrop]);
+        let mut tally = Tally::default();
+        let inputs = [
+            (Input::Left, true),
+            (Input::Hold, true),
+            (Input::HardDrop, true),
+        ];
+        let finesse = tally
+            .frame(&inputs, true, Some((&board, &dropped)))
+            .unwrap();
+        assert_eq!(finesse.faults(), 0);
+        // Without a hold to use, the press counts and the count carries on
+        let finesse = tally
+            .frame(&inputs, false, Some((&board, &dropped)))
+            .unwrap();
+        assert_eq!(finesse.keys, 3);
+    }
+}
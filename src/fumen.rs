@@ -0,0 +1,610 @@
+use crate::board::{Board, Point, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::tetromino::{Color, Direction, Kind, Tetromino};
+
+// Fumen is the field editor setups are shared from, and v115 its format: "v115@"
+// followed by base-64 digits, least significant first, with a `?` every so often
+// that means nothing. Each page is
+//
+//     the field as runs of (change from the last page's field, length), 2 digits each
+//     the piece, its rotation and place, and the page's flags, in 3 digits
+//     the comment, if it changed: its length in 2 digits, then 4 characters per 5
+//
+// where a page's field is told apart from the one the page before left behind: the
+// last field with its piece locked in, if it locks, and any full lines cleared. A
+// field that's the same again says so with one more digit, the number of pages after
+// it that don't change it either.
+//
+// Fumen fields are 23 rows high with a garbage row below to raise the field by,
+// one row short of ours: our top row is left out.
+
+const DIGITS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+// Comments are escaped as JavaScript's `escape` does, into these characters
+const COMMENT_CHARS: &str =
+    " !\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+const ROWS: usize = 23;
+// Cells in the field and garbage row together
+const CELLS: usize = (ROWS + 1) * BOARD_WIDTH;
+
+/// One page of a fumen: a field, the piece being placed on it and a comment.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub board: Board,
+    /// The piece and where it is, as the piece in play is.
+    pub piece: Option<(Tetromino, Point)>,
+    /// Comments carry over from page to page until one changes them.
+    pub comment: String,
+    /// Whether the piece locks, clearing any lines it fills, for the next page to
+    /// build on.
+    pub lock: bool,
+}
+
+impl Page {
+    /// A page showing `board`, with no piece or comment.
+    pub fn new(board: Board) -> Page {
+        Page {
+            board,
+            piece: None,
+            comment: String::new(),
+            lock: true,
+        }
+    }
+}
+
+/// Reads the pages of a v115 fumen, e.g. "v115@vhAAgH". Anything before the
+/// version, such as the address of an editor, is skipped.
+pub fn decode(text: &str) -> Result<Vec<Page>, String> {
+    let start = text
+        .find("v115@")
+        .ok_or_else(|| String::from("not a v115 fumen"))?;
+    let digits = text[start + 5..]
+        .chars()
+        .filter(|c| *c != '?' && !c.is_whitespace())
+        .map(|c| {
+            DIGITS
+                .find(c)
+                .map(|d| d as u32)
+                .ok_or_else(|| format!("`{}` isn't part of a fumen", c))
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+    let mut reader = Reader {
+        digits: &digits,
+        at: 0,
+    };
+
+    let mut pages = Vec::new();
+    let mut last = [0u8; CELLS];
+    let mut repeat = 0;
+    let mut comment = String::new();
+    while reader.at < digits.len() {
+        let mut field = last;
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut at = 0;
+            while at < CELLS {
+                let run = reader.read(2)? as usize;
+                let (change, length) = (run / CELLS, run % CELLS + 1);
+                if at + length > CELLS {
+                    return Err(String::from("the field runs over"));
+                }
+                for cell in &mut field[at..at + length] {
+                    *cell = (*cell as usize + change)
+                        .checked_sub(8)
+                        .filter(|c| *c <= 8)
+                        .ok_or_else(|| String::from("a cell isn't a piece or garbage"))?
+                        as u8;
+                }
+                if change == 8 && length == CELLS {
+                    repeat = reader.read(1)?;
+                }
+                at += length;
+            }
+        }
+
+        let action = reader.read(3)? as usize;
+        let code = (action % 8) as u8;
+        let rotation = unflip(action / 8 % 4);
+        let location = action / 32 % CELLS;
+        let flags = action / (32 * CELLS);
+        let rise = flags & 1 != 0;
+        let mirror = flags & 2 != 0;
+        let commented = flags & 8 != 0;
+        let lock = flags & 16 == 0;
+        if commented {
+            let length = reader.read(2)? as usize;
+            let mut escaped = String::new();
+            while escaped.len() < length {
+                let mut chars = reader.read(5)? as usize;
+                for _ in 0..4.min(length - escaped.len()) {
+                    let c = COMMENT_CHARS
+                        .chars()
+                        .nth(chars % 96)
+                        .ok_or_else(|| String::from("bad comment"))?;
+                    escaped.push(c);
+                    chars /= 96;
+                }
+            }
+            comment = unescape(&escaped);
+        }
+
+        let cells = match kind(code) {
+            Some(kind) => {
+                let corrected = correction(kind, rotation);
+                let center = Point {
+                    x: (location % BOARD_WIDTH) as i32 + corrected.x,
+                    y: (ROWS - 1 - location / BOARD_WIDTH) as i32 + corrected.y,
+                };
+                let cells = blocks(kind, rotation).map(|p| Point {
+                    x: center.x + p.x,
+                    y: center.y + p.y,
+                });
+                if !cells.iter().all(|p| {
+                    (0..BOARD_WIDTH as i32).contains(&p.x) && (0..ROWS as i32).contains(&p.y)
+                }) {
+                    return Err(String::from("a piece is off the field"));
+                }
+                Some((kind, cells))
+            }
+            None => None,
+        };
+        pages.push(Page {
+            board: board(&field),
+            piece: cells.map(|(kind, cells)| piece(kind, rotation, cells)),
+            comment: comment.clone(),
+            lock,
+        });
+
+        if lock {
+            if let Some((_, cells)) = cells {
+                for p in cells {
+                    field[index(p)] = code;
+                }
+            }
+            clear_lines(&mut field);
+        }
+        if rise {
+            field.copy_within(BOARD_WIDTH.., 0);
+            field[ROWS * BOARD_WIDTH..].fill(0);
+        }
+        if mirror {
+            for row in field[..ROWS * BOARD_WIDTH].chunks_mut(BOARD_WIDTH) {
+                row.reverse();
+            }
+        }
+        last = field;
+    }
+    Ok(pages)
+}
+
+/// Writes `pages` as a v115 fumen. Our board's top row has no place in it, so it's
+/// left out, along with any piece reaching into it.
+pub fn encode(pages: &[Page]) -> String {
+    let blank = [Page::new(Board::new())];
+    let pages = if pages.is_empty() { &blank[..] } else { pages };
+    let mut digits = Vec::new();
+    let mut last = [0u8; CELLS];
+    // Where the count of pages since the field last changed is
+    let mut repeat = None;
+    let mut comment = "";
+    for (n, page) in pages.iter().enumerate() {
+        let mut field = [0u8; CELLS];
+        for y in 0..ROWS {
+            for x in 0..BOARD_WIDTH {
+                field[y * BOARD_WIDTH + x] = color_code(page.board.cell(x, y + 1));
+            }
+        }
+        let mut runs: Vec<(usize, usize)> = Vec::new();
+        for (cell, old) in field.iter().zip(last) {
+            let change = (*cell + 8 - old) as usize;
+            match runs.last_mut() {
+                Some((c, length)) if *c == change => *length += 1,
+                _ => runs.push((change, 1)),
+            }
+        }
+        let changed = runs != [(8, CELLS)];
+        match repeat {
+            Some(at) if !changed && digits[at] < 63 => digits[at] += 1,
+            _ => {
+                for (change, length) in runs {
+                    push(&mut digits, (change * CELLS + length - 1) as u32, 2);
+                }
+                repeat = None;
+                if !changed {
+                    digits.push(0);
+                    repeat = Some(digits.len() - 1);
+                }
+            }
+        }
+
+        // The top row is ours alone
+        let shown = page
+            .piece
+            .as_ref()
+            .map(|(tetromino, position)| (tetromino, cells(tetromino, *position)))
+            .filter(|(_, cells)| cells.iter().all(|p| p.y > 0));
+        let (code, rotation, location) = match &shown {
+            Some((tetromino, cells)) => {
+                let kind = tetromino.kind;
+                let rotation = tetromino.rotation as usize % 4;
+                let cells = cells.map(|p| Point {
+                    x: p.x,
+                    y: BOARD_HEIGHT as i32 - 1 - p.y,
+                });
+                let offsets = blocks(kind, rotation);
+                let center = *cells
+                    .iter()
+                    .find(|c| {
+                        offsets.iter().all(|o| {
+                            cells.contains(&Point {
+                                x: c.x + o.x,
+                                y: c.y + o.y,
+                            })
+                        })
+                    })
+                    .expect("the cells are the piece's");
+                let corrected = correction(kind, rotation);
+                let raw = Point {
+                    x: center.x - corrected.x,
+                    y: center.y - corrected.y,
+                };
+                let location = (ROWS as i32 - 1 - raw.y) as usize * BOARD_WIDTH + raw.x as usize;
+                (kind_code(kind), unflip(rotation), location)
+            }
+            None => (0, 0, 0),
+        };
+        let fitted = fit_comment(&page.comment);
+        let commented = fitted != comment;
+        let flags =
+            ((n == 0) as usize) << 2 | (commented as usize) << 3 | (!page.lock as usize) << 4;
+        let action = code as usize + 8 * rotation + 32 * location + 32 * CELLS * flags;
+        push(&mut digits, action as u32, 3);
+        if commented {
+            let escaped = escape(fitted);
+            push(&mut digits, escaped.len() as u32, 2);
+            let chars: Vec<usize> = escaped
+                .chars()
+                .map(|c| COMMENT_CHARS.find(c).unwrap_or(0))
+                .collect();
+            for four in chars.chunks(4) {
+                let value = four.iter().rev().fold(0, |value, c| value * 96 + c);
+                push(&mut digits, value as u32, 5);
+            }
+        }
+        comment = fitted;
+
+        if page.lock {
+            if let Some((tetromino, cells)) = shown {
+                for p in cells {
+                    field[(p.y as usize - 1) * BOARD_WIDTH + p.x as usize] =
+                        kind_code(tetromino.kind);
+                }
+            }
+            clear_lines(&mut field);
+        }
+        last = field;
+    }
+
+    let text: String = digits
+        .iter()
+        .map(|d| DIGITS.as_bytes()[*d as usize] as char)
+        .collect();
+    // Editors break the data after 42 characters and then every 47
+    let mut fumen = String::from("v115@");
+    fumen.push_str(&text[..text.len().min(42)]);
+    for chunk in text.as_bytes()[text.len().min(42)..].chunks(47) {
+        fumen.push('?');
+        fumen.push_str(std::str::from_utf8(chunk).expect("digits are ASCII"));
+    }
+    fumen
+}
+
+struct Reader<'a> {
+    digits: &'a [u32],
+    at: usize,
+}
+
+impl Reader<'_> {
+    // A number `n` digits long
+    fn read(&mut self, n: usize) -> Result<u32, String> {
+        let digits = self
+            .digits
+            .get(self.at..self.at + n)
+            .ok_or_else(|| String::from("the fumen ends early"))?;
+        self.at += n;
+        Ok(digits.iter().rev().fold(0, |value, d| value * 64 + d))
+    }
+}
+
+fn push(digits: &mut Vec<u32>, mut value: u32, n: usize) {
+    for _ in 0..n {
+        digits.push(value % 64);
+        value /= 64;
+    }
+}
+
+// Fumen numbers rotations 180, right, spawn, left, where ours go spawn, right, 180,
+// left. Swapping the first and third goes either way.
+fn unflip(rotation: usize) -> usize {
+    [2, 1, 0, 3][rotation]
+}
+
+fn kind(code: u8) -> Option<Kind> {
+    match code {
+        1 => Some(Kind::I),
+        2 => Some(Kind::L),
+        3 => Some(Kind::O),
+        4 => Some(Kind::Z),
+        5 => Some(Kind::T),
+        6 => Some(Kind::J),
+        7 => Some(Kind::S),
+        _ => None,
+    }
+}
+
+fn kind_code(kind: Kind) -> u8 {
+    match kind {
+        Kind::I => 1,
+        Kind::L => 2,
+        Kind::O => 3,
+        Kind::Z => 4,
+        Kind::T => 5,
+        Kind::J => 6,
+        Kind::S => 7,
+    }
+}
+
+fn color_code(cell: Option<Color>) -> u8 {
+    match cell {
+        None => 0,
+        Some(Color::Cyan) => 1,
+        Some(Color::Orange) => 2,
+        Some(Color::Yellow) => 3,
+        Some(Color::Red) => 4,
+        Some(Color::Purple) => 5,
+        Some(Color::Blue) => 6,
+        Some(Color::Green) => 7,
+        Some(Color::Gray | Color::Black) => 8,
+    }
+}
+
+// The field's cells, with fumen's top row our second
+fn board(field: &[u8; CELLS]) -> Board {
+    let mut board = Board::new();
+    for y in 0..ROWS {
+        for x in 0..BOARD_WIDTH {
+            let cell = match field[y * BOARD_WIDTH + x] {
+                0 => None,
+                8 => Some(Color::Gray),
+                code => kind(code).map(|kind| Tetromino::new(kind).color),
+            };
+            board.set_cell(x, y + 1, cell);
+        }
+    }
+    board
+}
+
+// Where a cell is in the field, counting rows up from the bottom
+fn index(p: Point) -> usize {
+    (ROWS - 1 - p.y as usize) * BOARD_WIDTH + p.x as usize
+}
+
+// Full rows of the field go, the garbage row aside
+fn clear_lines(field: &mut [u8; CELLS]) {
+    let kept: Vec<u8> = field[..ROWS * BOARD_WIDTH]
+        .chunks(BOARD_WIDTH)
+        .filter(|row| row.contains(&0))
+        .flatten()
+        .copied()
+        .collect();
+    let start = ROWS * BOARD_WIDTH - kept.len();
+    field[..start].fill(0);
+    field[start..ROWS * BOARD_WIDTH].copy_from_slice(&kept);
+}
+
+// The piece's cells around its center, rows counted up, as fumen places pieces
+fn blocks(kind: Kind, rotation: usize) -> [Point; 4] {
+    let spawn = match kind {
+        Kind::I => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        Kind::T => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        Kind::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        Kind::L => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        Kind::J => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        Kind::S => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+        Kind::Z => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+    };
+    spawn.map(|(x, y)| {
+        // Clockwise a quarter turn at a time
+        (0..rotation).fold(Point { x, y }, |p, _| Point { x: p.y, y: -p.x })
+    })
+}
+
+// Fumen keeps the O, I, S and Z pieces' centers a cell off from the others' in
+// some rotations; this is what takes a stored center to the real one
+fn correction(kind: Kind, rotation: usize) -> Point {
+    let (x, y) = match (kind, rotation) {
+        (Kind::O, 0) => (0, -1),
+        (Kind::O, 2) => (1, 0),
+        (Kind::O, 3) => (1, -1),
+        (Kind::I, 2) => (1, 0),
+        (Kind::I, 3) => (0, -1),
+        (Kind::S, 0) | (Kind::Z, 0) => (0, -1),
+        (Kind::S, 1) => (-1, 0),
+        (Kind::Z, 3) => (1, 0),
+        _ => (0, 0),
+    };
+    Point { x, y }
+}
+
+// The cells `tetromino` covers at `position`
+fn cells(tetromino: &Tetromino, position: Point) -> [Point; 4] {
+    let mut cells = [Point::default(); 4];
+    let mut n = 0;
+    tetromino.each_point(&mut |row, col| {
+        cells[n] = Point {
+            x: position.x + col,
+            y: position.y + row,
+        };
+        n += 1;
+    });
+    cells
+}
+
+// Our piece covering the same cells as fumen's, given with rows counted up
+fn piece(kind: Kind, rotation: usize, cells: [Point; 4]) -> (Tetromino, Point) {
+    let mut tetromino = Tetromino::new(kind);
+    for _ in 0..rotation {
+        tetromino.rotate(Direction::CW);
+    }
+    let top = cells
+        .iter()
+        .map(|p| BOARD_HEIGHT as i32 - 1 - p.y)
+        .min()
+        .unwrap();
+    let left = cells.iter().map(|p| p.x).min().unwrap();
+    let shape = self::cells(&tetromino, Point::default());
+    let position = Point {
+        x: left - shape.iter().map(|p| p.x).min().unwrap(),
+        y: top - shape.iter().map(|p| p.y).min().unwrap(),
+    };
+    (tetromino, position)
+}
+
+// As much of a comment as fits: its length escaped has to fit in two digits, and it's cut
+// between characters so no escape is split
+fn fit_comment(text: &str) -> &str {
+    let mut length = 0;
+    for (i, c) in text.char_indices() {
+        length += escape(c.encode_utf8(&mut [0; 4])).len();
+        if length > 4095 {
+            return &text[..i];
+        }
+    }
+    text
+}
+
+// As JavaScript's `escape`
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for unit in text.encode_utf16() {
+        match char::from_u32(unit as u32) {
+            Some(c) if c.is_ascii_alphanumeric() || "@*_+-./".contains(c) => escaped.push(c),
+            _ if unit < 256 => escaped.push_str(&format!("%{:02X}", unit)),
+            _ => escaped.push_str(&format!("%u{:04X}", unit)),
+        }
+    }
+    escaped
+}
+
+// As JavaScript's `unescape`: anything that isn't an escape is kept as is
+fn unescape(text: &str) -> String {
+    let mut units = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let escape = match rest.strip_prefix("%u") {
+            Some(hex) => hex.get(..4).map(|hex| (hex, 6)),
+            None => rest
+                .strip_prefix('%')
+                .and_then(|hex| hex.get(..2))
+                .map(|hex| (hex, 3)),
+        };
+        match escape.and_then(|(hex, n)| Some((u16::from_str_radix(hex, 16).ok()?, n))) {
+            Some((unit, n)) => {
+                units.push(unit);
+                rest = &rest[n..];
+            }
+            None => {
+                units.extend(c.encode_utf16(&mut [0; 2]).iter());
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    String::from_utf16_lossy(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Kind, rotation and position
+    fn shown(page: &Page) -> Option<(Kind, u8, Point)> {
+        let (tetromino, position) = page.piece.as_ref()?;
+        Some((tetromino.kind, tetromino.rotation, *position))
+    }
+
+    #[test]
+    fn decodes_an_empty_field() {
+        let pages = decode("v115@vhAAgH").unwrap();
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].board, Board::new());
+        assert!(pages[0].piece.is_none() && pages[0].comment.is_empty() && pages[0].lock);
+        assert_eq!(encode(&pages), "v115@vhAAgH");
+    }
+
+    #[test]
+    fn decodes_garbage() {
+        // tetris-fumen's example: four rows of six garbage cells at the bottom
+        let text = "v115@9gF8DeF8DeF8DeF8NeAgH";
+        let pages = decode(text).unwrap();
+        assert_eq!(pages.len(), 1);
+        let board = &pages[0].board;
+        for y in 0..BOARD_HEIGHT {
+            for x in 0..BOARD_WIDTH {
+                let garbage = y >= BOARD_HEIGHT - 4 && x < 6;
+                assert_eq!(
+                    board.cell(x, y),
+                    garbage.then_some(Color::Gray),
+                    "{} {}",
+                    x,
+                    y
+                );
+            }
+        }
+        assert_eq!(encode(&pages), text);
+    }
+
+    #[test]
+    fn pieces_and_comments_round_trip() {
+        let mut first = Page::new(Board::new());
+        let mut t = Tetromino::new(Kind::T);
+        t.rotate(crate::tetromino::Direction::CW);
+        let position = Point { x: 4, y: 19 };
+        first.comment = String::from("TSD ok? 100% 日本");
+        // The next page's field has the piece locked in
+        let mut locked = Board::new();
+        t.each_point(&mut |row, col| {
+            let (x, y) = ((position.x + col) as usize, (position.y + row) as usize);
+            locked.set_cell(x, y, Some(Color::Purple));
+        });
+        first.piece = Some((t, position));
+        let mut second = Page::new(locked);
+        second.piece = Some((Tetromino::new(Kind::I), Point { x: 3, y: 10 }));
+        second.comment = first.comment.clone();
+        second.lock = false;
+        let pages = decode(&encode(&[first.clone(), second.clone()])).unwrap();
+        assert_eq!(pages.len(), 2);
+        assert_eq!(shown(&pages[0]), shown(&first));
+        assert_eq!(pages[0].comment, first.comment);
+        assert_eq!(pages[1].board, second.board);
+        assert_eq!(shown(&pages[1]), shown(&second));
+        assert_eq!(pages[1].comment, second.comment);
+        assert!(!pages[1].lock);
+    }
+
+    #[test]
+    fn long_comments_are_cut_between_characters() {
+        // Six escaped characters each, so 682 of them fit
+        let long = "日".repeat(1000);
+        let mut first = Page::new(Board::new());
+        first.comment = long.clone();
+        let mut second = first.clone();
+        second.comment = long;
+        let text = encode(&[first, second]);
+        let pages = decode(&text).unwrap();
+        assert_eq!(pages[0].comment, "日".repeat(682));
+        assert_eq!(pages[1].comment, pages[0].comment);
+        // The second page is the same as the first, so it doesn't write the comment again
+        let mut once = Page::new(Board::new());
+        once.comment = "日".repeat(682);
+        assert_eq!(text, encode(&[once.clone(), once]));
+    }
+}
@@ -0,0 +1,335 @@
+use crate::protocol::ClientMessage;
+use crate::simulation::Input;
+#[cfg(feature = "save")]
+use serde::{Deserialize, Serialize};
+
+// How many frames of network jitter the server forgives when checking repeats
+const TOLERANCE_FRAMES: u64 = 6;
+
+/// How held keys repeat, in frames.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Handling {
+    /// Delayed auto shift: how long a direction is held before it starts repeating.
+    pub das: u32,
+    /// Auto repeat rate: frames between repeats once charged; 0 shifts straight to the wall.
+    pub arr: u32,
+    /// Soft drop factor: soft drop falls this many times faster than gravity; 0 is instant.
+    pub sdf: u32,
+    /// DAS cut delay: rotating, holding or dropping pauses a charged shift this long.
+    pub dcd: u32,
+}
+impl Default for Handling {
+    fn default() -> Self {
+        Handling {
+            das: 10,
+            arr: 2,
+            sdf: 20,
+            dcd: 1,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Shift {
+    input: Input,
+    charge: u32,
+    // Whether the key is known to be held; without key-up events a single press might be a tap
+    confirmed: bool,
+}
+
+/// Turns key-down/key-up into messages for the server, one frame at a time.
+#[derive(Debug, Clone, Default)]
+pub struct Autoshift {
+    pub handling: Handling,
+    // Held directions, most recent last; only the most recent one shifts
+    shifts: Vec<Shift>,
+    // Soft drop progress in gravity units while the key is held
+    soft_drop: Option<u32>,
+}
+
+impl Autoshift {
+    pub fn new(handling: Handling) -> Self {
+        Autoshift {
+            handling,
+            ..Autoshift::default()
+        }
+    }
+
+    pub fn is_held(&self, input: Input) -> bool {
+        match input {
+            Input::SoftDrop => self.soft_drop.is_some(),
+            _ => self.shifts.iter().any(|s| s.input == input),
+        }
+    }
+
+    /// A key went down. Left, right and soft drop stay held until `release`; everything
+    /// else is a single input.
+    pub fn press(&mut self, input: Input, confirmed: bool) -> Vec<ClientMessage> {
+        match input {
+            Input::Left | Input::Right => {
+                self.shifts.retain(|s| s.input != input);
+                self.shifts.push(Shift {
+                    input,
+                    charge: 0,
+                    confirmed,
+                });
+                vec![ClientMessage::Press(input)]
+            }
+            Input::SoftDrop => {
+                self.soft_drop = Some(0);
+                vec![ClientMessage::Press(input)]
+            }
+            _ => {
+                self.cut();
+                vec![ClientMessage::Input(input)]
+            }
+        }
+    }
+
+    /// The key is definitely being held down, not tapped.
+    pub fn confirm(&mut self, input: Input) {
+        if let Some(shift) = self.shifts.iter_mut().find(|s| s.input == input) {
+            shift.confirmed = true;
+        }
+    }
+
+    pub fn release(&mut self, input: Input) -> Vec<ClientMessage> {
+        if !self.is_held(input) {
+            return Vec::new();
+        }
+        match input {
+            Input::SoftDrop => self.soft_drop = None,
+            _ => {
+                let was_active = self.shifts.last().map(|s| s.input) == Some(input);
+                self.shifts.retain(|s| s.input != input);
+                // The other direction, if still held, charges again from scratch
+                if was_active {
+                    if let Some(shift) = self.shifts.last_mut() {
+                        shift.charge = 0;
+                    }
+                }
+            }
+        }
+        vec![ClientMessage::Release(input)]
+    }
+
+    /// Advances one frame and returns the repeats that fall on it. `gravity` is the
+    /// current gravity in frames per row.
+    pub fn tick(&mut self, gravity: u32) -> Vec<ClientMessage> {
+        let Handling { das, arr, sdf, .. } = self.handling;
+        let mut repeats = Vec::new();
+        if let Some(shift) = self.shifts.last_mut() {
+            shift.charge = shift.charge.saturating_add(1);
+            if shift.confirmed && shift.charge >= das {
+                if arr == 0 {
+                    let wall = match shift.input {
+                        Input::Left => Input::DasLeft,
+                        _ => Input::DasRight,
+                    };
+                    repeats.push(ClientMessage::Repeat(wall));
+                } else if (shift.charge - das) % arr == 0 {
+                    repeats.push(ClientMessage::Repeat(shift.input));
+                }
+            }
+        }
+        if let Some(progress) = self.soft_drop.as_mut() {
+            if sdf == 0 {
+                repeats.push(ClientMessage::Repeat(Input::SonicDrop));
+            } else if gravity > 0 {
+                *progress += sdf;
+                while *progress >= gravity {
+                    *progress -= gravity;
+                    repeats.push(ClientMessage::Repeat(Input::SoftDrop));
+                }
+            }
+        }
+        repeats
+    }
+
+    // DAS cut: a charged shift waits `dcd` frames before repeating again
+    fn cut(&mut self) {
+        let Handling { das, dcd, .. } = self.handling;
+        if let Some(shift) = self.shifts.last_mut() {
+            if shift.charge >= das {
+                shift.charge = das.saturating_sub(dcd);
+            }
+        }
+    }
+}
+
+/// Server-side check that repeated movement could really have come from holding a key
+/// with the handling the client announced.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct RepeatGuard {
+    pub handling: Handling,
+    // (key, frame it was pressed, repeats accepted since)
+    held: Vec<(Input, u64, u64)>,
+}
+
+impl RepeatGuard {
+    pub fn press(&mut self, input: Input, frame: u64) {
+        self.release(input);
+        self.held.push((input, frame, 0));
+    }
+
+    pub fn release(&mut self, input: Input) {
+        self.held.retain(|(key, _, _)| *key != input);
+    }
+
+    /// Whether a repeated `input` arriving at `frame` is allowed. Accepted repeats are counted.
+    pub fn allow(&mut self, input: Input, frame: u64, gravity: u32) -> bool {
+        let Handling { das, arr, sdf, .. } = self.handling;
+        let (das, arr, sdf, gravity) = (das as u64, arr as u64, sdf as u64, gravity as u64);
+        let key = match input {
+            Input::Left | Input::DasLeft => Input::Left,
+            Input::Right | Input::DasRight => Input::Right,
+            Input::SoftDrop | Input::SonicDrop => Input::SoftDrop,
+            _ => return false,
+        };
+        let Some((_, pressed, repeats)) = self.held.iter_mut().find(|(k, _, _)| *k == key) else {
+            return false;
+        };
+        let elapsed = frame.saturating_sub(*pressed) + TOLERANCE_FRAMES;
+        let allowed = match input {
+            Input::Left | Input::Right if arr > 0 && elapsed >= das => 1 + (elapsed - das) / arr,
+            Input::DasLeft | Input::DasRight if arr == 0 && elapsed >= das => u64::MAX,
+            Input::SoftDrop if sdf > 0 && gravity > 0 => 1 + elapsed * sdf / gravity,
+            Input::SonicDrop if sdf == 0 => u64::MAX,
+            _ => 0,
+        };
+        if *repeats < allowed {
+            *repeats += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ClientMessage::{Press, Repeat};
+
+    const HANDLING: Handling = Handling {
+        das: 10,
+        arr: 2,
+        sdf: 20,
+        dcd: 3,
+    };
+
+    // The frames, counting from 1, that `input` repeats on over the next `frames`
+    fn repeats(autoshift: &mut Autoshift, input: Input, frames: u32) -> Vec<u32> {
+        (1..=frames)
+            .filter(|_| autoshift.tick(60).contains(&Repeat(input)))
+            .collect()
+    }
+
+    #[test]
+    fn shifts_charge_then_repeat() {
+        let mut autoshift = Autoshift::new(HANDLING);
+        assert_eq!(autoshift.press(Input::Left, true), vec![Press(Input::Left)]);
+        assert_eq!(repeats(&mut autoshift, Input::Left, 16), [10, 12, 14, 16]);
+        // Pressing the other way takes over and charges from scratch
+        autoshift.press(Input::Right, true);
+        assert_eq!(repeats(&mut autoshift, Input::Right, 12), [10, 12]);
+        // Letting go of it hands back to the first, charging again too
+        assert_eq!(
+            autoshift.release(Input::Right),
+            vec![ClientMessage::Release(Input::Right)]
+        );
+        assert_eq!(repeats(&mut autoshift, Input::Left, 10), [10]);
+        assert!(autoshift.release(Input::Right).is_empty());
+    }
+
+    #[test]
+    fn taps_only_repeat_once_confirmed() {
+        let mut autoshift = Autoshift::new(HANDLING);
+        autoshift.press(Input::Left, false);
+        assert!(repeats(&mut autoshift, Input::Left, 20).is_empty());
+        // Held all along, so it's charged already
+        autoshift.confirm(Input::Left);
+        assert_eq!(repeats(&mut autoshift, Input::Left, 4), [2, 4]);
+    }
+
+    #[test]
+    fn no_arr_goes_straight_to_the_wall() {
+        let mut autoshift = Autoshift::new(Handling { arr: 0, ..HANDLING });
+        autoshift.press(Input::Right, true);
+        assert_eq!(repeats(&mut autoshift, Input::DasRight, 12), [10, 11, 12]);
+        assert!(repeats(&mut autoshift, Input::Right, 5).is_empty());
+    }
+
+    #[test]
+    fn soft_drop_goes_sdf_times_gravity() {
+        let mut autoshift = Autoshift::new(HANDLING);
+        autoshift.press(Input::SoftDrop, true);
+        // 20 times gravity at 60 frames a row is a row every 3 frames
+        assert_eq!(repeats(&mut autoshift, Input::SoftDrop, 9), [3, 6, 9]);
+        autoshift.release(Input::SoftDrop);
+        assert!(repeats(&mut autoshift, Input::SoftDrop, 9).is_empty());
+
+        let mut autoshift = Autoshift::new(Handling { sdf: 0, ..HANDLING });
+        autoshift.press(Input::SoftDrop, true);
+        assert_eq!(repeats(&mut autoshift, Input::SonicDrop, 3), [1, 2, 3]);
+    }
+
+    #[test]
+    fn rotating_cuts_a_charged_shift() {
+        let mut autoshift = Autoshift::new(HANDLING);
+        autoshift.press(Input::Left, true);
+        assert_eq!(repeats(&mut autoshift, Input::Left, 10), [10]);
+        assert_eq!(
+            autoshift.press(Input::RotateCW, true),
+            vec![ClientMessage::Input(Input::RotateCW)]
+        );
+        // DCD frames of nothing, then back to the full rate
+        assert_eq!(repeats(&mut autoshift, Input::Left, 7), [3, 5, 7]);
+    }
+
+    #[test]
+    fn guard_allows_what_the_handling_could_send() {
+        let mut guard = RepeatGuard {
+            handling: HANDLING,
+            ..RepeatGuard::default()
+        };
+        // Nothing repeats before it's pressed
+        assert!(!guard.allow(Input::Left, 100, 60));
+        guard.press(Input::Left, 100);
+        // Charging, even allowing for jitter
+        assert!(!guard.allow(Input::Left, 103, 60));
+        // Charged with the jitter, one repeat so far
+        assert!(guard.allow(Input::Left, 104, 60));
+        assert!(!guard.allow(Input::Left, 104, 60));
+        // Six frames later, three more at an ARR of 2
+        assert_eq!(
+            (0..5).filter(|_| guard.allow(Input::Left, 110, 60)).count(),
+            3
+        );
+        // With an ARR, shifting to the wall at once is too fast
+        assert!(!guard.allow(Input::DasLeft, 200, 60));
+        guard.release(Input::Left);
+        assert!(!guard.allow(Input::Left, 200, 60));
+        // Only held movement repeats
+        assert!(!guard.allow(Input::RotateCW, 200, 60));
+    }
+
+    #[test]
+    fn guard_allows_soft_drop_at_sdf() {
+        let mut guard = RepeatGuard {
+            handling: HANDLING,
+            ..RepeatGuard::default()
+        };
+        guard.press(Input::SoftDrop, 0);
+        // 6 frames of jitter at 20 times gravity 60 is 2 rows, plus the first
+        assert_eq!(
+            (0..10)
+                .filter(|_| guard.allow(Input::SoftDrop, 0, 60))
+                .count(),
+            3
+        );
+        assert!(!guard.allow(Input::SonicDrop, 0, 60));
+    }
+}
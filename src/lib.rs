@@ -0,0 +1,59 @@
+//! TetrisZero: the rules engine as a library.
+//!
+//! `board`, `tetromino`, `rules` and `simulation` make up the engine and have no
+//! networking or async dependencies, and `protocol` is the text format spoken over
+//! the wire. `session` runs a match's frame loop in those terms, for the server and
+//! for local play of the solo `mode`s. The tokio game server lives behind the `server` feature, the terminal
+//! client behind `client`, the Tetris Bot Protocol bridge behind `tbp` and saving
+//! sessions behind `save` (all on by default).
+
+pub mod beam;
+pub mod board;
+pub mod bot;
+pub mod dirs;
+pub mod env;
+pub mod eval;
+pub mod finesse;
+pub mod fumen;
+pub mod handling;
+pub mod mcts;
+pub mod mode;
+pub mod movegen;
+pub mod nn;
+pub mod opener;
+pub mod pc;
+pub mod protocol;
+pub mod puzzle;
+pub mod rules;
+#[cfg(feature = "save")]
+pub mod save;
+pub mod selfplay;
+pub mod session;
+pub mod simulation;
+#[cfg(feature = "tbp")]
+pub mod tbp;
+pub mod tetromino;
+pub mod tune;
+
+#[cfg(feature = "server")]
+pub mod accounts;
+#[cfg(feature = "server")]
+pub mod game;
+#[cfg(feature = "server")]
+pub mod participant;
+#[cfg(feature = "server")]
+pub mod player;
+#[cfg(feature = "server")]
+pub mod server3;
+
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "client")]
+pub mod config;
+#[cfg(feature = "client")]
+pub mod render;
+
+pub use board::{Board, Point};
+pub use rules::{Chain, LockResult, TSpin};
+pub use simulation::{Event, Input, Rules, Simulation};
+pub use tetromino::{Color, Direction, Kind, Tetromino, TetrominoQueue};
@@ -1,17 +1,169 @@
-mod board;
-mod game;
-mod player;
-mod server3;
-mod tetromino;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tetris_zero::accounts::Accounts;
+use tetris_zero::bot::Difficulty;
+use tetris_zero::eval::Weights;
+use tetris_zero::mode::GameMode;
+use tetris_zero::nn::Network;
+use tetris_zero::opener::Opener;
+use tetris_zero::participant::BotKind;
+use tetris_zero::puzzle::Puzzle;
+use tetris_zero::server3::start_game_server;
+use tetris_zero::session::Session;
 
-use crate::server3::server3::start_game_server;
+const USAGE: &str = "Usage: tetris-2 [--players N] [--bot LEVEL]... [--vs-bot [LEVEL]] [--tbp-bot COMMAND]... [--weights FILE] [--network FILE] [--bot-threads N] [--opener NAME] [--mode MODE] [--puzzle FILE] [--accounts [FILE]] [address]\n\
+     LEVEL is easy, medium, hard or <pps (0.01-100)>:<depth>:<mistake rate (0-1)>\n\
+     COMMAND starts a bot speaking the Tetris Bot Protocol, e.g. \"tetris-tbp --level easy\"\n\
+     Our bots judge boards with weights from FILE (default ~/.config/tetriszero/weights.conf)\n\
+     and start by building the opener NAME if given: TKI, MKO, PCO or DT-cannon\n\
+     --network has them search with MCTS instead, judging positions with the network in FILE\n\
+     at their level's pace and mistake rate; they don't build openers then\n\
+     --bot-threads is how many threads each of those searches on; by default they share\n\
+     the machine's cores out evenly\n\
+     MODE is versus (the default) or a solo mode for one player:\n\
+     sprint[:LINES], ultra[:SECONDS], marathon[:LINES], cheese[:LINES] or survival\n\
+     --puzzle plays the puzzle in FILE solo; solo games have no bots\n\
+     --accounts lets people sign in to keep their match history, records and stats,\n\
+     in FILE (default ~/.local/share/tetriszero/accounts.json)";
 
 #[tokio::main] // This attribute macro automatically sets up the Tokio runtime
 async fn main() {
+    let mut players = 10;
+    let mut bots = Vec::new();
+    let mut levels = Vec::new();
+    let mut weights_path = None;
+    let mut network_path = None;
+    let mut bot_threads = None;
+    let mut opener = None;
+    let mut mode = GameMode::Versus;
+    let mut puzzle_path = None;
+    let mut accounts_path = None;
+    let mut addr = String::from("127.0.0.1:7878");
+    let mut args = std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--players" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) => players = n,
+                None => return eprintln!("{}", USAGE),
+            },
+            "--bot" => match args.next().as_deref().and_then(Difficulty::parse) {
+                Some(difficulty) => levels.push(difficulty),
+                None => return eprintln!("{}", USAGE),
+            },
+            "--tbp-bot" => match args.next() {
+                Some(command) => bots.push(BotKind::Tbp(command)),
+                None => return eprintln!("{}", USAGE),
+            },
+            // Quick start: you against one bot
+            "--vs-bot" => {
+                let level = args.next_if(|a| Difficulty::parse(a).is_some());
+                levels = vec![level
+                    .as_deref()
+                    .and_then(Difficulty::parse)
+                    .unwrap_or(Difficulty::MEDIUM)];
+                bots.clear();
+                players = 2;
+            }
+            "--weights" => match args.next() {
+                Some(path) => weights_path = Some(PathBuf::from(path)),
+                None => return eprintln!("{}", USAGE),
+            },
+            "--network" => match args.next() {
+                Some(path) => network_path = Some(PathBuf::from(path)),
+                None => return eprintln!("{}", USAGE),
+            },
+            "--bot-threads" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) if n > 0 => bot_threads = Some(n),
+                _ => return eprintln!("{}", USAGE),
+            },
+            "--opener" => match args.next().as_deref().and_then(Opener::find) {
+                Some(o) => opener = Some(o),
+                None => return eprintln!("{}", USAGE),
+            },
+            // Puzzles need their field, so they only come from a file
+            "--mode" => match args.next().as_deref().and_then(GameMode::parse) {
+                Some(m) if !matches!(m, GameMode::Puzzle { .. }) => mode = m,
+                _ => return eprintln!("{}", USAGE),
+            },
+            "--puzzle" => match args.next() {
+                Some(path) => puzzle_path = Some(PathBuf::from(path)),
+                None => return eprintln!("{}", USAGE),
+            },
+            "--accounts" => {
+                // Addresses always have a port, so anything else that follows is the file
+                let path = args.next_if(|a| !a.starts_with("--") && !a.contains(':'));
+                match path.map(PathBuf::from).or_else(Accounts::default_path) {
+                    Some(path) => accounts_path = Some(path),
+                    None => return eprintln!("{}", USAGE),
+                }
+            }
+            _ if arg.starts_with("--") => return eprintln!("{}", USAGE),
+            _ => addr = arg,
+        }
+    }
+    if players == 0 {
+        return eprintln!("{}", USAGE);
+    }
+    let session = match puzzle_path {
+        Some(path) => match Puzzle::load(&path) {
+            Ok(puzzle) => Session::with_puzzle(rand::random(), puzzle),
+            Err(e) => return eprintln!("Bad puzzle: {}", e),
+        },
+        None => Session::new(rand::random(), mode),
+    };
+    // The one seat in a solo game is for whoever connects
+    if session.mode.is_solo() {
+        if !levels.is_empty() || !bots.is_empty() {
+            return eprintln!("{}", USAGE);
+        }
+        players = 1;
+    }
+    let weights = match weights_path.or_else(Weights::default_path) {
+        Some(path) => match Weights::load(&path) {
+            Ok(weights) => weights,
+            Err(e) => return eprintln!("Bad weights: {}", e),
+        },
+        None => Weights::default(),
+    };
+    let network = match network_path.map(|path| Network::load(&path)) {
+        Some(Ok(network)) => Some(Arc::new(network)),
+        Some(Err(e)) => return eprintln!("Bad network: {}", e),
+        None => None,
+    };
+    if network.is_some() && opener.is_some() {
+        return eprintln!("{}", USAGE);
+    }
+    let accounts = match accounts_path.map(|path| Accounts::open(&path)) {
+        Some(Ok(accounts)) => Some(accounts),
+        Some(Err(e)) => return eprintln!("Bad accounts: {}", e),
+        None => None,
+    };
+    let threads = bot_threads.unwrap_or_else(|| {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        (cores / levels.len().max(1)).max(1)
+    });
+    // Our own bots sit down before outside ones
+    let mut seated: Vec<BotKind> = levels
+        .into_iter()
+        .map(|difficulty| match &network {
+            Some(network) => BotKind::Network {
+                difficulty,
+                network: network.clone(),
+                threads,
+            },
+            None => BotKind::Builtin {
+                difficulty,
+                weights,
+                opener,
+            },
+        })
+        .collect();
+    seated.extend(bots);
+
     println!("Launching server!");
 
     // Call the start_game_server function with the required arguments and await its result
-    if let Err(e) = start_game_server("127.0.0.1:7878", 10).await {
+    if let Err(e) = start_game_server(&addr, players, &seated, session, accounts).await {
         eprintln!("Failed to start game server: {}", e);
     }
-}
\ No newline at end of file
+}
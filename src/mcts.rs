@@ -0,0 +1,419 @@
+use crate::bot::{BotState, Brain, Difficulty, Plan, MISTAKE_CHOICES};
+use crate::eval::{Features, Weights};
+use crate::rules::LockResult;
+use crate::simulation::FRAMES_PER_SECOND;
+use crate::tetromino::Kind;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::time::{Duration, Instant};
+
+/// Judges positions for the tree search.
+pub trait Evaluator: Sync {
+    /// How promising each of `plans` looks, as probabilities, and how good `state` is.
+    fn evaluate(&self, state: &BotState, plans: &[Plan]) -> (Vec<f32>, f32);
+
+    /// What a single lock is worth on top of the position it leaves.
+    fn reward(&self, result: &LockResult) -> f32 {
+        result.attack as f32
+    }
+}
+
+/// The hand-written evaluator: board features for the value, and a softmax over how
+/// each plan's result looks for the priors.
+#[derive(Debug, Clone)]
+pub struct Heuristic {
+    pub weights: Weights,
+    /// Higher spreads the priors out more evenly.
+    pub temperature: f32,
+}
+
+impl Default for Heuristic {
+    fn default() -> Self {
+        Heuristic {
+            weights: Weights::default(),
+            temperature: 2.0,
+        }
+    }
+}
+
+impl Evaluator for Heuristic {
+    fn evaluate(&self, state: &BotState, plans: &[Plan]) -> (Vec<f32>, f32) {
+        let scores: Vec<f32> = plans
+            .iter()
+            .map(|plan| {
+                let (next, result) = state.play(plan);
+                self.weights.reward(&result) + self.weights.board(&Features::of(&next.board))
+            })
+            .collect();
+        let value = self.weights.board(&Features::of(&state.board));
+        (softmax(&scores, self.temperature), value)
+    }
+
+    fn reward(&self, result: &LockResult) -> f32 {
+        self.weights.reward(result)
+    }
+}
+
+pub fn softmax(scores: &[f32], temperature: f32) -> Vec<f32> {
+    let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let exp: Vec<f32> = scores
+        .iter()
+        .map(|s| ((s - max) / temperature).exp())
+        .collect();
+    let sum: f32 = exp.iter().sum();
+    exp.iter().map(|e| e / sum).collect()
+}
+
+/// Monte Carlo tree search over placements. Pieces in the known queue are played out
+/// as they are; pieces past it are drawn at random from what the 7-bag still allows.
+#[derive(Debug, Clone)]
+pub struct Mcts<E = Heuristic> {
+    pub evaluator: E,
+    pub think_time: Duration,
+    /// Stop after this many iterations per thread, even with time left.
+    pub iterations: Option<u32>,
+    /// Each thread grows its own tree and their visit counts are added up at the end.
+    pub threads: usize,
+    /// How much the priors count against the values found so far.
+    pub exploration: f32,
+}
+
+impl Default for Mcts {
+    fn default() -> Self {
+        Mcts {
+            evaluator: Heuristic::default(),
+            think_time: Duration::from_millis(200),
+            iterations: None,
+            threads: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            exploration: 1.5,
+        }
+    }
+}
+
+/// One of the plans for the current piece, and how the search rated it.
+#[derive(Debug, Clone)]
+pub struct Choice {
+    pub plan: Plan,
+    pub visits: u32,
+    /// Average value found below this plan.
+    pub value: f32,
+}
+
+// Value of a position where the game is over
+const DEAD: f32 = -1000.0;
+
+struct Edge {
+    plan: Plan,
+    prior: f32,
+    reward: f32,
+    // The position right after the lock, before any unknown pieces are dealt
+    after: BotState,
+    visits: u32,
+    total: f32,
+    // One child per distinct run of dealt pieces
+    outcomes: Vec<(Vec<Kind>, usize)>,
+}
+
+struct Node {
+    state: BotState,
+    edges: Vec<Edge>,
+    expanded: bool,
+    value: f32,
+}
+
+struct Tree<'a, E> {
+    mcts: &'a Mcts<E>,
+    nodes: Vec<Node>,
+    rng: Pcg32,
+    // Range of edge values seen, to put them on the same scale as the priors
+    min: f32,
+    max: f32,
+}
+
+impl<'a, E: Evaluator> Tree<'a, E> {
+    fn new(mcts: &'a Mcts<E>, state: &BotState, seed: u64) -> Self {
+        Tree {
+            mcts,
+            nodes: vec![Node {
+                state: state.clone(),
+                edges: Vec::new(),
+                expanded: false,
+                value: 0.0,
+            }],
+            rng: Pcg32::seed_from_u64(seed),
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+        }
+    }
+
+    fn expand(&mut self, id: usize) -> f32 {
+        let state = &self.nodes[id].state;
+        let mut plans = Vec::new();
+        let mut afters = Vec::new();
+        for plan in state.plans() {
+            let (after, result) = state.play(&plan);
+            if !after.board.lost() {
+                plans.push(plan);
+                afters.push((after, self.mcts.evaluator.reward(&result)));
+            }
+        }
+        let value = if plans.is_empty() {
+            DEAD
+        } else {
+            let (priors, value) = self.mcts.evaluator.evaluate(state, &plans);
+            self.nodes[id].edges = plans
+                .into_iter()
+                .zip(afters)
+                .zip(priors)
+                .map(|((plan, (after, reward)), prior)| Edge {
+                    plan,
+                    prior,
+                    reward,
+                    after,
+                    visits: 0,
+                    total: 0.0,
+                    outcomes: Vec::new(),
+                })
+                .collect();
+            value
+        };
+        let node = &mut self.nodes[id];
+        node.expanded = true;
+        node.value = value;
+        value
+    }
+
+    fn normalize(&self, value: f32) -> f32 {
+        if self.max > self.min {
+            (value - self.min) / (self.max - self.min)
+        } else {
+            0.5
+        }
+    }
+
+    fn select(&self, id: usize) -> Option<usize> {
+        let node = &self.nodes[id];
+        let visits: u32 = node.edges.iter().map(|e| e.visits).sum();
+        let explore = self.mcts.exploration * (visits.max(1) as f32).sqrt();
+        node.edges
+            .iter()
+            .map(|edge| {
+                let q = if edge.visits == 0 {
+                    0.0
+                } else {
+                    self.normalize(edge.total / edge.visits as f32)
+                };
+                q + explore * edge.prior / (1 + edge.visits) as f32
+            })
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+
+    // Deals whatever pieces the position needs and returns the node for the result
+    fn outcome(&mut self, id: usize, edge: usize) -> usize {
+        let mut state = self.nodes[id].edges[edge].after.clone();
+        let mut dealt = Vec::new();
+        while state.piece.is_none() || (state.hold_enabled && state.queue.is_empty()) {
+            let kind = *state.possible_next().choose(&mut self.rng).unwrap();
+            state.deal(kind);
+            dealt.push(kind);
+        }
+        let outcomes = &self.nodes[id].edges[edge].outcomes;
+        if let Some((_, child)) = outcomes.iter().find(|(kinds, _)| *kinds == dealt) {
+            return *child;
+        }
+        let child = self.nodes.len();
+        self.nodes.push(Node {
+            state,
+            edges: Vec::new(),
+            expanded: false,
+            value: 0.0,
+        });
+        self.nodes[id].edges[edge].outcomes.push((dealt, child));
+        child
+    }
+
+    fn iterate(&mut self) {
+        let mut path = Vec::new();
+        let mut id = 0;
+        let mut value = loop {
+            let node = &self.nodes[id];
+            if !node.expanded {
+                break if node.state.dead() {
+                    self.nodes[id].expanded = true;
+                    self.nodes[id].value = DEAD;
+                    DEAD
+                } else {
+                    self.expand(id)
+                };
+            }
+            let Some(edge) = self.select(id) else {
+                break node.value;
+            };
+            path.push((id, edge));
+            id = self.outcome(id, edge);
+        };
+        for (id, edge) in path.into_iter().rev() {
+            let edge = &mut self.nodes[id].edges[edge];
+            value += edge.reward;
+            edge.visits += 1;
+            edge.total += value;
+            let mean = edge.total / edge.visits as f32;
+            self.min = self.min.min(mean);
+            self.max = self.max.max(mean);
+        }
+    }
+}
+
+impl<E> Mcts<E> {
+    /// The same search settings judging positions with `evaluator` instead.
+    pub fn with_evaluator<F>(self, evaluator: F) -> Mcts<F> {
+        Mcts {
+            evaluator,
+            think_time: self.think_time,
+            iterations: self.iterations,
+            threads: self.threads,
+            exploration: self.exploration,
+        }
+    }
+}
+
+impl<E: Evaluator> Mcts<E> {
+    /// Rates every plan for the current piece, most visited first. `seed` drives the
+    /// pieces dealt past the known queue, so equal seeds and iteration limits give equal
+    /// results.
+    pub fn search(&self, state: &BotState, seed: u64) -> Vec<Choice> {
+        let deadline = Instant::now() + self.think_time;
+        let roots: Vec<Vec<Choice>> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.threads.max(1) as u64)
+                .map(|thread| {
+                    scope.spawn(move || {
+                        let mut tree = Tree::new(self, state, seed.wrapping_add(thread));
+                        let mut iterations = 0;
+                        while self.iterations.is_none_or(|limit| iterations < limit) {
+                            tree.iterate();
+                            iterations += 1;
+                            if tree.nodes[0].edges.is_empty() || Instant::now() >= deadline {
+                                break;
+                            }
+                        }
+                        tree.nodes
+                            .swap_remove(0)
+                            .edges
+                            .into_iter()
+                            .map(|e| Choice {
+                                plan: e.plan,
+                                visits: e.visits,
+                                value: e.total,
+                            })
+                            .collect()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        // Every tree expands the root the same way, so edges line up between threads
+        let mut roots = roots.into_iter();
+        let mut choices = roots.next().unwrap_or_default();
+        for root in roots {
+            for (choice, other) in choices.iter_mut().zip(root) {
+                choice.visits += other.visits;
+                choice.value += other.value;
+            }
+        }
+        for choice in &mut choices {
+            choice.value = if choice.visits > 0 {
+                choice.value / choice.visits as f32
+            } else {
+                DEAD
+            };
+        }
+        choices.sort_by(|a, b| b.visits.cmp(&a.visits).then(b.value.total_cmp(&a.value)));
+        choices
+    }
+
+    /// The most visited plan, or None if every option tops out.
+    pub fn best(&self, state: &BotState, seed: u64) -> Option<Plan> {
+        self.search(state, seed).into_iter().next().map(|c| c.plan)
+    }
+}
+
+/// A bot player searching with MCTS, at a difficulty's pace. Its mistakes are one of the
+/// next most visited plans; the difficulty's depth doesn't apply.
+#[derive(Debug, Clone)]
+pub struct MctsBot<E = Heuristic> {
+    pub difficulty: Difficulty,
+    pub mcts: Mcts<E>,
+    rng: Pcg32,
+}
+
+impl<E> MctsBot<E> {
+    /// Searches on `threads` threads; a server running several bots shares its cores
+    /// out between them.
+    pub fn new(difficulty: Difficulty, evaluator: E, threads: usize, seed: u64) -> Self {
+        // As for our other bots, thinking takes most of the time there is per piece
+        let per_piece = Duration::from_secs_f32(0.8 / difficulty.pps);
+        MctsBot {
+            difficulty,
+            mcts: Mcts {
+                think_time: per_piece.min(Duration::from_millis(200)),
+                threads: threads.max(1),
+                ..Mcts::default()
+            }
+            .with_evaluator(evaluator),
+            rng: Pcg32::seed_from_u64(seed),
+        }
+    }
+}
+
+impl<E: Evaluator + Send> Brain for MctsBot<E> {
+    fn frames_per_piece(&self) -> u64 {
+        (FRAMES_PER_SECOND as f32 / self.difficulty.pps).ceil() as u64
+    }
+
+    fn think(&mut self, state: &BotState) -> Option<Plan> {
+        let mut choices = self.mcts.search(state, self.rng.gen());
+        choices.truncate(MISTAKE_CHOICES);
+        if choices.is_empty() {
+            return None;
+        }
+        let i = match self.rng.gen::<f32>() < self.difficulty.mistake_rate {
+            true => self.rng.gen_range(0..choices.len()),
+            false => 0,
+        };
+        Some(choices.swap_remove(i).plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::Rules;
+    use crate::simulation::Simulation;
+
+    #[test]
+    fn threaded_search_is_repeatable() {
+        let mut simulation = Simulation::new(7, Rules::default());
+        simulation.add_player(1);
+        let state = BotState::from_player(simulation.player(1).unwrap(), &simulation.rules);
+        let mcts = Mcts {
+            // Only the iteration limit may end it
+            think_time: Duration::from_secs(3600),
+            iterations: Some(20),
+            threads: 3,
+            ..Mcts::default()
+        };
+        let rated = |seed| {
+            mcts.search(&state, seed)
+                .into_iter()
+                .map(|c| (c.plan, c.visits, c.value.to_bits()))
+                .collect::<Vec<_>>()
+        };
+        let first = rated(3);
+        assert!(first.iter().map(|c| c.1).sum::<u32>() > 20);
+        assert_eq!(rated(3), first);
+        assert_ne!(rated(4), first);
+    }
+}
@@ -0,0 +1,939 @@
+use crate::board::{Board, BOARD_HEIGHT, BOARD_WIDTH, HIDDEN_ROWS};
+use crate::dirs;
+use crate::finesse::Tally;
+use crate::puzzle::Goal;
+use crate::rules::{self, LockResult};
+use crate::simulation::{PlayerState, Rules, FRAMES_PER_SECOND};
+use crate::tetromino::Color;
+use rand::Rng;
+#[cfg(feature = "save")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Solo game types played against the clock rather than an opponent. A mode says when a
+// player's run is over and what the run is judged by; everything is counted in frames,
+// so times are exact no matter how the game was paced.
+
+// Marathon speeds up every 10 lines, up to this level
+const MAX_LEVEL: usize = 15;
+// A cheese race leaves a couple of rows free for the pieces to come in
+const MAX_CHEESE: usize = BOARD_HEIGHT - HIDDEN_ROWS - 2;
+// Survival's first line rises after 5 seconds, and each one comes sooner, down to a
+// line every half second
+const FIRST_RISE: u64 = 300;
+const RISE_SPEEDUP: u64 = 10;
+const FASTEST_RISE: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub enum GameMode {
+    /// The usual match: last one standing wins.
+    Versus,
+    /// Clear `lines` as fast as possible.
+    Sprint { lines: usize },
+    /// Score as many points as possible in `seconds`.
+    Ultra { seconds: u64 },
+    /// Clear `lines` while gravity speeds up level by level, scoring as you go.
+    Marathon { lines: usize },
+    /// Dig through `lines` of messy garbage as fast as possible.
+    Cheese { lines: usize },
+    /// Last as long as possible while garbage rises faster and faster.
+    Survival,
+    /// Meet a puzzle's goal, within `limit` pieces if there is one. The field and
+    /// pieces come from the `Puzzle` itself.
+    Puzzle { goal: Goal, limit: Option<usize> },
+}
+
+impl GameMode {
+    /// "versus", "sprint[:LINES]" (40 lines), "ultra[:SECONDS]" (2 minutes),
+    /// "marathon[:LINES]" (150 lines), "cheese[:LINES]" (10 lines) or "survival".
+    /// Puzzles are "puzzle:GOAL[:LIMIT]", though without their field.
+    pub fn parse(text: &str) -> Option<GameMode> {
+        let (name, arg) = match text.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (text, None),
+        };
+        if name == "puzzle" {
+            let (goal, limit) = match arg?.split_once(':') {
+                Some((goal, limit)) => (goal, Some(limit.parse().ok().filter(|n| *n > 0)?)),
+                None => (arg?, None),
+            };
+            let goal = Goal::parse(goal)?;
+            return Some(GameMode::Puzzle { goal, limit });
+        }
+        let number = match arg {
+            Some(arg) => match arg.parse::<u64>() {
+                Ok(n) if n > 0 => Some(n),
+                _ => return None,
+            },
+            None => None,
+        };
+        match name {
+            "versus" if number.is_none() => Some(GameMode::Versus),
+            "sprint" => Some(GameMode::Sprint {
+                lines: number.unwrap_or(40) as usize,
+            }),
+            "ultra" => Some(GameMode::Ultra {
+                seconds: number.unwrap_or(120),
+            }),
+            "marathon" => Some(GameMode::Marathon {
+                lines: number.unwrap_or(150) as usize,
+            }),
+            "cheese" => match number.unwrap_or(10) as usize {
+                lines if lines <= MAX_CHEESE => Some(GameMode::Cheese { lines }),
+                _ => None,
+            },
+            "survival" if number.is_none() => Some(GameMode::Survival),
+            _ => None,
+        }
+    }
+
+    /// The form `parse` reads back, also used on the wire and as the personal-best key.
+    pub fn encode(&self) -> String {
+        match self {
+            GameMode::Versus => String::from("versus"),
+            GameMode::Sprint { lines } => format!("sprint:{}", lines),
+            GameMode::Ultra { seconds } => format!("ultra:{}", seconds),
+            GameMode::Marathon { lines } => format!("marathon:{}", lines),
+            GameMode::Cheese { lines } => format!("cheese:{}", lines),
+            GameMode::Survival => String::from("survival"),
+            GameMode::Puzzle { goal, limit } => match limit {
+                Some(limit) => format!("puzzle:{}:{}", goal.encode(), limit),
+                None => format!("puzzle:{}", goal.encode()),
+            },
+        }
+    }
+
+    /// What to call it on screen.
+    pub fn title(&self) -> String {
+        match self {
+            GameMode::Versus => String::from("Versus"),
+            GameMode::Sprint { lines } => format!("{}L Sprint", lines),
+            GameMode::Ultra { seconds } => {
+                format!("{}:{:02} Ultra", seconds / 60, seconds % 60)
+            }
+            GameMode::Marathon { lines } => format!("{}L Marathon", lines),
+            GameMode::Cheese { lines } => format!("{}L Cheese Race", lines),
+            GameMode::Survival => String::from("Survival"),
+            GameMode::Puzzle { goal, limit } => match limit {
+                Some(1) => format!("{} with 1 piece", goal.describe()),
+                Some(limit) => format!("{} in {} pieces", goal.describe(), limit),
+                None => goal.describe(),
+            },
+        }
+    }
+
+    pub fn rules(&self) -> Rules {
+        let mut rules = Rules::default();
+        if let Some(gravity) = self.gravity(1) {
+            rules.gravity = gravity;
+        }
+        rules
+    }
+
+    /// Whether runs are tracked at all; versus matches just end when one player is left.
+    pub fn is_solo(&self) -> bool {
+        *self != GameMode::Versus
+    }
+
+    /// Whether runs earn guideline points.
+    pub fn keeps_score(&self) -> bool {
+        matches!(self, GameMode::Ultra { .. } | GameMode::Marathon { .. })
+    }
+
+    /// The level a run is on after clearing `lines`.
+    pub fn level(&self, lines: usize) -> usize {
+        match self {
+            GameMode::Marathon { .. } => (1 + lines / 10).min(MAX_LEVEL),
+            _ => 1,
+        }
+    }
+
+    /// Frames per row of gravity at `level`, for modes that speed up.
+    pub fn gravity(&self, level: usize) -> Option<u32> {
+        match self {
+            GameMode::Marathon { .. } => {
+                // The guideline curve, in seconds per row
+                let n = (level - 1) as f64;
+                let seconds = (0.8 - n * 0.007).powf(n);
+                Some(((seconds * FRAMES_PER_SECOND as f64).round() as u32).max(1))
+            }
+            _ => None,
+        }
+    }
+
+    /// The number a run is ranked by, if it counts for a personal best.
+    pub fn record(&self, summary: &Summary) -> Option<u64> {
+        match self {
+            GameMode::Versus => None,
+            GameMode::Sprint { .. } => summary.completed.then_some(summary.frames),
+            GameMode::Ultra { .. } => summary.completed.then_some(summary.score),
+            GameMode::Cheese { .. } => summary.completed.then_some(summary.frames),
+            // However far a marathon got, its score stands
+            GameMode::Marathon { .. } => Some(summary.score),
+            GameMode::Survival => Some(summary.frames),
+            // Puzzles are only solved or not
+            GameMode::Puzzle { .. } => None,
+        }
+    }
+
+    /// A record as it should be shown.
+    pub fn format_record(&self, record: u64) -> String {
+        match self {
+            GameMode::Versus
+            | GameMode::Sprint { .. }
+            | GameMode::Cheese { .. }
+            | GameMode::Survival
+            | GameMode::Puzzle { .. } => format_time(record),
+            GameMode::Ultra { .. } | GameMode::Marathon { .. } => record.to_string(),
+        }
+    }
+
+    /// Whether `a` is a better record than `b`.
+    pub fn beats(&self, a: u64, b: u64) -> bool {
+        match self {
+            GameMode::Versus
+            | GameMode::Sprint { .. }
+            | GameMode::Cheese { .. }
+            | GameMode::Puzzle { .. } => a < b,
+            GameMode::Ultra { .. } | GameMode::Marathon { .. } | GameMode::Survival => a > b,
+        }
+    }
+
+    /// Garbage holes to fill the board with before the first piece, bottom line last.
+    pub fn setup(&self, rng: &mut impl Rng) -> Vec<usize> {
+        match self {
+            GameMode::Cheese { lines } => messy_holes(rng, *lines),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Frames until the next garbage line rises, after `risen` so far.
+    pub fn rise_interval(&self, risen: usize) -> Option<u64> {
+        match self {
+            GameMode::Survival => Some(
+                FIRST_RISE
+                    .saturating_sub(RISE_SPEEDUP * risen as u64)
+                    .max(FASTEST_RISE),
+            ),
+            _ => None,
+        }
+    }
+
+    // Whether a run has reached the goal
+    fn completed(&self, frames: u64, state: &PlayerState) -> bool {
+        match *self {
+            GameMode::Versus | GameMode::Survival => false,
+            GameMode::Sprint { lines } | GameMode::Marathon { lines } => state.lines >= lines,
+            GameMode::Ultra { seconds } => frames >= seconds * FRAMES_PER_SECOND as u64,
+            GameMode::Cheese { .. } => garbage_rows(&state.board) == 0,
+            GameMode::Puzzle { goal, .. } => match goal {
+                Goal::Lines(lines) => state.lines >= lines,
+                _ => false,
+            },
+        }
+    }
+
+    // Whether a run is out of pieces
+    fn out_of_pieces(&self, state: &PlayerState) -> bool {
+        match self {
+            GameMode::Puzzle {
+                limit: Some(limit), ..
+            } => state.pieces >= *limit,
+            _ => false,
+        }
+    }
+}
+
+// Holes for garbage lines where no hole lines up with the one below it
+fn messy_holes(rng: &mut impl Rng, lines: usize) -> Vec<usize> {
+    let mut holes: Vec<usize> = Vec::with_capacity(lines);
+    for _ in 0..lines {
+        let hole = match holes.last() {
+            Some(below) => (below + rng.gen_range(1..BOARD_WIDTH)) % BOARD_WIDTH,
+            None => rng.gen_range(0..BOARD_WIDTH),
+        };
+        holes.push(hole);
+    }
+    holes
+}
+
+/// Rows with any garbage left in them.
+pub fn garbage_rows(board: &Board) -> usize {
+    (0..BOARD_HEIGHT)
+        .filter(|y| (0..BOARD_WIDTH).any(|x| board.cell(x, *y) == Some(Color::Gray)))
+        .count()
+}
+
+/// How a run went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Summary {
+    pub mode: GameMode,
+    /// Whether the goal was reached, rather than the player topping out.
+    pub completed: bool,
+    pub frames: u64,
+    pub pieces: usize,
+    pub lines: usize,
+    /// Key presses; held keys repeating don't count again.
+    pub keys: usize,
+    pub attack: usize,
+    /// Guideline points, in modes that keep score.
+    pub score: u64,
+    /// Key presses beyond the fewest each piece needed.
+    pub faults: usize,
+}
+
+impl Summary {
+    /// Pieces per second.
+    pub fn pps(&self) -> f64 {
+        if self.frames == 0 {
+            return 0.0;
+        }
+        self.pieces as f64 * FRAMES_PER_SECOND as f64 / self.frames as f64
+    }
+
+    /// Keys per piece.
+    pub fn kpp(&self) -> f64 {
+        if self.pieces == 0 {
+            return 0.0;
+        }
+        self.keys as f64 / self.pieces as f64
+    }
+
+    /// The level reached, in modes that have levels.
+    pub fn level(&self) -> usize {
+        self.mode.level(self.lines)
+    }
+
+    /// "<mode> <completed> <frames> <pieces> <lines> <keys> <attack> <score> <faults>"
+    pub fn encode(&self) -> String {
+        format!(
+            "{} {} {} {} {} {} {} {} {}",
+            self.mode.encode(),
+            self.completed as u8,
+            self.frames,
+            self.pieces,
+            self.lines,
+            self.keys,
+            self.attack,
+            self.score,
+            self.faults
+        )
+    }
+
+    pub fn decode(text: &str) -> Option<Summary> {
+        let fields: Vec<&str> = text.split(' ').collect();
+        if fields.len() != 9 {
+            return None;
+        }
+        Some(Summary {
+            mode: GameMode::parse(fields[0])?,
+            completed: match fields[1] {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            },
+            frames: fields[2].parse().ok()?,
+            pieces: fields[3].parse().ok()?,
+            lines: fields[4].parse().ok()?,
+            keys: fields[5].parse().ok()?,
+            attack: fields[6].parse().ok()?,
+            score: fields[7].parse().ok()?,
+            faults: fields[8].parse().ok()?,
+        })
+    }
+
+    /// One line for the end screen, e.g. "40L Sprint: 1:02.350, 1.63 PPS, 3.10 KPP,
+    /// 4 finesse faults".
+    pub fn describe(&self) -> String {
+        let mut result = match self.mode {
+            GameMode::Sprint { .. } if self.completed => format_time(self.frames),
+            GameMode::Sprint { .. } => format!("topped out at {} lines", self.lines),
+            GameMode::Ultra { .. } => format!("{} points", self.score),
+            GameMode::Marathon { .. } => format!(
+                "{} points, level {}, {} lines",
+                self.score,
+                self.level(),
+                self.lines
+            ),
+            GameMode::Cheese { .. } if self.completed => format_time(self.frames),
+            GameMode::Cheese { .. } => String::from("topped out"),
+            GameMode::Puzzle { .. } if self.completed => {
+                format!("solved in {}", format_time(self.frames))
+            }
+            GameMode::Puzzle { .. } => String::from("failed"),
+            GameMode::Survival => format!(
+                "survived {} and cleared {} lines",
+                format_time(self.frames),
+                self.lines
+            ),
+            GameMode::Versus => format!("{} lines", self.lines),
+        };
+        if self.mode.keeps_score() && !self.completed {
+            result.push_str(" (topped out)");
+        }
+        format!(
+            "{}: {}, {:.2} PPS, {:.2} KPP, {} finesse fault{}",
+            self.mode.title(),
+            result,
+            self.pps(),
+            self.kpp(),
+            self.faults,
+            if self.faults == 1 { "" } else { "s" }
+        )
+    }
+}
+
+/// One match a player played, for their history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Match {
+    /// Seconds since the Unix epoch when it ended.
+    pub at: u64,
+    /// Everyone seated, bots included.
+    pub players: usize,
+    /// How it went. In versus, `completed` means won.
+    pub summary: Summary,
+}
+
+impl Match {
+    pub fn new(players: usize, summary: Summary) -> Match {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Match {
+            at,
+            players,
+            summary,
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        format!("{} {} {}", self.at, self.players, self.summary.encode())
+    }
+
+    pub fn decode(text: &str) -> Option<Match> {
+        let mut parts = text.splitn(3, ' ');
+        Some(Match {
+            at: parts.next()?.parse().ok()?,
+            players: parts.next()?.parse().ok()?,
+            summary: Summary::decode(parts.next()?)?,
+        })
+    }
+
+    /// One line for a history, e.g. "Versus with 4 players: won, 1.52 PPS, 40 lines, 18 attack".
+    pub fn describe(&self) -> String {
+        let summary = &self.summary;
+        match summary.mode {
+            GameMode::Versus => format!(
+                "Versus with {} players: {}, {:.2} PPS, {} lines, {} attack",
+                self.players,
+                if summary.completed { "won" } else { "lost" },
+                summary.pps(),
+                summary.lines,
+                summary.attack
+            ),
+            _ => summary.describe(),
+        }
+    }
+}
+
+/// Totals over every match a player played.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Stats {
+    pub played: usize,
+    /// Versus matches won and solo goals reached.
+    pub won: usize,
+    pub frames: u64,
+    pub pieces: usize,
+    pub lines: usize,
+    pub attack: usize,
+}
+
+impl Stats {
+    pub fn add(&mut self, summary: &Summary) {
+        self.played += 1;
+        self.won += summary.completed as usize;
+        self.frames += summary.frames;
+        self.pieces += summary.pieces;
+        self.lines += summary.lines;
+        self.attack += summary.attack;
+    }
+
+    pub fn encode(&self) -> String {
+        format!(
+            "{} {} {} {} {} {}",
+            self.played, self.won, self.frames, self.pieces, self.lines, self.attack
+        )
+    }
+
+    pub fn decode(text: &str) -> Option<Stats> {
+        let fields: Vec<&str> = text.split(' ').collect();
+        if fields.len() != 6 {
+            return None;
+        }
+        Some(Stats {
+            played: fields[0].parse().ok()?,
+            won: fields[1].parse().ok()?,
+            frames: fields[2].parse().ok()?,
+            pieces: fields[3].parse().ok()?,
+            lines: fields[4].parse().ok()?,
+            attack: fields[5].parse().ok()?,
+        })
+    }
+
+    /// e.g. "12 played, 5 won, 1.61 PPS, 480 lines, 150 attack".
+    pub fn describe(&self) -> String {
+        let pps = match self.frames {
+            0 => 0.0,
+            frames => self.pieces as f64 * FRAMES_PER_SECOND as f64 / frames as f64,
+        };
+        format!(
+            "{} played, {} won, {:.2} PPS, {} lines, {} attack",
+            self.played, self.won, pps, self.lines, self.attack
+        )
+    }
+}
+
+/// Frames as "m:ss.mmm".
+pub fn format_time(frames: u64) -> String {
+    let millis = frames * 1000 / FRAMES_PER_SECOND as u64;
+    format!(
+        "{}:{:02}.{:03}",
+        millis / 60_000,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// One player's progress through a solo mode.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Run {
+    pub mode: GameMode,
+    /// The simulation frame the run started on.
+    pub start: u64,
+    pub keys: usize,
+    /// Points from clears so far; drops are added from the player's counts.
+    pub points: u64,
+    /// Garbage lines risen so far, and the frame the next one is due.
+    pub risen: usize,
+    pub next_rise: Option<u64>,
+    /// Whether a lock has met a puzzle's goal.
+    pub solved: bool,
+    /// Keys pressed on each piece, judged as they lock.
+    pub finesse: Tally,
+    /// Set on the frame the run ends.
+    pub result: Option<Summary>,
+}
+
+impl Run {
+    pub fn new(mode: GameMode, start: u64) -> Run {
+        Run {
+            mode,
+            start,
+            keys: 0,
+            points: 0,
+            risen: 0,
+            next_rise: mode.rise_interval(0).map(|frames| start + frames),
+            solved: false,
+            finesse: Tally::default(),
+            result: None,
+        }
+    }
+
+    /// Whether a garbage line should rise on `frame`, scheduling the next if so.
+    pub fn rise_due(&mut self, frame: u64) -> bool {
+        match self.next_rise {
+            Some(due) if self.result.is_none() && frame >= due => {
+                self.risen += 1;
+                self.next_rise = self.mode.rise_interval(self.risen).map(|f| frame + f);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn key(&mut self) {
+        self.keys += 1;
+    }
+
+    /// How the run stands at `frame`, finished or not.
+    pub fn summary(&self, frame: u64, state: &PlayerState) -> Summary {
+        if let Some(result) = self.result {
+            return result;
+        }
+        let frames = frame - self.start;
+        let score = if self.mode.keeps_score() {
+            self.points + state.soft_dropped as u64 + 2 * state.hard_dropped as u64
+        } else {
+            0
+        };
+        Summary {
+            mode: self.mode,
+            completed: self.solved || self.mode.completed(frames, state),
+            frames,
+            pieces: state.pieces,
+            lines: state.lines,
+            keys: self.keys,
+            attack: state.attack,
+            score,
+            faults: self.finesse.faults(),
+        }
+    }
+
+    /// Checks the run after a simulation step that locked `locks` for this player,
+    /// returning the result on the frame it ends.
+    pub fn update(
+        &mut self,
+        frame: u64,
+        state: &PlayerState,
+        locks: &[LockResult],
+    ) -> Option<Summary> {
+        if self.result.is_some() || !self.mode.is_solo() {
+            return None;
+        }
+        // Clears score at the level they were made on
+        let mut lines = state.lines - locks.iter().map(|r| r.lines).sum::<usize>();
+        for result in locks {
+            self.points += rules::points(result) * self.mode.level(lines) as u64;
+            lines += result.lines;
+            if let GameMode::Puzzle { goal, .. } = self.mode {
+                self.solved |= goal.met_by(result);
+            }
+        }
+        let summary = self.summary(frame, state);
+        if summary.completed || !state.alive || self.mode.out_of_pieces(state) {
+            self.result = Some(summary);
+        }
+        self.result
+    }
+}
+
+/// The best result for each mode played, kept as `mode = record` lines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PersonalBests {
+    pub records: BTreeMap<String, u64>,
+}
+
+impl PersonalBests {
+    pub fn get(&self, mode: GameMode) -> Option<u64> {
+        self.records.get(&mode.encode()).copied()
+    }
+
+    /// Keeps `summary` if it beats the record for its mode, returning whether it did.
+    pub fn submit(&mut self, summary: &Summary) -> bool {
+        let Some(record) = summary.mode.record(summary) else {
+            return false;
+        };
+        match self.get(summary.mode) {
+            Some(best) if !summary.mode.beats(record, best) => false,
+            _ => {
+                self.records.insert(summary.mode.encode(), record);
+                true
+            }
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<PersonalBests, String> {
+        let mut bests = PersonalBests::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", n + 1, msg);
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(err(format!("expected `mode = record`, got `{}`", line)));
+            };
+            let (name, value) = (name.trim(), value.trim());
+            let mode = GameMode::parse(name)
+                .filter(GameMode::is_solo)
+                .ok_or_else(|| err(format!("unknown mode `{}`", name)))?;
+            let record = value
+                .parse()
+                .map_err(|_| err(format!("`{}` is not a number", value)))?;
+            bests.records.insert(mode.encode(), record);
+        }
+        Ok(bests)
+    }
+
+    pub fn encode(&self) -> String {
+        self.records
+            .iter()
+            .map(|(mode, record)| format!("{} = {}\n", mode, record))
+            .collect()
+    }
+
+    /// `$XDG_CONFIG_HOME/tetriszero/records.conf`, falling back to `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(dirs::config_dir()?.join("records.conf"))
+    }
+
+    /// Reads records from `path`. A missing file just means none yet.
+    pub fn load(path: &Path) -> io::Result<PersonalBests> {
+        match fs::read_to_string(path) {
+            Ok(text) => PersonalBests::parse(&text).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: {}", path.display(), e),
+                )
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PersonalBests::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, self.encode())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::Session;
+    use crate::simulation::Simulation;
+    use rand::SeedableRng;
+
+    fn player() -> PlayerState {
+        let mut simulation = Simulation::new(0, Rules::default());
+        simulation.add_player(1);
+        simulation.player(1).unwrap().clone()
+    }
+
+    fn clear(lines: usize) -> LockResult {
+        LockResult {
+            lines,
+            ..LockResult::default()
+        }
+    }
+
+    #[test]
+    fn modes_round_trip() {
+        for text in [
+            "versus",
+            "sprint:20",
+            "ultra:180",
+            "marathon:150",
+            "cheese:18",
+            "survival",
+            "puzzle:tspin-2",
+            "puzzle:lines-4:10",
+            "puzzle:perfect-clear:1",
+        ] {
+            let mode = GameMode::parse(text).unwrap();
+            assert_eq!(mode.encode(), text);
+            assert_eq!(GameMode::parse(&mode.encode()), Some(mode));
+        }
+        assert_eq!(
+            GameMode::parse("sprint"),
+            Some(GameMode::Sprint { lines: 40 })
+        );
+        assert_eq!(
+            GameMode::parse("ultra"),
+            Some(GameMode::Ultra { seconds: 120 })
+        );
+        for text in [
+            "",
+            "sprint:0",
+            "sprint:-1",
+            "sprint:forty",
+            "versus:2",
+            "survival:1",
+            "cheese:19",
+            "puzzle",
+            "puzzle:tspin-2:0",
+            "zen",
+        ] {
+            assert_eq!(GameMode::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn summaries_round_trip() {
+        let summary = Summary {
+            mode: GameMode::Sprint { lines: 40 },
+            completed: true,
+            frames: 3723,
+            pieces: 101,
+            lines: 40,
+            keys: 290,
+            attack: 12,
+            score: 0,
+            faults: 7,
+        };
+        assert_eq!(summary.encode(), "sprint:40 1 3723 101 40 290 12 0 7");
+        assert_eq!(Summary::decode(&summary.encode()), Some(summary));
+        for text in [
+            "sprint:40 1 3723 101 40 290 12 0",
+            "sprint:40 1 3723 101 40 290 12 0 7 1",
+            "sprint:40 2 3723 101 40 290 12 0 7",
+            "sprint:40 1 -1 101 40 290 12 0 7",
+            "sprint:0 1 3723 101 40 290 12 0 7",
+        ] {
+            assert_eq!(Summary::decode(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn sprints_end_on_their_lines() {
+        let mut run = Run::new(GameMode::Sprint { lines: 40 }, 100);
+        let mut state = player();
+        state.lines = 38;
+        state.pieces = 95;
+        assert_eq!(run.update(1000, &state, &[clear(2)]), None);
+        run.key();
+        state.lines = 41;
+        state.pieces = 96;
+        let result = run.update(1100, &state, &[clear(3)]).unwrap();
+        assert!(result.completed);
+        assert_eq!(result.frames, 1000);
+        assert_eq!((result.pieces, result.lines, result.keys), (96, 41, 1));
+        // Over is over: nothing after changes it
+        assert_eq!(run.update(1200, &state, &[clear(4)]), None);
+        assert_eq!(run.summary(1200, &state), result);
+    }
+
+    #[test]
+    fn topping_out_ends_a_run_unfinished() {
+        let mut run = Run::new(GameMode::Sprint { lines: 40 }, 0);
+        let mut state = player();
+        state.lines = 12;
+        state.alive = false;
+        let result = run.update(600, &state, &[]).unwrap();
+        assert!(!result.completed);
+        assert_eq!(GameMode::Sprint { lines: 40 }.record(&result), None);
+
+        // Versus isn't a run at all
+        let mut run = Run::new(GameMode::Versus, 0);
+        assert_eq!(run.update(600, &state, &[]), None);
+    }
+
+    #[test]
+    fn marathon_follows_the_guideline_curve() {
+        let marathon = GameMode::Marathon { lines: 150 };
+        assert_eq!(marathon.level(0), 1);
+        assert_eq!(marathon.level(9), 1);
+        assert_eq!(marathon.level(10), 2);
+        assert_eq!(marathon.level(1000), MAX_LEVEL);
+        let curve: Vec<u32> = (1..=MAX_LEVEL)
+            .map(|level| marathon.gravity(level).unwrap())
+            .collect();
+        assert_eq!(curve, [60, 48, 37, 28, 21, 16, 11, 8, 6, 4, 3, 2, 1, 1, 1]);
+        assert_eq!(marathon.rules().gravity, 60);
+        assert_eq!(GameMode::Ultra { seconds: 120 }.gravity(1), None);
+        assert_eq!(GameMode::Sprint { lines: 40 }.level(100), 1);
+    }
+
+    #[test]
+    fn marathon_scores_clears_at_their_level() {
+        let mut run = Run::new(GameMode::Marathon { lines: 150 }, 0);
+        let mut state = player();
+        // A tetris from 8 lines is made on level 1 and takes the run to level 2
+        state.lines = 12;
+        state.soft_dropped = 10;
+        state.hard_dropped = 20;
+        run.update(600, &state, &[clear(4)]);
+        // Then two singles, both on level 2
+        state.lines = 14;
+        let summary = run.update(700, &state, &[clear(1), clear(1)]);
+        assert_eq!(summary, None);
+        assert_eq!(run.points, 800 + 2 * 200);
+        let summary = run.summary(700, &state);
+        assert_eq!(summary.score, 800 + 2 * 200 + 10 + 2 * 20);
+        assert_eq!(summary.level(), 2);
+        // However it ends, the score is the record
+        state.alive = false;
+        let result = run.update(800, &state, &[]).unwrap();
+        assert!(!result.completed);
+        assert_eq!(result.mode.record(&result), Some(result.score));
+    }
+
+    #[test]
+    fn ultra_ends_on_time() {
+        let mut run = Run::new(GameMode::Ultra { seconds: 120 }, 50);
+        let state = player();
+        assert_eq!(run.update(50 + 120 * 60 - 1, &state, &[]), None);
+        let result = run.update(50 + 120 * 60, &state, &[]).unwrap();
+        assert!(result.completed);
+        assert_eq!(result.frames, 7200);
+    }
+
+    #[test]
+    fn cheese_holes_never_line_up() {
+        let cheese = GameMode::Cheese { lines: MAX_CHEESE };
+        let mut rng = rand_pcg::Pcg32::seed_from_u64(5);
+        for _ in 0..20 {
+            let holes = cheese.setup(&mut rng);
+            assert_eq!(holes.len(), MAX_CHEESE);
+            assert!(holes.iter().all(|hole| *hole < BOARD_WIDTH));
+            assert!(holes.windows(2).all(|pair| pair[0] != pair[1]));
+        }
+        assert!(GameMode::Sprint { lines: 40 }.setup(&mut rng).is_empty());
+    }
+
+    #[test]
+    fn cheese_ends_with_the_last_garbage() {
+        let mut run = Run::new(GameMode::Cheese { lines: 2 }, 0);
+        let mut state = player();
+        state.board.add_lines(1, 3);
+        assert_eq!(garbage_rows(&state.board), 1);
+        assert_eq!(run.update(100, &state, &[]), None);
+        // What the player stacked doesn't count, only garbage
+        state.board = Board::new();
+        state.board.set_cell(0, BOARD_HEIGHT - 1, Some(Color::Red));
+        state.lines = 1;
+        let result = run.update(200, &state, &[clear(1)]).unwrap();
+        assert!(result.completed);
+        assert_eq!(result.mode.record(&result), Some(200));
+    }
+
+    #[test]
+    fn survival_rises_faster_and_faster() {
+        let survival = GameMode::Survival;
+        assert_eq!(survival.rise_interval(0), Some(FIRST_RISE));
+        assert_eq!(survival.rise_interval(1), Some(FIRST_RISE - RISE_SPEEDUP));
+        assert_eq!(survival.rise_interval(1000), Some(FASTEST_RISE));
+        assert_eq!(GameMode::Cheese { lines: 10 }.rise_interval(0), None);
+
+        let mut run = Run::new(survival, 100);
+        assert!(!run.rise_due(399));
+        assert!(run.rise_due(400));
+        assert!(!run.rise_due(400));
+        assert_eq!(run.next_rise, Some(400 + FIRST_RISE - RISE_SPEEDUP));
+        // A finished run stops rising
+        let mut state = player();
+        state.alive = false;
+        let result = run.update(500, &state, &[]).unwrap();
+        assert!(!result.completed);
+        assert_eq!(result.mode.record(&result), Some(400));
+        assert!(!run.rise_due(10_000));
+    }
+
+    #[test]
+    fn survival_garbage_comes_up_in_play() {
+        let mut session = Session::new(1, GameMode::Survival);
+        session.add_player(1);
+        let rows = |session: &Session| garbage_rows(&session.simulation.player(1).unwrap().board);
+        for _ in 0..FIRST_RISE - 1 {
+            session.step();
+        }
+        assert_eq!(rows(&session), 0);
+        session.step();
+        assert_eq!(rows(&session), 1);
+        for _ in 0..FIRST_RISE - RISE_SPEEDUP {
+            session.step();
+        }
+        assert_eq!(rows(&session), 2);
+    }
+}
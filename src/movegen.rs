@@ -0,0 +1,289 @@
+use crate::board::{Board, Point};
+use crate::rules::{self, TSpin};
+use crate::simulation::Input;
+use crate::tetromino::{Direction, Kind, Tetromino};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Everything that can be pressed while the piece is in play, cheapest-looking first
+// so that ties between equally short paths go to the simpler one.
+const INPUTS: [Input; 9] = [
+    Input::Left,
+    Input::Right,
+    Input::DasLeft,
+    Input::DasRight,
+    Input::RotateCW,
+    Input::RotateCCW,
+    Input::Rotate180,
+    Input::SonicDrop,
+    Input::SoftDrop,
+];
+
+/// Where a piece comes to rest, and how the last action left it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Placement {
+    pub kind: Kind,
+    pub rotation: u8,
+    pub position: Point,
+    /// Kick index of the rotation that put the piece here, if nothing moved it since.
+    /// Only tracked for T pieces, the only ones it matters for.
+    pub last_kick: Option<usize>,
+    pub tspin: TSpin,
+}
+
+impl Placement {
+    /// The piece, turned to its final rotation.
+    pub fn tetromino(&self) -> Tetromino {
+        let mut tetromino = Tetromino::new(self.kind);
+        for _ in 0..self.rotation {
+            tetromino.rotate(Direction::CW);
+        }
+        tetromino
+    }
+
+    /// The four board cells the piece covers, top to bottom, left to right.
+    pub fn cells(&self) -> [Point; 4] {
+        let mut cells = [Point::default(); 4];
+        let mut n = 0;
+        self.tetromino().each_point(&mut |row, col| {
+            cells[n] = Point {
+                x: self.position.x + col,
+                y: self.position.y + row,
+            };
+            n += 1;
+        });
+        cells
+    }
+}
+
+/// `cells` top to bottom, left to right, the order `Placement::cells` gives them in, so
+/// pieces can be compared by the cells they cover whatever their rotation.
+pub fn sorted_cells(mut cells: [Point; 4]) -> [Point; 4] {
+    cells.sort_by_key(|p| (p.y, p.x));
+    cells
+}
+
+/// A reachable placement and the shortest inputs that get there, ending in a hard drop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Move {
+    pub placement: Placement,
+    pub inputs: Vec<Input>,
+}
+
+// A piece in play. Kicks only matter for T pieces, so other pieces always have None.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct Node {
+    position: Point,
+    rotation: u8,
+    last_kick: Option<usize>,
+}
+
+// The piece in each rotation, with its cells and kicks worked out up front
+struct Shapes {
+    kind: Kind,
+    tetrominoes: Vec<Tetromino>,
+    cells: Vec<Vec<(i32, i32)>>,
+    // Indexed by [rotation][CW, CCW, 180]
+    kicks: Vec<[Vec<(i32, i32)>; 3]>,
+}
+
+impl Shapes {
+    fn new(kind: Kind) -> Self {
+        let mut tetrominoes = vec![Tetromino::new(kind)];
+        for _ in 1..4 {
+            let mut next = tetrominoes.last().unwrap().clone();
+            next.rotate(Direction::CW);
+            tetrominoes.push(next);
+        }
+        let cells = tetrominoes
+            .iter()
+            .map(|t| {
+                let mut cells = Vec::new();
+                t.each_point(&mut |row, col| cells.push((col, row)));
+                cells
+            })
+            .collect();
+        let kicks = (0..4)
+            .map(|r| {
+                [Direction::CW, Direction::CCW, Direction::Half].map(|d| rules::kicks(kind, r, d))
+            })
+            .collect();
+        Shapes {
+            kind,
+            tetrominoes,
+            cells,
+            kicks,
+        }
+    }
+
+    fn fits(&self, board: &Board, rotation: u8, position: Point) -> bool {
+        self.cells[rotation as usize]
+            .iter()
+            .all(|(x, y)| !board.occupied(position.x + x, position.y + y))
+    }
+
+    fn shift(&self, board: &Board, node: Node, x: i32, y: i32) -> Option<Node> {
+        let position = Point {
+            x: node.position.x + x,
+            y: node.position.y + y,
+        };
+        self.fits(board, node.rotation, position).then_some(Node {
+            position,
+            rotation: node.rotation,
+            last_kick: None,
+        })
+    }
+
+    // Moves as far as possible; None if it can't move at all
+    fn slide(&self, board: &Board, node: Node, x: i32, y: i32) -> Option<Node> {
+        let mut moved = self.shift(board, node, x, y)?;
+        while let Some(next) = self.shift(board, moved, x, y) {
+            moved = next;
+        }
+        Some(moved)
+    }
+
+    fn rotate(&self, board: &Board, node: Node, direction: usize) -> Option<Node> {
+        let rotation = match direction {
+            0 => (node.rotation + 1) % 4,
+            1 => (node.rotation + 3) % 4,
+            _ => (node.rotation + 2) % 4,
+        };
+        self.kicks[node.rotation as usize][direction]
+            .iter()
+            .enumerate()
+            .find_map(|(i, (x, y))| {
+                let position = Point {
+                    x: node.position.x + x,
+                    y: node.position.y + y,
+                };
+                self.fits(board, rotation, position).then_some(Node {
+                    position,
+                    rotation,
+                    last_kick: (self.kind == Kind::T).then_some(i),
+                })
+            })
+    }
+
+    // Mirrors what the same input does in the simulation
+    fn apply(&self, board: &Board, node: Node, input: Input) -> Option<Node> {
+        match input {
+            Input::Left => self.shift(board, node, -1, 0),
+            Input::Right => self.shift(board, node, 1, 0),
+            Input::DasLeft => self.slide(board, node, -1, 0),
+            Input::DasRight => self.slide(board, node, 1, 0),
+            Input::SoftDrop => self.shift(board, node, 0, 1),
+            Input::SonicDrop => self.slide(board, node, 0, 1),
+            Input::RotateCW => self.rotate(board, node, 0),
+            Input::RotateCCW => self.rotate(board, node, 1),
+            Input::Rotate180 => self.rotate(board, node, 2),
+            _ => None,
+        }
+    }
+
+    fn placement(&self, board: &Board, node: Node) -> Placement {
+        let landed = self.slide(board, node, 0, 1).unwrap_or(node);
+        let tetromino = &self.tetrominoes[landed.rotation as usize];
+        Placement {
+            kind: self.kind,
+            rotation: landed.rotation,
+            position: landed.position,
+            last_kick: landed.last_kick,
+            tspin: rules::detect_tspin(board, tetromino, landed.position, landed.last_kick),
+        }
+    }
+}
+
+/// Every distinct place `tetromino`, currently at `position`, can be locked, with the
+/// shortest input sequence for each. Gravity and lock delay are not considered, so
+/// tucks and spins that need the piece to slide along the stack are all found.
+///
+/// Placements covering the same cells count as one (so the two vertical I rotations,
+/// say, aren't both returned) unless they differ in the kind of t-spin they score.
+pub fn moves(board: &Board, tetromino: &Tetromino, position: Point) -> Vec<Move> {
+    let shapes = Shapes::new(tetromino.kind);
+    let start = Node {
+        position,
+        rotation: tetromino.rotation,
+        last_kick: None,
+    };
+    if !shapes.fits(board, start.rotation, start.position) {
+        return Vec::new();
+    }
+
+    // Breadth first, so the first path found to anything is a shortest one
+    let mut parents: HashMap<Node, (Node, Input)> = HashMap::new();
+    let mut queue = VecDeque::from([start]);
+    let mut seen = HashSet::new();
+    let mut moves = Vec::new();
+    while let Some(node) = queue.pop_front() {
+        let placement = shapes.placement(board, node);
+        if seen.insert((sorted_cells(placement.cells()), placement.tspin)) {
+            let mut inputs = vec![Input::HardDrop];
+            let mut at = node;
+            while let Some((parent, input)) = parents.get(&at) {
+                inputs.push(*input);
+                at = *parent;
+            }
+            inputs.reverse();
+            moves.push(Move { placement, inputs });
+        }
+
+        for input in INPUTS {
+            if let Some(next) = shapes.apply(board, node, input) {
+                if next != start && !parents.contains_key(&next) {
+                    parents.insert(next, (node, input));
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::BOARD_HEIGHT;
+    use crate::simulation::spawn_position;
+    use crate::tetromino::Color;
+
+    #[test]
+    fn spins_into_a_tsd_slot() {
+        // The overhang keeps a T from dropping straight in
+        let rows = ["...G......", "GGG...GGGG", "GGGG.GGGGG"];
+        let mut board = Board::new();
+        let top = BOARD_HEIGHT - rows.len();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == 'G' {
+                    board.set_cell(x, top + y, Some(Color::Gray));
+                }
+            }
+        }
+        let tetromino = Tetromino::new(Kind::T);
+        let moves = moves(&board, &tetromino, spawn_position(&tetromino));
+        let y = top as i32 + 1;
+        let slot = sorted_cells([
+            Point { x: 3, y },
+            Point { x: 4, y },
+            Point { x: 5, y },
+            Point { x: 4, y: y + 1 },
+        ]);
+        let tsd = moves
+            .iter()
+            .find(|m| sorted_cells(m.placement.cells()) == slot)
+            .expect("the slot is reachable");
+        assert_eq!(tsd.placement.tspin, TSpin::Full);
+        assert!(matches!(
+            tsd.inputs[..],
+            [
+                ..,
+                Input::RotateCW | Input::RotateCCW | Input::Rotate180,
+                Input::HardDrop
+            ]
+        ));
+        let mut after = board.clone();
+        after.lock_tetromino(&tsd.placement.tetromino(), tsd.placement.position);
+        assert_eq!(after.clear_lines(), 2);
+    }
+}
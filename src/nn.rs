@@ -0,0 +1,482 @@
+use crate::board::{BOARD_HEIGHT, BOARD_WIDTH};
+use crate::bot::{BotState, Plan};
+use crate::env::{encode_bot, Encoding, MAX_GARBAGE_METER, MAX_PREVIEWS};
+use crate::mcts::{softmax, Evaluator};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::{fs, io, path::Path};
+
+/// Columns a placement's x can fall in, from -3 up.
+pub const POLICY_COLUMNS: usize = 14;
+/// One policy output per hold or not, rotation and column.
+pub const POLICY_SIZE: usize = 2 * 4 * POLICY_COLUMNS;
+
+/// Where a plan's prior comes from in the policy output. Plans that only differ in
+/// height or spin share one.
+pub fn policy_index(plan: &Plan) -> usize {
+    let column = (plan.placement.position.x + 3).clamp(0, POLICY_COLUMNS as i32 - 1) as usize;
+    ((plan.hold as usize) * 4 + plan.placement.rotation as usize) * POLICY_COLUMNS + column
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Activation {
+    Linear,
+    Relu,
+    Tanh,
+}
+
+impl Activation {
+    fn apply(self, values: &mut [f32]) {
+        match self {
+            Activation::Linear => {}
+            Activation::Relu => values.iter_mut().for_each(|v| *v = v.max(0.0)),
+            Activation::Tanh => values.iter_mut().for_each(|v| *v = v.tanh()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Activation::Linear => "linear",
+            Activation::Relu => "relu",
+            Activation::Tanh => "tanh",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Activation> {
+        [Activation::Linear, Activation::Relu, Activation::Tanh]
+            .into_iter()
+            .find(|a| a.name() == name)
+    }
+}
+
+/// A convolution over the board with zero padding, so every channel stays board sized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conv {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// Odd width and height of the square kernel.
+    pub kernel: usize,
+    /// Indexed [output][input][row][column].
+    pub weights: Vec<f32>,
+    pub bias: Vec<f32>,
+    pub activation: Activation,
+}
+
+impl Conv {
+    fn forward(&self, input: &[f32]) -> Vec<f32> {
+        let (h, w, k) = (BOARD_HEIGHT as i32, BOARD_WIDTH as i32, self.kernel as i32);
+        let cells = BOARD_HEIGHT * BOARD_WIDTH;
+        let mut output = vec![0.0; self.outputs * cells];
+        for o in 0..self.outputs {
+            let out = &mut output[o * cells..(o + 1) * cells];
+            out.fill(self.bias[o]);
+            for i in 0..self.inputs {
+                let plane = &input[i * cells..(i + 1) * cells];
+                let kernel = &self.weights[(o * self.inputs + i) * (k * k) as usize..];
+                for ky in 0..k {
+                    for kx in 0..k {
+                        let weight = kernel[(ky * k + kx) as usize];
+                        let (dy, dx) = (ky - k / 2, kx - k / 2);
+                        for y in (-dy).max(0)..h.min(h - dy) {
+                            for x in (-dx).max(0)..w.min(w - dx) {
+                                out[(y * w + x) as usize] +=
+                                    weight * plane[((y + dy) * w + x + dx) as usize];
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        self.activation.apply(&mut output);
+        output
+    }
+}
+
+/// A fully connected layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dense {
+    pub inputs: usize,
+    pub outputs: usize,
+    /// Indexed [output][input].
+    pub weights: Vec<f32>,
+    pub bias: Vec<f32>,
+    pub activation: Activation,
+}
+
+impl Dense {
+    fn forward(&self, input: &[f32]) -> Vec<f32> {
+        let mut output: Vec<f32> = self
+            .weights
+            .chunks(self.inputs)
+            .zip(&self.bias)
+            .map(|(row, bias)| bias + row.iter().zip(input).map(|(w, x)| w * x).sum::<f32>())
+            .collect();
+        self.activation.apply(&mut output);
+        output
+    }
+}
+
+/// A policy and value network over `env` observations. The board planes go through
+/// the convolutions, get flattened and joined with the other features, and go
+/// through the trunk into both heads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Network {
+    pub encoding: Encoding,
+    pub convs: Vec<Conv>,
+    pub trunk: Vec<Dense>,
+    /// Ends in `POLICY_SIZE` logits.
+    pub policy: Vec<Dense>,
+    /// Ends in a single value.
+    pub value: Vec<Dense>,
+}
+
+impl Network {
+    /// A freshly initialized network to start training from, with relu convolutions
+    /// of `channels` and relu trunk layers of `hidden` width.
+    pub fn random(encoding: Encoding, channels: &[usize], hidden: &[usize], seed: u64) -> Self {
+        let mut rng = Pcg32::seed_from_u64(seed);
+        // He uniform: keeps activations roughly the same size through relu layers
+        let mut init = |fan_in: usize, n: usize| -> Vec<f32> {
+            let limit = (6.0 / fan_in as f32).sqrt();
+            (0..n).map(|_| rng.gen_range(-limit..limit)).collect()
+        };
+        let mut convs = Vec::new();
+        let mut planes = encoding.planes();
+        for &outputs in channels {
+            convs.push(Conv {
+                inputs: planes,
+                outputs,
+                kernel: 3,
+                weights: init(planes * 9, outputs * planes * 9),
+                bias: vec![0.0; outputs],
+                activation: Activation::Relu,
+            });
+            planes = outputs;
+        }
+        let mut dense = |inputs: usize, outputs: usize, activation| Dense {
+            inputs,
+            outputs,
+            weights: init(inputs, inputs * outputs),
+            bias: vec![0.0; outputs],
+            activation,
+        };
+        let mut width = planes * BOARD_HEIGHT * BOARD_WIDTH + encoding.features_len();
+        let mut trunk = Vec::new();
+        for &outputs in hidden {
+            trunk.push(dense(width, outputs, Activation::Relu));
+            width = outputs;
+        }
+        Network {
+            policy: vec![dense(width, POLICY_SIZE, Activation::Linear)],
+            value: vec![dense(width, 1, Activation::Linear)],
+            encoding,
+            convs,
+            trunk,
+        }
+    }
+
+    /// Policy logits and value for an observation.
+    pub fn forward(&self, board: &[f32], features: &[f32]) -> (Vec<f32>, f32) {
+        let mut x = board.to_vec();
+        for conv in &self.convs {
+            x = conv.forward(&x);
+        }
+        x.extend_from_slice(features);
+        for layer in &self.trunk {
+            x = layer.forward(&x);
+        }
+        let mut policy = x.clone();
+        for layer in &self.policy {
+            policy = layer.forward(&policy);
+        }
+        let mut value = x;
+        for layer in &self.value {
+            value = layer.forward(&value);
+        }
+        (policy, value[0])
+    }
+
+    // Every layer has to take what the one before it gives
+    fn check(&self) -> Result<(), String> {
+        let mut planes = self.encoding.planes();
+        for (i, conv) in self.convs.iter().enumerate() {
+            if conv.inputs != planes {
+                return Err(format!(
+                    "conv {} takes {} planes, gets {}",
+                    i + 1,
+                    conv.inputs,
+                    planes
+                ));
+            }
+            if conv.kernel % 2 == 0 {
+                return Err(format!("conv {} needs an odd kernel", i + 1));
+            }
+            planes = conv.outputs;
+        }
+        let mut width = planes
+            .checked_mul(BOARD_HEIGHT * BOARD_WIDTH)
+            .and_then(|board| board.checked_add(self.encoding.features_len()))
+            .ok_or_else(|| String::from("the convolutions make too many planes"))?;
+        for layer in &self.trunk {
+            if layer.inputs != width {
+                return Err(format!(
+                    "trunk layer takes {} inputs, gets {}",
+                    layer.inputs, width
+                ));
+            }
+            width = layer.outputs;
+        }
+        for (name, head, size) in [
+            ("policy", &self.policy, POLICY_SIZE),
+            ("value", &self.value, 1),
+        ] {
+            let mut inputs = width;
+            for layer in head {
+                if layer.inputs != inputs {
+                    return Err(format!(
+                        "{} layer takes {} inputs, gets {}",
+                        name, layer.inputs, inputs
+                    ));
+                }
+                inputs = layer.outputs;
+            }
+            if head.is_empty() || inputs != size {
+                return Err(format!("{} head must end in {} outputs", name, size));
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the format `encode` writes: an `encoding` line, then layers each followed
+    /// by their weights and biases, with `policy` and `value` starting the heads.
+    /// Numbers can be spread over lines however is convenient; `#` starts a comment.
+    pub fn parse(text: &str) -> Result<Network, String> {
+        let mut tokens = Tokens::new(text);
+        tokens.keyword("encoding")?;
+        let encoding = Encoding {
+            piece_planes: tokens.number::<u8>()? != 0,
+            previews: tokens.number()?,
+            garbage_meter: tokens.number()?,
+        };
+        if encoding.previews > MAX_PREVIEWS {
+            return Err(format!(
+                "line {}: at most {} previews can be encoded",
+                tokens.line, MAX_PREVIEWS
+            ));
+        }
+        if encoding.garbage_meter > MAX_GARBAGE_METER {
+            return Err(format!(
+                "line {}: the garbage meter is at most {} long",
+                tokens.line, MAX_GARBAGE_METER
+            ));
+        }
+        let mut network = Network {
+            encoding,
+            convs: Vec::new(),
+            trunk: Vec::new(),
+            policy: Vec::new(),
+            value: Vec::new(),
+        };
+        let mut section = "trunk";
+        while let Some((line, word)) = tokens.next() {
+            match word {
+                "policy" | "value" => section = word,
+                "conv" if section == "trunk" && network.trunk.is_empty() => {
+                    let (inputs, outputs, kernel) = (
+                        tokens.number()?,
+                        tokens.number()?,
+                        tokens.number::<usize>()?,
+                    );
+                    let activation = tokens.activation()?;
+                    let size = [inputs, kernel, kernel]
+                        .into_iter()
+                        .try_fold(outputs, usize::checked_mul)
+                        .ok_or_else(|| format!("line {}: conv layer too large", line))?;
+                    network.convs.push(Conv {
+                        inputs,
+                        outputs,
+                        kernel,
+                        weights: tokens.numbers(size)?,
+                        bias: tokens.numbers(outputs)?,
+                        activation,
+                    });
+                }
+                "dense" => {
+                    let (inputs, outputs) = (tokens.number()?, tokens.number::<usize>()?);
+                    let activation = tokens.activation()?;
+                    let size = outputs
+                        .checked_mul(inputs)
+                        .ok_or_else(|| format!("line {}: dense layer too large", line))?;
+                    let layer = Dense {
+                        inputs,
+                        outputs,
+                        weights: tokens.numbers(size)?,
+                        bias: tokens.numbers(outputs)?,
+                        activation,
+                    };
+                    match section {
+                        "trunk" => network.trunk.push(layer),
+                        "policy" => network.policy.push(layer),
+                        _ => network.value.push(layer),
+                    }
+                }
+                _ => return Err(format!("line {}: unexpected `{}`", line, word)),
+            }
+        }
+        network.check()?;
+        Ok(network)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Network> {
+        let text = fs::read_to_string(path)?;
+        Network::parse(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+
+    /// The network in the format `parse` reads, one row of weights per line.
+    pub fn encode(&self) -> String {
+        let e = &self.encoding;
+        let mut text = format!(
+            "encoding {} {} {}\n",
+            e.piece_planes as u8, e.previews, e.garbage_meter
+        );
+        let numbers = |values: &[f32], per_line: usize| -> String {
+            values
+                .chunks(per_line.max(1))
+                .map(|row| {
+                    let row: Vec<String> = row.iter().map(|v| v.to_string()).collect();
+                    row.join(" ") + "\n"
+                })
+                .collect()
+        };
+        for conv in &self.convs {
+            text += &format!(
+                "conv {} {} {} {}\n",
+                conv.inputs,
+                conv.outputs,
+                conv.kernel,
+                conv.activation.name()
+            );
+            text += &numbers(&conv.weights, conv.kernel * conv.kernel);
+            text += &numbers(&conv.bias, conv.outputs);
+        }
+        for (header, layers) in [
+            ("", &self.trunk),
+            ("policy\n", &self.policy),
+            ("value\n", &self.value),
+        ] {
+            text += header;
+            for layer in layers {
+                text += &format!(
+                    "dense {} {} {}\n",
+                    layer.inputs,
+                    layer.outputs,
+                    layer.activation.name()
+                );
+                text += &numbers(&layer.weights, layer.inputs);
+                text += &numbers(&layer.bias, layer.outputs);
+            }
+        }
+        text
+    }
+}
+
+impl Evaluator for Network {
+    fn evaluate(&self, state: &BotState, plans: &[Plan]) -> (Vec<f32>, f32) {
+        // The search doesn't see garbage coming, so neither does the network
+        let observation = encode_bot(state, 0, &self.encoding);
+        let (policy, value) = self.forward(&observation.board, &observation.features);
+        let logits: Vec<f32> = plans.iter().map(|p| policy[policy_index(p)]).collect();
+        (softmax(&logits, 1.0), value)
+    }
+}
+
+// Whitespace separated words, remembering their line for errors
+struct Tokens<'a> {
+    words: Box<dyn Iterator<Item = (usize, &'a str)> + 'a>,
+    line: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(text: &'a str) -> Self {
+        let words = text.lines().enumerate().flat_map(|(n, line)| {
+            let line = line.split('#').next().unwrap_or("");
+            line.split_whitespace().map(move |word| (n + 1, word))
+        });
+        Tokens {
+            words: Box::new(words),
+            line: 0,
+        }
+    }
+
+    fn next(&mut self) -> Option<(usize, &'a str)> {
+        let next = self.words.next();
+        if let Some((line, _)) = next {
+            self.line = line;
+        }
+        next
+    }
+
+    fn word(&mut self) -> Result<&'a str, String> {
+        self.next()
+            .map(|(_, word)| word)
+            .ok_or_else(|| format!("line {}: unexpected end of file", self.line))
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), String> {
+        match self.word()? {
+            word if word == keyword => Ok(()),
+            word => Err(format!(
+                "line {}: expected `{}`, got `{}`",
+                self.line, keyword, word
+            )),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, String> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| format!("line {}: `{}` is not a number", self.line, word))
+    }
+
+    fn numbers(&mut self, n: usize) -> Result<Vec<f32>, String> {
+        (0..n).map(|_| self.number()).collect()
+    }
+
+    fn activation(&mut self) -> Result<Activation, String> {
+        let word = self.word()?;
+        Activation::from_name(word)
+            .ok_or_else(|| format!("line {}: unknown activation `{}`", self.line, word))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_round_trips() {
+        let network = Network::random(Encoding::default(), &[2], &[8], 3);
+        assert_eq!(Network::parse(&network.encode()), Ok(network));
+    }
+
+    #[test]
+    fn oversized_layers_are_refused() {
+        let huge = usize::MAX / 2;
+        let conv = format!("encoding 1 5 20\nconv 2 {} 3 relu\n", huge);
+        assert!(Network::parse(&conv).unwrap_err().contains("too large"));
+        let dense = format!("encoding 1 5 20\ndense {} 4 relu\n", huge);
+        assert!(Network::parse(&dense).unwrap_err().contains("too large"));
+        // Sizes that would overflow working out the first dense layer's width
+        let previews = format!("encoding 1 {} 20\n", usize::MAX / 7);
+        assert!(Network::parse(&previews).unwrap_err().contains("previews"));
+        let meter = format!("encoding 1 5 {}\n", usize::MAX);
+        assert!(Network::parse(&meter)
+            .unwrap_err()
+            .contains("garbage meter"));
+    }
+}
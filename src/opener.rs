@@ -0,0 +1,679 @@
+use crate::board::{Board, Point, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::bot::{Bot, BotState, Brain, Plan};
+use crate::movegen::sorted_cells;
+use crate::pc::Solver;
+use crate::rules::TSpin;
+use crate::tetromino::{Direction, Kind, Tetromino};
+use std::collections::HashSet;
+
+// An opener is drawn as everything it places, top row first and resting on the floor,
+// with each piece in its letter: uppercase for the first bag and lowercase for the
+// second, which also tells two touching pieces of one kind apart. A drawn T is a
+// t-spin, the first bag's before the second's, and the rows it fills are cleared
+// before the pieces above them are built on. T pieces that aren't drawn go in hold.
+// Other than the t-spins, the letters are only one way to fill the cells: any pieces
+// from the opener's bags that fill them build it just the same.
+//
+// Every opener can be built the other way round too, mirrored left to right, which
+// swaps L with J and S with Z.
+
+/// A standard setup for the first bags of a game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opener {
+    pub name: &'static str,
+    /// What it sets up.
+    pub description: &'static str,
+    /// The shapes it can be built in, drawn as above.
+    pub layouts: &'static [&'static [&'static str]],
+    /// Whether the setup is finished off with a perfect clear, found by the solver
+    /// once it's built.
+    pub perfect_clear: bool,
+}
+
+/// The openers we know, by name.
+pub static OPENERS: [Opener; 4] = [
+    Opener {
+        name: "TKI",
+        description: "T-spin double on the left with the first bag",
+        layouts: &[
+            &["...SZZ....", "L..SSZZ...", "LTTTSJJJOO", "LLTIIIIJOO"],
+            &[".......J..", "L..ZZ.SJJJ", "LTTTZZSSOO", "LLTIIIISOO"],
+            &["...OOJ....", "L..OOJ..S.", "LTTTJJZZSS", "LLTIIIIZZS"],
+        ],
+        perfect_clear: false,
+    },
+    Opener {
+        name: "MKO",
+        description: "T-spin double on the right with the first bag",
+        layouts: &[
+            &["..S..OO...", "..SS.OO..J", "LLLSZZTTTJ", "LIIIIZZTJJ"],
+            &["....SSZ...", "...SSZZ..J", "OOLLLZTTTJ", "OOLIIIITJJ"],
+            &["..L.......", "LLLZ.SS..J", "OOZZSSTTTJ", "OOZIIIITJJ"],
+            &["....LOO...", ".Z..LOO..J", "ZZSSLLTTTJ", "ZSSIIIITJJ"],
+        ],
+        perfect_clear: false,
+    },
+    Opener {
+        name: "PCO",
+        description: "perfect clear with the held T and the second bag",
+        layouts: &[&["LLLJJJ....", "LZOOSJ....", "ZZOOSS....", "ZIIIIS...."]],
+        perfect_clear: true,
+    },
+    Opener {
+        name: "DT cannon",
+        description: "T-spin double then T-spin triple over two bags",
+        layouts: &[
+            &[
+                "..j....zz.",
+                "..j.....zz",
+                "sjjiiiitoo",
+                "sslllSttoo",
+                "LslZZSStOO",
+                "LTTTZZSJOO",
+                "LLTIIIIJJJ",
+            ],
+            &[
+                ".j..ss....",
+                ".j.ss.....",
+                "jjllltiiii",
+                "zzlOOttZoo",
+                "LzzOOtZZoo",
+                "LTTTSSZJJJ",
+                "LLTSSIIIIJ",
+            ],
+        ],
+        perfect_clear: false,
+    },
+];
+
+/// Which way an opener is built: one of its layouts, as drawn or mirrored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Build {
+    pub layout: usize,
+    pub mirrored: bool,
+}
+
+/// One piece of an opener: its kind and the cells it covers, top to bottom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub kind: Kind,
+    pub cells: [Point; 4],
+    /// Which bag it comes from, counting from 1.
+    pub bag: usize,
+}
+
+impl Opener {
+    /// Looks an opener up by name, ignoring case, with `-` or `_` for spaces, e.g.
+    /// "tki" or "dt-cannon".
+    pub fn find(name: &str) -> Option<&'static Opener> {
+        let name = name.replace(['-', '_'], " ");
+        OPENERS.iter().find(|o| o.name.eq_ignore_ascii_case(&name))
+    }
+
+    /// Every way to build it.
+    pub fn builds(&self) -> Vec<Build> {
+        [false, true]
+            .into_iter()
+            .flat_map(|mirrored| {
+                (0..self.layouts.len()).map(move |layout| Build { layout, mirrored })
+            })
+            .collect()
+    }
+
+    /// The name as players see it, e.g. "TKI (mirrored)".
+    pub fn title(&self, mirrored: bool) -> String {
+        match mirrored {
+            true => format!("{} (mirrored)", self.name),
+            false => self.name.to_string(),
+        }
+    }
+
+    /// Every piece the opener places built as `build`, where it goes on an empty
+    /// board before anything is cleared.
+    pub fn slots(&self, build: Build) -> Vec<Slot> {
+        let rows = self.layouts[build.layout];
+        let mirrored = build.mirrored;
+        let top = BOARD_HEIGHT - rows.len();
+        let letters: Vec<Vec<char>> = rows.iter().map(|r| r.chars().collect()).collect();
+        let mut seen = vec![vec![false; BOARD_WIDTH]; rows.len()];
+        let mut slots = Vec::new();
+        for y in 0..rows.len() {
+            for x in 0..BOARD_WIDTH {
+                let letter = letters[y][x];
+                if letter == '.' || seen[y][x] {
+                    continue;
+                }
+                // The piece is every cell of its letter joined to this one
+                let mut cells = Vec::new();
+                let mut stack = vec![(x, y)];
+                seen[y][x] = true;
+                while let Some((x, y)) = stack.pop() {
+                    cells.push(Point {
+                        x: if mirrored { BOARD_WIDTH - 1 - x } else { x } as i32,
+                        y: (top + y) as i32,
+                    });
+                    let neighbours = [
+                        (x.wrapping_sub(1), y),
+                        (x + 1, y),
+                        (x, y.wrapping_sub(1)),
+                        (x, y + 1),
+                    ];
+                    for (nx, ny) in neighbours {
+                        let same = letters.get(ny).and_then(|row| row.get(nx)) == Some(&letter);
+                        if same && !seen[ny][nx] {
+                            seen[ny][nx] = true;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+                let kind = Kind::from_letter(letter.to_ascii_uppercase())
+                    .expect("openers are drawn with piece letters");
+                let cells: [Point; 4] =
+                    cells.try_into().expect("openers are drawn in whole pieces");
+                slots.push(Slot {
+                    kind: if mirrored { mirror(kind) } else { kind },
+                    cells: sorted_cells(cells),
+                    bag: if letter.is_ascii_uppercase() { 1 } else { 2 },
+                });
+            }
+        }
+        slots
+    }
+
+    /// What's still to do on `board` to build it as `build` with `spun` of its t-spins
+    /// done, or None if `board` isn't partway there: something is where the opener
+    /// has nothing, or is in the way of a t-spin.
+    pub fn left(&self, board: &Board, build: Build, spun: usize) -> Option<Left> {
+        let slots = self.slots(build);
+        let mut spins: Vec<Slot> = slots
+            .iter()
+            .filter(|s| s.kind == Kind::T)
+            .copied()
+            .collect();
+        spins.sort_by_key(|s| s.bag);
+        if spun > spins.len() {
+            return None;
+        }
+        let cells: Vec<Point> = slots
+            .iter()
+            .filter(|s| s.kind != Kind::T)
+            .chain(&spins[..spun])
+            .flat_map(|s| s.cells)
+            .collect();
+        // Each t-spin done cleared the rows it filled up
+        let cleared: Vec<i32> = (0..BOARD_HEIGHT as i32)
+            .filter(|y| (0..BOARD_WIDTH as i32).all(|x| cells.contains(&Point { x, y: *y })))
+            .collect();
+        let shift = |p: &Point| Point {
+            x: p.x,
+            y: p.y + cleared.iter().filter(|y| **y > p.y).count() as i32,
+        };
+        let kept: Vec<Point> = cells
+            .iter()
+            .filter(|p| !cleared.contains(&p.y))
+            .map(shift)
+            .collect();
+        let filled = kept.iter().filter(|p| board.occupied(p.x, p.y)).count();
+        let total = (0..BOARD_HEIGHT)
+            .flat_map(|y| (0..BOARD_WIDTH).map(move |x| (x, y)))
+            .filter(|(x, y)| board.cell(*x, *y).is_some())
+            .count();
+        if filled != total {
+            return None;
+        }
+        let spins = spins[spun..]
+            .iter()
+            .map(|slot| Slot {
+                cells: sorted_cells(slot.cells.map(|p| shift(&p))),
+                ..*slot
+            })
+            .collect::<Vec<_>>();
+        let blocked = spins
+            .iter()
+            .flat_map(|slot| slot.cells)
+            .any(|p| board.occupied(p.x, p.y));
+        (!blocked).then(|| Left {
+            cells: kept
+                .into_iter()
+                .filter(|p| !board.occupied(p.x, p.y))
+                .collect(),
+            spins,
+        })
+    }
+
+    /// Whether everything but the t-spins is in place on `board`, so it's built and
+    /// ready to spin or clear.
+    pub fn built(&self, board: &Board, build: Build) -> bool {
+        let spins = self
+            .slots(build)
+            .iter()
+            .filter(|s| s.kind == Kind::T)
+            .count();
+        !board.is_empty()
+            && (0..=spins).any(|spun| {
+                self.left(board, build, spun)
+                    .is_some_and(|left| left.cells.is_empty())
+            })
+    }
+}
+
+/// What's still to do for an opener on some board, where it goes there.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Left {
+    /// Cells to fill, with any pieces but T.
+    pub cells: Vec<Point>,
+    /// The t-spins to do, in order.
+    pub spins: Vec<Slot>,
+}
+
+impl Left {
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty() && self.spins.is_empty()
+    }
+}
+
+/// The opener `board` shows built, and whether it's mirrored. Only the cells count,
+/// so any pieces filling them will do.
+pub fn recognize(board: &Board) -> Option<(&'static Opener, bool)> {
+    OPENERS.iter().find_map(|opener| {
+        opener
+            .builds()
+            .into_iter()
+            .find(|build| opener.built(board, *build))
+            .map(|build| (opener, build.mirrored))
+    })
+}
+
+/// Builds an opener, whichever way the pieces it's dealt allow, then hands over to
+/// `bot`. It also hands over for good once the opener can't be finished: the pieces
+/// in view don't fit, or something landed where the opener has nothing.
+#[derive(Debug, Clone)]
+pub struct OpenerBot {
+    pub opener: &'static Opener,
+    pub bot: Bot,
+    // T-spins done and pieces placed so far
+    spun: usize,
+    placed: usize,
+    stage: Stage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Building,
+    // Built, looking for the perfect clear that finishes it
+    Clearing,
+    Done,
+}
+
+impl OpenerBot {
+    pub fn new(opener: &'static Opener, bot: Bot) -> Self {
+        OpenerBot {
+            opener,
+            bot,
+            spun: 0,
+            placed: 0,
+            stage: Stage::Building,
+        }
+    }
+
+    /// Whether the opener is over, played out or given up on, and `bot` has taken over.
+    pub fn done(&self) -> bool {
+        self.stage == Stage::Done
+    }
+
+    // The next piece toward the opener, if the known pieces can all go into it one
+    // of the ways the board so far allows
+    fn build(&mut self, state: &BotState) -> Option<Plan> {
+        for build in self.opener.builds() {
+            let Some(left) = self.opener.left(&state.board, build, self.spun) else {
+                continue;
+            };
+            if left.is_empty() {
+                self.stage = match self.opener.perfect_clear {
+                    true => Stage::Clearing,
+                    false => Stage::Done,
+                };
+                return None;
+            }
+            if let Some(plan) = fill(self.opener, build, state, self.spun, self.placed) {
+                self.spun += (plan.placement.kind == Kind::T) as usize;
+                self.placed += 1;
+                return Some(plan);
+            }
+        }
+        self.stage = Stage::Done;
+        None
+    }
+
+    // The next piece of a perfect clear with the pieces in view
+    fn clear(&mut self, state: &BotState) -> Option<Plan> {
+        let (piece, _) = state.piece.as_ref()?;
+        let solver = Solver {
+            hold: state.hold_enabled,
+            limit: Some(1),
+            ..Solver::default()
+        };
+        let solutions = solver.solve(&state.board, piece.kind, state.hold, &state.queue);
+        let first = solutions.first()?.plans.first()?;
+        // The solver's inputs start at the spawn position; ours start where the piece is
+        let cells = sorted_cells(first.placement.cells());
+        state
+            .plans()
+            .into_iter()
+            .find(|plan| plan.hold == first.hold && sorted_cells(plan.placement.cells()) == cells)
+    }
+}
+
+impl Brain for OpenerBot {
+    fn think(&mut self, state: &BotState) -> Option<Plan> {
+        if self.stage == Stage::Building {
+            if let Some(plan) = self.build(state) {
+                return Some(plan);
+            }
+        }
+        if self.stage == Stage::Clearing {
+            match self.clear(state).filter(|_| !state.board.is_empty()) {
+                Some(plan) => return Some(plan),
+                None => self.stage = Stage::Done,
+            }
+        }
+        self.bot.think(state)
+    }
+
+    fn frames_per_piece(&self) -> u64 {
+        self.bot.frames_per_piece()
+    }
+}
+
+// The first plan of a way to play every piece we know of into the opener, holding
+// whatever doesn't fit. Pieces other than T fill any of its cells they fit in, and a
+// T has to spin into the next t-spin. Running out of known pieces, or reaching the
+// bags after the opener's, counts as fitting if what's left could still be filled.
+fn fill(
+    opener: &Opener,
+    build: Build,
+    state: &BotState,
+    spun: usize,
+    placed: usize,
+) -> Option<Plan> {
+    let bags = opener.slots(build).iter().map(|s| s.bag).max().unwrap_or(0);
+    let mut search = Search {
+        opener,
+        build,
+        pieces: 7 * bags,
+        dead: HashSet::new(),
+    };
+    search.search(state, spun, placed)
+}
+
+struct Search<'a> {
+    opener: &'a Opener,
+    build: Build,
+    // How many pieces the opener's bags hold
+    pieces: usize,
+    // Positions with no way on, by field, hold, pieces placed and t-spins done, since
+    // pieces played in another order often meet
+    dead: HashSet<(Board, Option<Kind>, usize, usize)>,
+}
+
+impl Search<'_> {
+    // `placed` counts the pieces put down, so the piece in play is the one after
+    // those and the held piece
+    fn search(&mut self, state: &BotState, spun: usize, placed: usize) -> Option<Plan> {
+        let key = (state.board.clone(), state.hold, placed, spun);
+        if self.dead.contains(&key) {
+            return None;
+        }
+        let left = self.opener.left(&state.board, self.build, spun)?;
+        let found = state.plans().into_iter().find(|plan| {
+            let cells = plan.placement.cells();
+            let spin = plan.placement.kind == Kind::T;
+            let fits = match left.spins.first() {
+                _ if !spin => cells.iter().all(|p| left.cells.contains(p)),
+                Some(slot) => {
+                    slot.cells == sorted_cells(cells) && plan.placement.tspin == TSpin::Full
+                }
+                None => false,
+            };
+            if !fits {
+                return false;
+            }
+            let (next, _) = state.play(plan);
+            let spun = spun + spin as usize;
+            let Some(rest) = self.opener.left(&next.board, self.build, spun) else {
+                return false;
+            };
+            if !tileable(&rest.cells, &mut self.to_come(&next, placed + 1)) {
+                return false;
+            }
+            // Pieces from later bags are for whoever plays on
+            let drawn = placed + 1 + next.hold.is_some() as usize;
+            let beyond = next.piece.is_none() || drawn >= self.pieces;
+            rest.is_empty() || beyond || self.search(&next, spun, placed + 1).is_some()
+        });
+        if found.is_none() {
+            self.dead.insert(key);
+        }
+        found
+    }
+
+    // The pieces other than T still to come from the opener's bags, the held one
+    // included, as far as they're known. When the queue runs out within them, the
+    // rest of its bag and any bags after are known as kinds, if not in order.
+    fn to_come(&self, state: &BotState, placed: usize) -> Vec<Kind> {
+        let drawn = placed + state.hold.is_some() as usize;
+        let mut kinds: Vec<Kind> = state.hold.into_iter().collect();
+        let known = state.piece.iter().map(|(piece, _)| piece.kind);
+        kinds.extend(
+            known
+                .chain(state.queue.iter().copied())
+                .take(self.pieces.saturating_sub(drawn)),
+        );
+        let seen = drawn + state.piece.is_some() as usize + state.queue.len();
+        if seen < self.pieces {
+            kinds.extend(&state.bag);
+            let bags = (self.pieces - seen).saturating_sub(state.bag.len()) / 7;
+            kinds.extend((0..bags).flat_map(|_| Kind::ALL));
+        }
+        kinds.retain(|kind| *kind != Kind::T);
+        kinds
+    }
+}
+
+// Whether `cells` could be covered exactly by tetrominoes of some of `kinds`
+fn tileable(cells: &[Point], kinds: &mut Vec<Kind>) -> bool {
+    let Some(first) = cells.iter().min_by_key(|p| (p.y, p.x)) else {
+        return true;
+    };
+    let mut tried = Vec::new();
+    for i in 0..kinds.len() {
+        let kind = kinds[i];
+        if tried.contains(&kind) {
+            continue;
+        }
+        tried.push(kind);
+        let mut tetromino = Tetromino::new(kind);
+        for _ in 0..4 {
+            let mut shape = Vec::new();
+            tetromino.each_point(&mut |row, col| shape.push(Point { x: col, y: row }));
+            tetromino.rotate(Direction::CW);
+            // The shape's first cell in reading order goes on ours
+            let anchor = *shape.iter().min_by_key(|p| (p.y, p.x)).unwrap();
+            let placed: Vec<Point> = shape
+                .iter()
+                .map(|p| Point {
+                    x: first.x + p.x - anchor.x,
+                    y: first.y + p.y - anchor.y,
+                })
+                .collect();
+            if !placed.iter().all(|p| cells.contains(p)) {
+                continue;
+            }
+            let rest: Vec<Point> = cells
+                .iter()
+                .filter(|p| !placed.contains(p))
+                .copied()
+                .collect();
+            kinds.swap_remove(i);
+            let fits = tileable(&rest, kinds);
+            kinds.push(kind);
+            let last = kinds.len() - 1;
+            kinds.swap(i, last);
+            if fits {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn mirror(kind: Kind) -> Kind {
+    match kind {
+        Kind::L => Kind::J,
+        Kind::J => Kind::L,
+        Kind::S => Kind::Z,
+        Kind::Z => Kind::S,
+        kind => kind,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::Difficulty;
+    use crate::rules::Chain;
+
+    // Cells moved up against the top left corner, to compare shapes wherever they are
+    fn shape(cells: impl IntoIterator<Item = Point>) -> Vec<Point> {
+        let cells: Vec<Point> = cells.into_iter().collect();
+        let left = cells.iter().map(|p| p.x).min().unwrap();
+        let top = cells.iter().map(|p| p.y).min().unwrap();
+        let mut shape: Vec<Point> = cells
+            .iter()
+            .map(|p| Point {
+                x: p.x - left,
+                y: p.y - top,
+            })
+            .collect();
+        shape.sort_by_key(|p| (p.y, p.x));
+        shape
+    }
+
+    fn shapes(kind: Kind) -> Vec<Vec<Point>> {
+        let mut tetromino = Tetromino::new(kind);
+        (0..4)
+            .map(|_| {
+                let mut cells = Vec::new();
+                tetromino.each_point(&mut |y, x| cells.push(Point { x, y }));
+                tetromino.rotate(Direction::CW);
+                shape(cells)
+            })
+            .collect()
+    }
+
+    fn empty_state(kinds: &[Kind]) -> BotState {
+        let mut state = BotState {
+            board: Board::new(),
+            chain: Chain::default(),
+            piece: None,
+            hold: None,
+            hold_enabled: true,
+            can_hold: true,
+            queue: Vec::new(),
+            bag: Vec::new(),
+        };
+        for kind in kinds {
+            state.deal(*kind);
+        }
+        state
+    }
+
+    #[test]
+    fn layouts_split_into_pieces() {
+        for opener in &OPENERS {
+            for layout in opener.layouts {
+                for row in *layout {
+                    assert_eq!(row.len(), BOARD_WIDTH, "{} {:?}", opener.name, row);
+                    assert!(row
+                        .chars()
+                        .all(|c| c == '.' || Kind::from_letter(c.to_ascii_uppercase()).is_some()));
+                }
+            }
+            for build in opener.builds() {
+                let slots = opener.slots(build);
+                let mut covered = HashSet::new();
+                for slot in &slots {
+                    assert!(
+                        shapes(slot.kind).contains(&shape(slot.cells)),
+                        "{} {:?}: {:?}",
+                        opener.name,
+                        build,
+                        slot
+                    );
+                    assert!(slot.cells.iter().all(|p| covered.insert(*p)));
+                }
+                // A bag deals each kind once
+                for bag in 1..=2 {
+                    let mut kinds: Vec<Kind> = slots
+                        .iter()
+                        .filter(|s| s.bag == bag)
+                        .map(|s| s.kind)
+                        .collect();
+                    let count = kinds.len();
+                    kinds.sort();
+                    kinds.dedup();
+                    assert_eq!(kinds.len(), count, "{} bag {}", opener.name, bag);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn recognizes_tki_either_way_round() {
+        let tki = Opener::find("tki").unwrap();
+        for mirrored in [false, true] {
+            let build = Build {
+                layout: 0,
+                mirrored,
+            };
+            let mut board = Board::new();
+            for slot in tki.slots(build).iter().filter(|s| s.kind != Kind::T) {
+                for p in slot.cells {
+                    board.set_cell(
+                        p.x as usize,
+                        p.y as usize,
+                        Some(Tetromino::new(slot.kind).color),
+                    );
+                }
+            }
+            let (opener, found) = recognize(&board).unwrap();
+            assert_eq!(opener.name, "TKI");
+            assert_eq!(found, mirrored);
+        }
+        assert_eq!(recognize(&Board::new()), None);
+    }
+
+    #[test]
+    fn builds_tki_from_the_first_bag() {
+        use Kind::*;
+        let tki = Opener::find("tki").unwrap();
+        let mut bot = OpenerBot::new(tki, Bot::new(Difficulty::HARD, 0));
+        let mut state = empty_state(&[I, O, L, J, S, Z, T, O, I, L, J, S, Z, T]);
+        for _ in 0..6 {
+            let plan = bot.think(&state).unwrap();
+            state = state.play(&plan).0;
+            assert!(!bot.done());
+        }
+        // Six pieces in, with the T left for the spin
+        assert_eq!(state.piece.as_ref().map(|(t, _)| t.kind), Some(T));
+        assert_eq!(recognize(&state.board).map(|(o, _)| o.name), Some("TKI"));
+        let spin = bot.think(&state).unwrap();
+        assert_eq!(spin.placement.kind, T);
+        assert_eq!(spin.placement.tspin, TSpin::Full);
+        let (state, result) = state.play(&spin);
+        assert_eq!(result.lines, 2);
+        // With nothing left to build it hands over
+        bot.think(&state);
+        assert!(bot.done());
+    }
+}
@@ -0,0 +1,89 @@
+use crate::bot::Difficulty;
+use crate::eval::Weights;
+use crate::nn::Network;
+use crate::opener::Opener;
+use crate::player::Player;
+use anyhow::Result;
+use std::sync::Arc;
+
+/// Who does the thinking for a bot seat.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BotKind {
+    /// Our own bot at some difficulty, judging boards with `weights`, and building
+    /// `opener` first if it has one.
+    Builtin {
+        difficulty: Difficulty,
+        weights: Weights,
+        opener: Option<&'static Opener>,
+    },
+    /// Our tree search at some difficulty, judging positions with `network`, on
+    /// `threads` threads.
+    Network {
+        difficulty: Difficulty,
+        network: Arc<Network>,
+        threads: usize,
+    },
+    /// An outside program speaking the Tetris Bot Protocol, run with this command line.
+    Tbp(String),
+}
+
+/// Someone playing in a match: a person connected over TCP, or a bot the server runs.
+#[derive(Debug)]
+pub enum Participant {
+    Human(Player),
+    Bot { name: String, kind: BotKind },
+}
+
+impl Participant {
+    pub fn bot(kind: BotKind) -> Self {
+        let name = match &kind {
+            BotKind::Builtin {
+                difficulty, opener, ..
+            } => match opener {
+                Some(opener) => format!("Bot ({}, {})", level(difficulty), opener.name),
+                None => format!("Bot ({})", level(difficulty)),
+            },
+            BotKind::Network { difficulty, .. } => {
+                format!("Bot ({}, network)", level(difficulty))
+            }
+            BotKind::Tbp(command) => {
+                let program = command.split_whitespace().next().unwrap_or_default();
+                let program = program.rsplit('/').next().unwrap_or(program);
+                format!("TBP ({})", program)
+            }
+        };
+        Participant::Bot { name, kind }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Participant::Human(player) => player.name(),
+            Participant::Bot { name, .. } => name,
+        }
+    }
+
+    /// The account results are kept under, for people who signed in.
+    pub fn account(&self) -> Option<&str> {
+        match self {
+            Participant::Human(player) => player.account(),
+            Participant::Bot { .. } => None,
+        }
+    }
+
+    /// Sends one line to a person; bots read the simulation directly instead.
+    pub async fn send(&mut self, msg: String) -> Result<()> {
+        match self {
+            Participant::Human(player) => player.send(msg).await,
+            Participant::Bot { .. } => Ok(()),
+        }
+    }
+}
+
+fn level(difficulty: &Difficulty) -> &'static str {
+    match *difficulty {
+        Difficulty::EASY => "easy",
+        Difficulty::MEDIUM => "medium",
+        Difficulty::HARD => "hard",
+        _ => "custom",
+    }
+}
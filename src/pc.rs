@@ -0,0 +1,365 @@
+use crate::board::{Board, Point, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::bot::Plan;
+use crate::movegen::{self, sorted_cells, Move, Placement};
+use crate::simulation::{spawn_position, Input};
+use crate::tetromino::{Kind, Tetromino};
+use std::collections::{HashMap, HashSet};
+
+/// The most lines a perfect clear can be searched for within.
+pub const MAX_HEIGHT: usize = 6;
+
+/// Finds every way to perfect clear from a position with the pieces known.
+#[derive(Debug, Clone)]
+pub struct Solver {
+    /// Lines the clear may take, counted up from the bottom of the board.
+    pub height: usize,
+    /// Whether the hold may be used.
+    pub hold: bool,
+    /// Stop after finding this many.
+    pub limit: Option<usize>,
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver {
+            height: 4,
+            hold: true,
+            limit: None,
+        }
+    }
+}
+
+/// One way to perfect clear: a plan per piece, in the order they're played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    pub plans: Vec<Plan>,
+    /// Lines the clear takes.
+    pub lines: usize,
+}
+
+impl Solution {
+    /// The pieces in the order they're placed, with `*` on those played by pressing
+    /// hold first, e.g. "*J J L".
+    pub fn describe(&self) -> String {
+        self.plans
+            .iter()
+            .map(|plan| {
+                let hold = if plan.hold { "*" } else { "" };
+                format!("{}{}", hold, plan.placement.kind.letter())
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// `board`, the field the solution starts from, with every piece drawn in where it
+    /// goes. Rows cleared partway through keep their place, so it reads as one picture.
+    pub fn field(&self, board: &Board) -> Board {
+        let mut field = board.clone();
+        let mut played = board.clone();
+        // Which row of `field` each row of `played` started out as
+        let mut rows: Vec<usize> = (0..BOARD_HEIGHT).collect();
+        for plan in &self.plans {
+            let tetromino = plan.placement.tetromino();
+            for cell in plan.placement.cells() {
+                field.set_cell(
+                    cell.x as usize,
+                    rows[cell.y as usize],
+                    Some(tetromino.color),
+                );
+            }
+            played.lock_tetromino(&tetromino, plan.placement.position);
+            let kept: Vec<usize> = (0..BOARD_HEIGHT)
+                .filter(|y| (0..BOARD_WIDTH).any(|x| played.cell(x, *y).is_none()))
+                .map(|y| rows[y])
+                .collect();
+            played.clear_lines();
+            // Pieces only go in the rows being cleared, so what's above never matters
+            rows = vec![0; BOARD_HEIGHT - kept.len()];
+            rows.extend(kept);
+        }
+        field
+    }
+}
+
+impl Solver {
+    /// Every perfect clear within `height` lines playing `current`, then `queue`, with
+    /// `hold` in the hold. Clears taking fewer lines come first. Nothing is known about
+    /// the pieces after the queue, but a held piece can still be swapped in for the
+    /// next one to finish.
+    pub fn solve(
+        &self,
+        board: &Board,
+        current: Kind,
+        hold: Option<Kind>,
+        queue: &[Kind],
+    ) -> Vec<Solution> {
+        let mut pieces = vec![current];
+        pieces.extend_from_slice(queue);
+        let mut search = Search {
+            solver: self,
+            pieces: &pieces,
+            placements: HashMap::new(),
+            moves: HashMap::new(),
+            dead: HashSet::new(),
+            path: Vec::new(),
+            seen: HashSet::new(),
+            solutions: Vec::new(),
+        };
+        let filled = (0..BOARD_HEIGHT)
+            .map(|y| {
+                (0..BOARD_WIDTH)
+                    .filter(|x| board.cell(*x, y).is_some())
+                    .count()
+            })
+            .collect::<Vec<_>>();
+        let total: usize = filled.iter().sum();
+        for lines in 1..=self.height.min(MAX_HEIGHT) {
+            let below: usize = filled[BOARD_HEIGHT - lines..].iter().sum();
+            let full = filled[BOARD_HEIGHT - lines..].contains(&BOARD_WIDTH);
+            if below < total || full || !(lines * BOARD_WIDTH - below).is_multiple_of(4) {
+                continue;
+            }
+            search.search(board, lines, 0, hold, lines);
+        }
+        search.solutions
+    }
+}
+
+struct Search<'a> {
+    solver: &'a Solver,
+    // The piece in play followed by the queue
+    pieces: &'a [Kind],
+    // Placements are worked out once per field and kind. Fields only ever have cells
+    // in the bottom MAX_HEIGHT rows, so those rows' bits stand for the whole board.
+    placements: HashMap<(u64, Kind), Vec<Placement>>,
+    // The same for moves from the spawn position, only needed for solutions
+    moves: HashMap<(u64, Kind), Vec<Move>>,
+    // (field, lines left, next piece, hold) for positions with no way to finish
+    dead: HashSet<(u64, usize, usize, Option<Kind>)>,
+    // The field before each piece, whether it was held and where it went
+    path: Vec<(Board, bool, Placement)>,
+    // Placement sequences already found, so different routes to one aren't repeated
+    seen: HashSet<Vec<(Kind, [Point; 4])>>,
+    solutions: Vec<Solution>,
+}
+
+impl Search<'_> {
+    // Plays on from `board`, which has `left` lines to go, with pieces[next] in play.
+    // Returns whether any way to finish was found.
+    fn search(
+        &mut self,
+        board: &Board,
+        left: usize,
+        next: usize,
+        hold: Option<Kind>,
+        lines: usize,
+    ) -> bool {
+        if left == 0 {
+            self.finish(lines);
+            return true;
+        }
+        if self
+            .solver
+            .limit
+            .is_some_and(|limit| self.solutions.len() >= limit)
+        {
+            return true;
+        }
+        let top = BOARD_HEIGHT - left;
+        let empty = (top..BOARD_HEIGHT)
+            .flat_map(|y| (0..BOARD_WIDTH).map(move |x| (x, y)))
+            .filter(|(x, y)| board.cell(*x, *y).is_none())
+            .count();
+        let pieces = self.pieces.len() - next + hold.is_some() as usize;
+        if empty > 4 * pieces || !split_evenly(board, top) {
+            return false;
+        }
+        let key = (bits(board), left, next, hold);
+        if self.dead.contains(&key) {
+            return false;
+        }
+
+        // (kind played, whether by holding, next piece after, hold after)
+        let mut options = Vec::new();
+        if let Some(&current) = self.pieces.get(next) {
+            options.push((current, false, next + 1, hold));
+        }
+        if self.solver.hold {
+            let current = self.pieces.get(next).copied();
+            match hold {
+                Some(kind) if Some(kind) != current => {
+                    options.push((kind, true, (next + 1).min(self.pieces.len()), current))
+                }
+                None => {
+                    if let (Some(current), Some(&kind)) = (current, self.pieces.get(next + 1)) {
+                        if kind != current {
+                            options.push((kind, true, next + 2, Some(current)));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut found = false;
+        for (kind, held, after, hold) in options {
+            for placement in self.placements(board, kind) {
+                if placement.cells().iter().any(|p| (p.y as usize) < top) {
+                    continue;
+                }
+                let mut played = board.clone();
+                played.lock_tetromino(&placement.tetromino(), placement.position);
+                let cleared = played.clear_lines();
+                // An earlier clear belongs to a search with fewer lines
+                if cleared < left && played.is_empty() {
+                    continue;
+                }
+                self.path.push((board.clone(), held, placement));
+                found |= self.search(&played, left - cleared, after, hold, lines);
+                self.path.pop();
+            }
+        }
+        if !found {
+            self.dead.insert(key);
+        }
+        found
+    }
+
+    // Where `kind` can go on `board`, one placement per set of cells. Everything above
+    // the bottom rows is empty, so the piece starts just above them rather than at
+    // the spawn position: it reaches all the same places and there's less to search.
+    fn placements(&mut self, board: &Board, kind: Kind) -> Vec<Placement> {
+        self.placements
+            .entry((bits(board), kind))
+            .or_insert_with(|| {
+                let tetromino = Tetromino::new(kind);
+                let start = Point {
+                    x: spawn_position(&tetromino).x,
+                    y: (BOARD_HEIGHT - MAX_HEIGHT - 4) as i32,
+                };
+                let mut seen = HashSet::new();
+                movegen::moves(board, &tetromino, start)
+                    .into_iter()
+                    .map(|m| m.placement)
+                    .filter(|p| seen.insert(sorted_cells(p.cells())))
+                    .collect()
+            })
+            .clone()
+    }
+
+    // The plan for one step of a solution, with inputs from the spawn position
+    fn plan(&mut self, board: &Board, held: bool, placement: Placement) -> Plan {
+        let moves = self
+            .moves
+            .entry((bits(board), placement.kind))
+            .or_insert_with(|| {
+                let tetromino = Tetromino::new(placement.kind);
+                movegen::moves(board, &tetromino, spawn_position(&tetromino))
+            });
+        let cells = sorted_cells(placement.cells());
+        let m = moves
+            .iter()
+            .filter(|m| sorted_cells(m.placement.cells()) == cells)
+            .min_by_key(|m| m.placement != placement)
+            .expect("placements are reachable from spawn");
+        let mut inputs = Vec::new();
+        if held {
+            inputs.push(Input::Hold);
+        }
+        inputs.extend(m.inputs.iter().copied());
+        Plan {
+            hold: held,
+            placement: m.placement,
+            inputs,
+        }
+    }
+
+    fn finish(&mut self, lines: usize) {
+        let key = self
+            .path
+            .iter()
+            .map(|(_, _, placement)| (placement.kind, sorted_cells(placement.cells())))
+            .collect();
+        if self.seen.insert(key) {
+            let path = self.path.clone();
+            let plans = path
+                .iter()
+                .map(|(board, held, placement)| self.plan(board, *held, *placement))
+                .collect();
+            self.solutions.push(Solution { plans, lines });
+        }
+    }
+}
+
+// The bottom MAX_HEIGHT rows of the board, ten bits a row
+fn bits(board: &Board) -> u64 {
+    let mut bits = 0;
+    for y in BOARD_HEIGHT - MAX_HEIGHT..BOARD_HEIGHT {
+        for x in 0..BOARD_WIDTH {
+            bits = bits << 1 | board.cell(x, y).is_some() as u64;
+        }
+    }
+    bits
+}
+
+// A column filled from `top` down walls off the two sides: clears take whole rows,
+// so it stays filled and no piece can cross it. Each side then has to be filled
+// with whole pieces.
+fn split_evenly(board: &Board, top: usize) -> bool {
+    let mut empty = 0usize;
+    for x in 0..BOARD_WIDTH {
+        let column = (top..BOARD_HEIGHT)
+            .filter(|y| board.cell(x, *y).is_none())
+            .count();
+        if column == 0 && !empty.is_multiple_of(4) {
+            return false;
+        }
+        empty += column;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::puzzle::Puzzle;
+
+    fn puzzle() -> Puzzle {
+        Puzzle::parse(include_str!("../puzzles/pc.txt")).unwrap()
+    }
+
+    #[test]
+    fn solves_the_box() {
+        let puzzle = puzzle();
+        let (current, queue) = puzzle.pieces.split_first().unwrap();
+        let solutions = Solver::default().solve(&puzzle.board, *current, puzzle.hold, queue);
+        // The two Js, either one first
+        assert_eq!(solutions.len(), 2);
+        assert_ne!(solutions[0], solutions[1]);
+        for solution in &solutions {
+            assert_eq!(solution.describe(), "*J J");
+            assert_eq!(solution.lines, 2);
+            let mut board = puzzle.board.clone();
+            for plan in &solution.plans {
+                let placement = &plan.placement;
+                board.lock_tetromino(&placement.tetromino(), placement.position);
+                board.clear_lines();
+            }
+            assert!(board.is_empty());
+        }
+    }
+
+    #[test]
+    fn holding_adds_no_duplicates() {
+        // Holding one J to play the other places the same pieces in the same order
+        let board = puzzle().board;
+        let held = Solver::default().solve(&board, Kind::J, None, &[Kind::J]);
+        let unheld = Solver {
+            hold: false,
+            ..Solver::default()
+        }
+        .solve(&board, Kind::J, None, &[Kind::J]);
+        assert_eq!(held.len(), 2);
+        assert_eq!(held.len(), unheld.len());
+    }
+}
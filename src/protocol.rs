@@ -0,0 +1,521 @@
+use crate::board::{Board, Point, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::finesse::Finesse;
+use crate::handling::Handling;
+use crate::mode::{Match, Stats, Summary};
+use crate::simulation::{Input, PlayerState, Rules};
+use crate::tetromino::{Color, Kind};
+
+// Everything on the wire is one line of text per message.
+
+/// What a client sends to the server.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ClientMessage {
+    /// A single input: "Left", "HardDrop", ...
+    Input(Input),
+    /// A held key went down ("Press Left"); this also moves once.
+    Press(Input),
+    Release(Input),
+    /// Movement from auto-repeat while a key is held ("Repeat Left", "Repeat DasLeft").
+    Repeat(Input),
+    /// "Handling <das> <arr> <sdf> <dcd>", so the server knows how fast repeats may come.
+    Handling(Handling),
+}
+
+impl ClientMessage {
+    pub fn encode(&self) -> String {
+        match self {
+            ClientMessage::Input(input) => encode_input(*input).to_string(),
+            ClientMessage::Press(input) => format!("Press {}", encode_input(*input)),
+            ClientMessage::Release(input) => format!("Release {}", encode_input(*input)),
+            ClientMessage::Repeat(input) => format!("Repeat {}", encode_input(*input)),
+            ClientMessage::Handling(h) => {
+                format!("Handling {} {} {} {}", h.das, h.arr, h.sdf, h.dcd)
+            }
+        }
+    }
+
+    pub fn decode(line: &str) -> Option<ClientMessage> {
+        let mut parts = line.split_whitespace();
+        let message = match parts.next()? {
+            "Press" => ClientMessage::Press(decode_input(parts.next()?)?),
+            "Release" => ClientMessage::Release(decode_input(parts.next()?)?),
+            "Repeat" => ClientMessage::Repeat(decode_input(parts.next()?)?),
+            "Handling" => {
+                let mut value = || parts.next()?.parse::<u32>().ok();
+                ClientMessage::Handling(Handling {
+                    das: value()?,
+                    arr: value()?,
+                    sdf: value()?,
+                    dcd: value()?,
+                })
+            }
+            input => ClientMessage::Input(decode_input(input)?),
+        };
+        Some(message)
+    }
+}
+
+/// Account commands and snapshots, which the server answers itself rather than passing
+/// on to the match. Registering or logging in is only for a connection's first line, in
+/// place of a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    /// "Register <name> <password>": makes an account and plays as it.
+    Register { name: String, password: String },
+    /// "Login <name> <password>"
+    Login { name: String, password: String },
+    /// "History [name]": the last matches an account played, ours if no name is given.
+    History(Option<String>),
+    /// "Bests [name]": an account's best run in each solo mode.
+    Bests(Option<String>),
+    /// "Stats [name]": an account's totals.
+    Stats(Option<String>),
+    /// "Snapshot": the whole match as it stands, as a save, for watching or attaching
+    /// to a bug report.
+    Snapshot,
+}
+
+impl Request {
+    pub fn encode(&self) -> String {
+        let query = |tag: &str, name: &Option<String>| match name {
+            Some(name) => format!("{} {}", tag, name),
+            None => tag.to_string(),
+        };
+        match self {
+            Request::Register { name, password } => format!("Register {} {}", name, password),
+            Request::Login { name, password } => format!("Login {} {}", name, password),
+            Request::History(name) => query("History", name),
+            Request::Bests(name) => query("Bests", name),
+            Request::Stats(name) => query("Stats", name),
+            Request::Snapshot => String::from("Snapshot"),
+        }
+    }
+
+    pub fn decode(line: &str) -> Option<Request> {
+        let mut parts = line.splitn(3, ' ');
+        let tag = parts.next()?;
+        let name = parts
+            .next()
+            .filter(|name| !name.is_empty())
+            .map(String::from);
+        // Passwords are the rest of the line, spaces and all
+        let password = parts.next().map(String::from);
+        let signing_in = matches!(tag, "Register" | "Login");
+        if signing_in && password.as_deref().is_none_or(str::is_empty) {
+            return None;
+        }
+        let request = match tag {
+            "Register" => Request::Register {
+                name: name?,
+                password: password?,
+            },
+            "Login" => Request::Login {
+                name: name?,
+                password: password?,
+            },
+            _ if password.is_some() => return None,
+            "History" => Request::History(name),
+            "Bests" => Request::Bests(name),
+            "Stats" => Request::Stats(name),
+            "Snapshot" if name.is_none() => Request::Snapshot,
+            _ => return None,
+        };
+        Some(request)
+    }
+}
+
+/// What the server tells clients about the match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerMessage {
+    /// Which player id the receiving connection controls.
+    Welcome {
+        id: usize,
+    },
+    Player {
+        id: usize,
+        name: String,
+    },
+    Board {
+        id: usize,
+        board: Box<Board>,
+    },
+    Piece {
+        id: usize,
+        kind: Kind,
+        rotation: u8,
+        position: Point,
+    },
+    Queue {
+        id: usize,
+        kinds: Vec<Kind>,
+    },
+    Hold {
+        id: usize,
+        kind: Option<Kind>,
+    },
+    Garbage {
+        id: usize,
+        lines: usize,
+    },
+    /// Who the player is sending garbage to.
+    Target {
+        id: usize,
+        target: Option<usize>,
+    },
+    /// Frames per row of gravity, so clients can pace soft drop.
+    Gravity {
+        id: usize,
+        frames: u32,
+    },
+    ToppedOut {
+        id: usize,
+    },
+    /// How a solo run is going, sent every frame while it lasts.
+    Run {
+        id: usize,
+        summary: Summary,
+    },
+    /// A solo run is over.
+    Result {
+        id: usize,
+        summary: Summary,
+    },
+    /// How a player's piece was played, sent as it locks in a solo run.
+    Finesse {
+        id: usize,
+        finesse: Finesse,
+    },
+    /// A player's board shows an opener built in the first bags, by its name.
+    Opener {
+        id: usize,
+        name: String,
+    },
+    /// The player is signed in to the account `name`.
+    Account {
+        id: usize,
+        name: String,
+    },
+    /// One match from an account's history, oldest first.
+    Match {
+        name: String,
+        entry: Match,
+    },
+    /// An account's best run in one solo mode.
+    Best {
+        name: String,
+        summary: Summary,
+    },
+    Stats {
+        name: String,
+        stats: Stats,
+    },
+    /// The text of a save of the match, which `save::decode` reads.
+    Snapshot(String),
+    /// Anything else is shown to the player as is.
+    Text(String),
+}
+
+impl ServerMessage {
+    pub fn encode(&self) -> String {
+        match self {
+            ServerMessage::Welcome { id } => format!("Welcome {}", id),
+            ServerMessage::Player { id, name } => format!("Player {} {}", id, name),
+            ServerMessage::Board { id, board } => format!("Board {} {}", id, encode_board(board)),
+            ServerMessage::Piece {
+                id,
+                kind,
+                rotation,
+                position,
+            } => format!(
+                "Piece {} {} {} {} {}",
+                id,
+                kind.letter(),
+                rotation,
+                position.x,
+                position.y
+            ),
+            ServerMessage::Queue { id, kinds } => {
+                format!(
+                    "Queue {} {}",
+                    id,
+                    kinds.iter().map(|k| k.letter()).collect::<String>()
+                )
+            }
+            ServerMessage::Hold { id, kind } => {
+                format!("Hold {} {}", id, kind.map_or('-', |k| k.letter()))
+            }
+            ServerMessage::Garbage { id, lines } => format!("Garbage {} {}", id, lines),
+            ServerMessage::Target { id, target } => match target {
+                Some(target) => format!("Target {} {}", id, target),
+                None => format!("Target {} -", id),
+            },
+            ServerMessage::Gravity { id, frames } => format!("Gravity {} {}", id, frames),
+            ServerMessage::ToppedOut { id } => format!("ToppedOut {}", id),
+            ServerMessage::Run { id, summary } => format!("Run {} {}", id, summary.encode()),
+            ServerMessage::Result { id, summary } => {
+                format!("Result {} {}", id, summary.encode())
+            }
+            ServerMessage::Finesse { id, finesse } => {
+                format!("Finesse {} {}", id, finesse.encode())
+            }
+            ServerMessage::Opener { id, name } => format!("Opener {} {}", id, name),
+            ServerMessage::Account { id, name } => format!("Account {} {}", id, name),
+            ServerMessage::Match { name, entry } => format!("Match {} {}", name, entry.encode()),
+            ServerMessage::Best { name, summary } => {
+                format!("Best {} {}", name, summary.encode())
+            }
+            ServerMessage::Stats { name, stats } => format!("Stats {} {}", name, stats.encode()),
+            ServerMessage::Snapshot(save) => format!("Snapshot {}", save),
+            ServerMessage::Text(text) => text.clone(),
+        }
+    }
+
+    /// Lines that don't parse as anything else come back as `Text`.
+    pub fn decode(line: &str) -> ServerMessage {
+        decode_message(line).unwrap_or_else(|| ServerMessage::Text(line.to_string()))
+    }
+}
+
+fn decode_message(line: &str) -> Option<ServerMessage> {
+    // A save can have spaces anywhere in it
+    if let Some(save) = line.strip_prefix("Snapshot ") {
+        return Some(ServerMessage::Snapshot(save.to_string()));
+    }
+    let mut parts = line.splitn(3, ' ');
+    let tag = parts.next()?;
+    let who = parts.next()?;
+    let rest = parts.next().unwrap_or("");
+    // Account records are about a name rather than a seat in the match
+    let name = who.to_string();
+    match tag {
+        "Match" => {
+            return Some(ServerMessage::Match {
+                name,
+                entry: Match::decode(rest)?,
+            })
+        }
+        "Best" => {
+            return Some(ServerMessage::Best {
+                name,
+                summary: Summary::decode(rest)?,
+            })
+        }
+        "Stats" => {
+            return Some(ServerMessage::Stats {
+                name,
+                stats: Stats::decode(rest)?,
+            })
+        }
+        _ => {}
+    }
+    let id: usize = who.parse().ok()?;
+    let message = match tag {
+        "Welcome" => ServerMessage::Welcome { id },
+        "Player" => ServerMessage::Player {
+            id,
+            name: rest.to_string(),
+        },
+        "Board" => ServerMessage::Board {
+            id,
+            board: Box::new(decode_board(rest)?),
+        },
+        "Piece" => {
+            let fields: Vec<&str> = rest.split(' ').collect();
+            if fields.len() != 4 {
+                return None;
+            }
+            ServerMessage::Piece {
+                id,
+                kind: Kind::from_letter(fields[0].chars().next()?)?,
+                rotation: fields[1].parse().ok()?,
+                position: Point {
+                    x: fields[2].parse().ok()?,
+                    y: fields[3].parse().ok()?,
+                },
+            }
+        }
+        "Queue" => ServerMessage::Queue {
+            id,
+            kinds: rest
+                .chars()
+                .map(Kind::from_letter)
+                .collect::<Option<Vec<Kind>>>()?,
+        },
+        "Hold" => ServerMessage::Hold {
+            id,
+            kind: rest.chars().next().and_then(Kind::from_letter),
+        },
+        "Garbage" => ServerMessage::Garbage {
+            id,
+            lines: rest.parse().ok()?,
+        },
+        "Target" => ServerMessage::Target {
+            id,
+            target: rest.parse().ok(),
+        },
+        "Gravity" => ServerMessage::Gravity {
+            id,
+            frames: rest.parse().ok()?,
+        },
+        "ToppedOut" => ServerMessage::ToppedOut { id },
+        "Run" => ServerMessage::Run {
+            id,
+            summary: Summary::decode(rest)?,
+        },
+        "Result" => ServerMessage::Result {
+            id,
+            summary: Summary::decode(rest)?,
+        },
+        "Finesse" => ServerMessage::Finesse {
+            id,
+            finesse: Finesse::decode(rest)?,
+        },
+        "Opener" => ServerMessage::Opener {
+            id,
+            name: rest.to_string(),
+        },
+        "Account" => ServerMessage::Account {
+            id,
+            name: rest.to_string(),
+        },
+        _ => return None,
+    };
+    Some(message)
+}
+
+/// The messages describing one player's current state.
+pub fn snapshot(id: usize, state: &PlayerState, rules: &Rules) -> Vec<ServerMessage> {
+    let mut messages = vec![
+        ServerMessage::Board {
+            id,
+            board: Box::new(state.board.clone()),
+        },
+        ServerMessage::Queue {
+            id,
+            kinds: state
+                .queue
+                .preview(rules.previews)
+                .iter()
+                .map(|t| t.kind)
+                .collect(),
+        },
+        ServerMessage::Hold {
+            id,
+            kind: state.hold.as_ref().map(|t| t.kind),
+        },
+        ServerMessage::Garbage {
+            id,
+            lines: state.pending_lines(),
+        },
+        ServerMessage::Target {
+            id,
+            target: state.target,
+        },
+        ServerMessage::Gravity {
+            id,
+            frames: rules.gravity,
+        },
+    ];
+    if state.alive {
+        messages.push(ServerMessage::Piece {
+            id,
+            kind: state.tetromino.kind,
+            rotation: state.tetromino.rotation,
+            position: state.position,
+        });
+    }
+    messages
+}
+
+pub fn encode_input(input: Input) -> &'static str {
+    match input {
+        Input::Left => "Left",
+        Input::Right => "Right",
+        Input::DasLeft => "DasLeft",
+        Input::DasRight => "DasRight",
+        Input::SoftDrop => "SoftDrop",
+        Input::SonicDrop => "SonicDrop",
+        Input::HardDrop => "HardDrop",
+        Input::RotateCW => "CW",
+        Input::RotateCCW => "CCW",
+        Input::Rotate180 => "180",
+        Input::Hold => "Hold",
+        Input::Forfeit => "Forfeit",
+        Input::NextTarget => "Target",
+    }
+}
+
+pub fn decode_input(msg: &str) -> Option<Input> {
+    match msg {
+        "Left" => Some(Input::Left),
+        "Right" => Some(Input::Right),
+        "DasLeft" => Some(Input::DasLeft),
+        "DasRight" => Some(Input::DasRight),
+        "SoftDrop" => Some(Input::SoftDrop),
+        "SonicDrop" => Some(Input::SonicDrop),
+        "Space" | "HardDrop" => Some(Input::HardDrop),
+        "CW" | "RotateRight" => Some(Input::RotateCW),
+        "CCW" => Some(Input::RotateCCW),
+        "180" => Some(Input::Rotate180),
+        "Hold" => Some(Input::Hold),
+        "Forfeit" => Some(Input::Forfeit),
+        "Target" => Some(Input::NextTarget),
+        _ => None,
+    }
+}
+
+/// One character per cell: `.` for empty, otherwise the piece letter the color belongs to
+/// (`G` for garbage).
+pub fn color_char(cell: Option<Color>) -> char {
+    match cell {
+        None => '.',
+        Some(Color::Cyan) => 'I',
+        Some(Color::Yellow) => 'O',
+        Some(Color::Purple) => 'T',
+        Some(Color::Green) => 'S',
+        Some(Color::Red) => 'Z',
+        Some(Color::Blue) => 'J',
+        Some(Color::Orange) => 'L',
+        Some(Color::Gray) => 'G',
+        Some(Color::Black) => '#',
+    }
+}
+
+pub fn char_color(c: char) -> Option<Option<Color>> {
+    let color = match c {
+        '.' => return Some(None),
+        'I' => Color::Cyan,
+        'O' => Color::Yellow,
+        'T' => Color::Purple,
+        'S' => Color::Green,
+        'Z' => Color::Red,
+        'J' => Color::Blue,
+        'L' => Color::Orange,
+        'G' => Color::Gray,
+        '#' => Color::Black,
+        _ => return None,
+    };
+    Some(Some(color))
+}
+
+fn encode_board(board: &Board) -> String {
+    let mut s = String::with_capacity(BOARD_WIDTH * BOARD_HEIGHT);
+    for y in 0..BOARD_HEIGHT {
+        for x in 0..BOARD_WIDTH {
+            s.push(color_char(board.cell(x, y)));
+        }
+    }
+    s
+}
+
+fn decode_board(s: &str) -> Option<Board> {
+    let cells: Vec<char> = s.chars().collect();
+    if cells.len() != BOARD_WIDTH * BOARD_HEIGHT {
+        return None;
+    }
+    let mut board = Board::new();
+    for (i, c) in cells.into_iter().enumerate() {
+        board.set_cell(i % BOARD_WIDTH, i / BOARD_WIDTH, char_color(c)?);
+    }
+    Some(board)
+}
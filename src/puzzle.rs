@@ -0,0 +1,282 @@
+use crate::board::{Board, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::fumen;
+use crate::mode::GameMode;
+use crate::protocol::{char_color, color_char};
+use crate::rules::{LockResult, TSpin};
+use crate::tetromino::{Color, Kind, TetrominoQueue};
+#[cfg(feature = "save")]
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// A puzzle is a starting field, the pieces to play and what to do with them, written
+// as `name = value` lines followed by the field's rows, bottom row last:
+//
+//     # Set up from a TKI opener
+//     name = TST from the right
+//     goal = tspin-3
+//     pieces = TI
+//     hold = L
+//     limit = 2
+//     ..........
+//     ZZ....OO..
+//     GGGG.GGGGG
+//     GGG..GGGGG
+//     GGGG.GGGGG
+//
+// Cells are `.` for empty, a piece letter for that piece's color or `G` for garbage.
+// The field can instead be pasted from fumen, as `fumen = v115@...`; the first page's
+// field is the one played on.
+// After the listed pieces come ordinary bags, and without a limit the puzzle lasts
+// until the goal is met or the player tops out.
+
+/// What a puzzle asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub enum Goal {
+    /// Clear this many lines in total.
+    Lines(usize),
+    /// A full t-spin clearing this many lines.
+    TSpin(usize),
+    PerfectClear,
+}
+
+impl Goal {
+    /// "lines-N", "tspin-N" (or "tss", "tsd", "tst") or "perfect-clear" (or "pc").
+    pub fn parse(text: &str) -> Option<Goal> {
+        let goal = match text {
+            "tss" => Goal::TSpin(1),
+            "tsd" => Goal::TSpin(2),
+            "tst" => Goal::TSpin(3),
+            "pc" | "perfect-clear" => Goal::PerfectClear,
+            _ => match text.split_once('-')? {
+                ("lines", n) => Goal::Lines(n.parse().ok().filter(|n| *n > 0)?),
+                ("tspin", n) => Goal::TSpin(n.parse().ok().filter(|n| (1..=3).contains(n))?),
+                _ => return None,
+            },
+        };
+        Some(goal)
+    }
+
+    pub fn encode(&self) -> String {
+        match self {
+            Goal::Lines(n) => format!("lines-{}", n),
+            Goal::TSpin(n) => format!("tspin-{}", n),
+            Goal::PerfectClear => String::from("perfect-clear"),
+        }
+    }
+
+    /// What to tell the player, e.g. "T-spin triple".
+    pub fn describe(&self) -> String {
+        match self {
+            Goal::Lines(1) => String::from("Clear a line"),
+            Goal::Lines(n) => format!("Clear {} lines", n),
+            Goal::TSpin(1) => String::from("T-spin single"),
+            Goal::TSpin(2) => String::from("T-spin double"),
+            Goal::TSpin(_) => String::from("T-spin triple"),
+            Goal::PerfectClear => String::from("Perfect clear"),
+        }
+    }
+
+    /// Whether a single lock meets the goal; line goals are counted over the whole run.
+    pub fn met_by(&self, result: &LockResult) -> bool {
+        match self {
+            Goal::Lines(_) => false,
+            Goal::TSpin(n) => result.tspin == TSpin::Full && result.lines == *n,
+            Goal::PerfectClear => result.perfect_clear,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Puzzle {
+    pub name: String,
+    pub board: Board,
+    /// Dealt in order before the bags start.
+    pub pieces: Vec<Kind>,
+    pub hold: Option<Kind>,
+    pub goal: Goal,
+    /// Pieces allowed before the puzzle is failed.
+    pub limit: Option<usize>,
+}
+
+impl Puzzle {
+    /// The solo mode this puzzle is played as.
+    pub fn mode(&self) -> GameMode {
+        GameMode::Puzzle {
+            goal: self.goal,
+            limit: self.limit,
+        }
+    }
+
+    /// The piece queue, with bags from `seed` after the listed pieces.
+    pub fn queue(&self, seed: u64) -> TetrominoQueue {
+        TetrominoQueue::with_pieces(&self.pieces, seed)
+    }
+
+    pub fn parse(text: &str) -> Result<Puzzle, String> {
+        let mut name = String::new();
+        let mut pieces = Vec::new();
+        let mut hold = None;
+        let mut goal = None;
+        let mut limit = None;
+        let mut fumen = None;
+        let mut rows: Vec<Vec<Option<Color>>> = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            // Only whole lines are comments, so names can have a `#` in them
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |msg: String| format!("line {}: {}", n + 1, msg);
+            let Some((key, value)) = line.split_once('=') else {
+                let row = line
+                    .chars()
+                    .map(|c| char_color(c.to_ascii_uppercase()))
+                    .collect::<Option<Vec<_>>>()
+                    .filter(|row| row.len() == BOARD_WIDTH)
+                    .ok_or_else(|| err(format!("`{}` is not a row of the field", line)))?;
+                rows.push(row);
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let kinds = || {
+                value
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .map(|c| {
+                        Kind::from_letter(c).ok_or_else(|| err(format!("`{}` is not a piece", c)))
+                    })
+                    .collect::<Result<Vec<Kind>, String>>()
+            };
+            match key {
+                "name" => name = value.to_string(),
+                "fumen" => {
+                    let pages =
+                        fumen::decode(value).map_err(|e| err(format!("bad fumen: {}", e)))?;
+                    fumen = pages.into_iter().next().map(|page| page.board);
+                }
+                "pieces" => pieces = kinds()?,
+                "hold" => match kinds()?.as_slice() {
+                    [] => hold = None,
+                    [kind] => hold = Some(*kind),
+                    _ => return Err(err(String::from("only one piece can be held"))),
+                },
+                "goal" => {
+                    goal = Some(
+                        Goal::parse(value)
+                            .ok_or_else(|| err(format!("unknown goal `{}`", value)))?,
+                    )
+                }
+                "limit" => {
+                    limit = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|n| *n > 0)
+                            .ok_or_else(|| err(format!("`{}` is not a piece count", value)))?,
+                    )
+                }
+                _ => return Err(err(format!("unknown setting `{}`", key))),
+            }
+        }
+        let goal = goal.ok_or_else(|| String::from("no goal given"))?;
+        // Rows above the visible field are allowed, as fumens can fill them
+        if rows.len() > BOARD_HEIGHT {
+            return Err(format!(
+                "the field has {} rows, more than the {} that fit",
+                rows.len(),
+                BOARD_HEIGHT
+            ));
+        }
+        if fumen.is_some() && !rows.is_empty() {
+            return Err(String::from(
+                "the field is given both as rows and as a fumen",
+            ));
+        }
+        let mut board = fumen.unwrap_or_default();
+        let top = BOARD_HEIGHT - rows.len();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                board.set_cell(x, top + y, *cell);
+            }
+        }
+        Ok(Puzzle {
+            name,
+            board,
+            pieces,
+            hold,
+            goal,
+            limit,
+        })
+    }
+
+    /// The text `parse` reads, for sharing a puzzle.
+    pub fn encode(&self) -> String {
+        let mut text = String::new();
+        if !self.name.is_empty() {
+            text.push_str(&format!("name = {}\n", self.name));
+        }
+        text.push_str(&format!("goal = {}\n", self.goal.encode()));
+        if !self.pieces.is_empty() {
+            let pieces: String = self.pieces.iter().map(|k| k.letter()).collect();
+            text.push_str(&format!("pieces = {}\n", pieces));
+        }
+        if let Some(kind) = self.hold {
+            text.push_str(&format!("hold = {}\n", kind.letter()));
+        }
+        if let Some(limit) = self.limit {
+            text.push_str(&format!("limit = {}\n", limit));
+        }
+        // Rows from the highest filled one down
+        let top = (0..BOARD_HEIGHT)
+            .find(|y| (0..BOARD_WIDTH).any(|x| self.board.cell(x, *y).is_some()))
+            .unwrap_or(BOARD_HEIGHT);
+        for y in top..BOARD_HEIGHT {
+            let row: String = (0..BOARD_WIDTH)
+                .map(|x| color_char(self.board.cell(x, y)))
+                .collect();
+            text.push_str(&row);
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn load(path: &Path) -> io::Result<Puzzle> {
+        let text = fs::read_to_string(path)?;
+        Puzzle::parse(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::board::HIDDEN_ROWS;
+
+    #[test]
+    fn encode_round_trips() {
+        let text = "name = TSD #2\ngoal = tsd\npieces = TI\nhold = L\nlimit = 3\n\
+                    GGGG......\nGGG...GGGG\nGGGG.GGGGG\n";
+        let puzzle = Puzzle::parse(text).unwrap();
+        assert_eq!(puzzle.name, "TSD #2");
+        assert_eq!(Puzzle::parse(&puzzle.encode()), Ok(puzzle));
+    }
+
+    #[test]
+    fn hidden_rows_round_trip() {
+        // A fumen's field is taller than the visible one
+        let mut puzzle = Puzzle::parse("goal = pc\n").unwrap();
+        puzzle.board.set_cell(4, HIDDEN_ROWS - 1, Some(Color::Gray));
+        puzzle
+            .board
+            .set_cell(0, BOARD_HEIGHT - 1, Some(Color::Purple));
+        assert_eq!(Puzzle::parse(&puzzle.encode()), Ok(puzzle));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::board::{Point, BOARD_HEIGHT, BOARD_WIDTH, HIDDEN_ROWS};
+use crate::client::{PlayerView, View};
+use crate::mode::{format_time, garbage_rows, GameMode, PersonalBests};
+use crate::simulation::FRAMES_PER_SECOND;
+use crate::tetromino::{Color, Kind, Tetromino};
+use crossterm::{
+    cursor::MoveTo,
+    style::{self, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType},
+};
+use std::io::{self, Write};
+
+// Rows shown above the visible field so freshly spawned pieces can be seen
+const SHOWN_HIDDEN_ROWS: usize = 2;
+const FIRST_ROW: usize = HIDDEN_ROWS - SHOWN_HIDDEN_ROWS;
+const SIDE_WIDTH: usize = 10;
+// Where run stats start, below the hold piece
+const STATS_ROW: usize = 4;
+const MINI_WIDTH: usize = BOARD_WIDTH + 2;
+// Name + half-height board + a spacer
+const MINI_HEIGHT: usize = (BOARD_HEIGHT - HIDDEN_ROWS) / 2 + 2;
+const SCREEN_HEIGHT: usize = BOARD_HEIGHT - FIRST_ROW + 4;
+
+fn term_color(color: Color) -> style::Color {
+    match color {
+        Color::Black => style::Color::Black,
+        Color::Cyan => style::Color::Cyan,
+        Color::Yellow => style::Color::Yellow,
+        Color::Purple => style::Color::Magenta,
+        Color::Green => style::Color::Green,
+        Color::Red => style::Color::Red,
+        Color::Blue => style::Color::Blue,
+        Color::Orange => style::Color::AnsiValue(208),
+        Color::Gray => style::Color::DarkGrey,
+    }
+}
+
+// A line of output that keeps track of how wide it looks, escape codes aside
+#[derive(Default)]
+struct Line {
+    text: String,
+    width: usize,
+}
+
+impl Line {
+    fn push(&mut self, s: &str) {
+        self.text.push_str(s);
+        self.width += s.chars().count();
+    }
+
+    fn pad_to(&mut self, width: usize) {
+        while self.width < width {
+            self.push(" ");
+        }
+    }
+
+    // A board cell is two characters wide so it looks square
+    fn cell(&mut self, cell: Option<Color>) {
+        match cell {
+            Some(color) => {
+                self.text
+                    .push_str(&format!("{}", SetBackgroundColor(term_color(color))));
+                self.push("  ");
+                self.text.push_str(&format!("{}", ResetColor));
+            }
+            None => self.push(" ."),
+        }
+    }
+
+    fn meter(&mut self, color: Color) {
+        self.text
+            .push_str(&format!("{}", SetBackgroundColor(term_color(color))));
+        self.push(" ");
+        self.text.push_str(&format!("{}", ResetColor));
+    }
+
+    fn ghost(&mut self, color: Color) {
+        self.text
+            .push_str(&format!("{}", SetForegroundColor(term_color(color))));
+        self.push("[]");
+        self.text.push_str(&format!("{}", ResetColor));
+    }
+
+    // Two board rows in one character using the upper half block
+    fn half_cells(&mut self, upper: Option<Color>, lower: Option<Color>) {
+        let empty = style::Color::AnsiValue(235);
+        self.text.push_str(&format!(
+            "{}{}",
+            SetForegroundColor(upper.map_or(empty, term_color)),
+            SetBackgroundColor(lower.map_or(empty, term_color)),
+        ));
+        self.push("▀");
+        self.text.push_str(&format!("{}", ResetColor));
+    }
+}
+
+// The cells a piece covers, as (row, col) relative to the board
+fn piece_cells(tetromino: &Tetromino, origin: Point) -> Vec<(i32, i32)> {
+    let mut cells = Vec::new();
+    tetromino.each_point(&mut |row, col| cells.push((origin.y + row, origin.x + col)));
+    cells
+}
+
+// The board with the active piece drawn in, one color per cell
+fn composite(player: &PlayerView) -> Vec<Vec<Option<Color>>> {
+    let mut cells: Vec<Vec<Option<Color>>> = (0..BOARD_HEIGHT)
+        .map(|y| (0..BOARD_WIDTH).map(|x| player.board.cell(x, y)).collect())
+        .collect();
+    if let Some((tetromino, position)) = &player.piece {
+        for (row, col) in piece_cells(tetromino, *position) {
+            if row >= 0 && (row as usize) < BOARD_HEIGHT {
+                cells[row as usize][col as usize] = Some(tetromino.color);
+            }
+        }
+    }
+    cells
+}
+
+// A preview piece in spawn orientation, as two rows of four cells
+fn preview_rows(kind: Kind) -> Vec<Vec<Option<Color>>> {
+    let tetromino = Tetromino::new(kind);
+    let mut rows: Vec<Vec<Option<Color>>> = tetromino
+        .shape
+        .iter()
+        .filter(|row| row.iter().any(|c| *c != 0))
+        .map(|row| {
+            let mut cells: Vec<Option<Color>> = row
+                .iter()
+                .map(|c| (*c != 0).then_some(tetromino.color))
+                .collect();
+            cells.resize(4, None);
+            cells
+        })
+        .collect();
+    rows.resize(2, vec![None; 4]);
+    rows
+}
+
+fn side_piece(line: &mut Line, kind: Option<Kind>, row: usize) {
+    if let Some(kind) = kind {
+        for cell in &preview_rows(kind)[row] {
+            match cell {
+                Some(color) => line.cell(Some(*color)),
+                None => line.push("  "),
+            }
+        }
+    }
+}
+
+// Timer and stats down the left side in solo modes, each label above its value
+fn run_stats(player: &PlayerView, bests: &PersonalBests) -> Vec<String> {
+    let Some(run) = player.run else {
+        return Vec::new();
+    };
+    // Ultra counts down
+    let time = match run.mode {
+        GameMode::Ultra { seconds } => {
+            format_time((seconds * FRAMES_PER_SECOND as u64).saturating_sub(run.frames))
+        }
+        _ => format_time(run.frames),
+    };
+    // Cheese counts down what's left to dig out instead of lines cleared
+    let lines = match run.mode {
+        GameMode::Sprint { lines } | GameMode::Marathon { lines } => (
+            String::from("LINES"),
+            format!("{}/{}", run.lines.min(lines), lines),
+        ),
+        GameMode::Cheese { lines } => (
+            String::from("CHEESE"),
+            format!("{}/{}", garbage_rows(&player.board), lines),
+        ),
+        _ => (String::from("LINES"), run.lines.to_string()),
+    };
+    let mut stats = vec![(String::from("TIME"), time), lines];
+    if let GameMode::Marathon { .. } = run.mode {
+        stats.push((String::from("LEVEL"), run.level().to_string()));
+    }
+    if let GameMode::Puzzle {
+        limit: Some(limit), ..
+    } = run.mode
+    {
+        stats.push((String::from("PIECES"), format!("{}/{}", run.pieces, limit)));
+    }
+    if run.mode.keeps_score() {
+        stats.push((String::from("SCORE"), run.score.to_string()));
+    }
+    stats.push((String::from("PPS"), format!("{:.2}", run.pps())));
+    stats.push((String::from("KPP"), format!("{:.2}", run.kpp())));
+    stats.push((String::from("FAULTS"), run.faults.to_string()));
+    if let Some(best) = bests.get(run.mode) {
+        stats.push((String::from("BEST"), run.mode.format_record(best)));
+    }
+    stats
+        .into_iter()
+        .flat_map(|(label, value)| [label, value])
+        .collect()
+}
+
+fn draw_main(lines: &mut [Line], player: &PlayerView, bests: &PersonalBests) {
+    let cells = composite(player);
+    let ghost = player.ghost().map(|g| {
+        let (tetromino, _) = player.piece.as_ref().unwrap();
+        (piece_cells(tetromino, g), tetromino.color)
+    });
+
+    lines[0].push("HOLD");
+    lines[0].pad_to(SIDE_WIDTH + 1);
+    lines[0].push(&format!(" {}", player.name));
+    let stats = run_stats(player, bests);
+    let board_rows = BOARD_HEIGHT - FIRST_ROW;
+    for (i, y) in (FIRST_ROW..BOARD_HEIGHT).enumerate() {
+        let line = &mut lines[i + 1];
+        if (1..3).contains(&i) {
+            side_piece(line, player.hold, i - 1);
+        }
+        if let Some(text) = i.checked_sub(STATS_ROW).and_then(|n| stats.get(n)) {
+            line.push(text);
+        }
+        line.pad_to(SIDE_WIDTH);
+        // Garbage meter: pending lines rise from the bottom of the field
+        if board_rows - i <= player.garbage {
+            line.meter(Color::Red);
+        } else {
+            line.push(" ");
+        }
+        line.push(if y < HIDDEN_ROWS { " " } else { "│" });
+        for (x, cell) in cells[y].iter().enumerate() {
+            let is_ghost = ghost
+                .as_ref()
+                .is_some_and(|(g, _)| g.contains(&(y as i32, x as i32)));
+            match (cell, &ghost) {
+                (None, Some((_, color))) if is_ghost => line.ghost(*color),
+                _ => line.cell(*cell),
+            }
+        }
+        line.push(if y < HIDDEN_ROWS { " " } else { "│" });
+    }
+    let bottom = &mut lines[board_rows + 1];
+    bottom.pad_to(SIDE_WIDTH + 1);
+    bottom.push(&format!("└{}┘", "─".repeat(BOARD_WIDTH * 2)));
+
+    let right = SIDE_WIDTH + BOARD_WIDTH * 2 + 4;
+    lines[0].pad_to(right);
+    lines[0].push("NEXT");
+    for (n, kind) in player.queue.iter().enumerate() {
+        for row in 0..2 {
+            let line = &mut lines[1 + n * 3 + row];
+            line.pad_to(right);
+            side_piece(line, Some(*kind), row);
+        }
+    }
+    if player.topped_out {
+        let line = &mut lines[board_rows];
+        line.pad_to(right);
+        line.push("TOPPED OUT");
+    }
+}
+
+fn draw_mini(lines: &mut [Line], left: usize, player: &PlayerView, targeted: bool) {
+    let cells = composite(player);
+    let mut name = String::from(if targeted { ">" } else { "" });
+    name.extend(player.name.chars().take(BOARD_WIDTH - 4));
+    if player.topped_out {
+        name.push_str(" KO");
+    }
+    lines[0].pad_to(left);
+    lines[0].push(&name);
+    for (i, y) in (HIDDEN_ROWS..BOARD_HEIGHT).step_by(2).enumerate() {
+        let line = &mut lines[i + 1];
+        line.pad_to(left);
+        for (upper, lower) in cells[y].iter().zip(&cells[y + 1]) {
+            line.half_cells(*upper, *lower);
+        }
+    }
+}
+
+/// Redraws the whole screen: our board, hold, next queue and opponents' mini boards.
+pub fn draw(out: &mut impl Write, view: &View) -> io::Result<()> {
+    let mut lines: Vec<Line> = (0..SCREEN_HEIGHT).map(|_| Line::default()).collect();
+    let me = view.id.and_then(|id| view.players.get(&id));
+    if let Some(player) = me {
+        draw_main(&mut lines, player, &view.bests);
+    }
+
+    // Opponents fill columns to the right, in as many bands as fit
+    let (width, _) = terminal::size().unwrap_or((80, 24));
+    let left = SIDE_WIDTH * 2 + BOARD_WIDTH * 2 + 6;
+    let columns = ((width as usize).saturating_sub(left) / MINI_WIDTH).max(1);
+    let bands = (SCREEN_HEIGHT - 1) / MINI_HEIGHT;
+    let opponents = view.players.iter().filter(|(id, _)| Some(**id) != view.id);
+    let target = me.and_then(|p| p.target);
+    for (n, (id, player)) in opponents.take(columns * bands).enumerate() {
+        let top = (n / columns) * MINI_HEIGHT;
+        draw_mini(
+            &mut lines[top..top + MINI_HEIGHT],
+            left + (n % columns) * MINI_WIDTH,
+            player,
+            target == Some(*id),
+        );
+    }
+    lines[SCREEN_HEIGHT - 1].push(&view.status);
+
+    write!(out, "{}", MoveTo(0, 0))?;
+    for line in lines {
+        write!(out, "{}{}\r\n", line.text, Clear(ClearType::UntilNewLine))?;
+    }
+    write!(out, "{}", Clear(ClearType::FromCursorDown))?;
+    out.flush()
+}
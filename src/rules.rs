@@ -0,0 +1,223 @@
+use crate::board::{Board, Point};
+use crate::tetromino::{Direction, Kind, Tetromino};
+#[cfg(feature = "save")]
+use serde::{Deserialize, Serialize};
+
+// SRS wall kicks for clockwise rotations, indexed by the rotation being left.
+// Offsets are (x, y) with y pointing down, like the board.
+// Counter-clockwise kicks are the negated clockwise kicks of the reverse rotation.
+const JLSTZ_KICKS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],  // 0 -> R
+    [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],    // R -> 2
+    [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],     // 2 -> L
+    [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)], // L -> 0
+];
+const I_KICKS: [[(i32, i32); 5]; 4] = [
+    [(0, 0), (-2, 0), (1, 0), (-2, 1), (1, -2)], // 0 -> R
+    [(0, 0), (-1, 0), (2, 0), (-1, -2), (2, 1)], // R -> 2
+    [(0, 0), (2, 0), (-1, 0), (2, -1), (-1, 2)], // 2 -> L
+    [(0, 0), (1, 0), (-2, 0), (1, 2), (-2, -1)], // L -> 0
+];
+// Not part of SRS; a small set of 180 kicks so flips work next to walls and in slots
+const HALF_KICKS: [(i32, i32); 5] = [(0, 0), (0, -1), (1, 0), (-1, 0), (0, 1)];
+
+// Extra garbage for consecutive clears, indexed by combo count
+const COMBO_TABLE: [usize; 12] = [0, 0, 1, 1, 1, 2, 2, 3, 3, 4, 4, 4];
+const PERFECT_CLEAR_BONUS: usize = 10;
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TSpin {
+    #[default]
+    None,
+    Mini,
+    Full,
+}
+
+/// Clear streaks carried from one lock to the next.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Chain {
+    /// Number of consecutive pieces that cleared lines.
+    pub combo: usize,
+    /// Whether the last clear was a tetris or t-spin.
+    pub back_to_back: bool,
+}
+
+/// Everything that happened when a piece locked.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LockResult {
+    pub lines: usize,
+    pub tspin: TSpin,
+    pub perfect_clear: bool,
+    pub combo: usize,
+    pub back_to_back: bool,
+    pub attack: usize,
+}
+
+/// Tries each kick for the rotation in order. On success returns the rotated piece,
+/// its new origin and the index of the kick that worked.
+pub fn try_rotate(
+    board: &Board,
+    tetromino: &Tetromino,
+    origin: Point,
+    direction: Direction,
+) -> Option<(Tetromino, Point, usize)> {
+    let mut rotated = tetromino.clone();
+    rotated.rotate(direction);
+    for (i, (x, y)) in kicks(tetromino.kind, tetromino.rotation, direction)
+        .into_iter()
+        .enumerate()
+    {
+        let kicked = Point {
+            x: origin.x + x,
+            y: origin.y + y,
+        };
+        if !board.collision_test(&rotated, kicked) {
+            return Some((rotated, kicked, i));
+        }
+    }
+    None
+}
+
+/// The offsets to try, in order, when rotating a `kind` piece out of rotation `from`.
+pub fn kicks(kind: Kind, from: u8, direction: Direction) -> Vec<(i32, i32)> {
+    let from = from as usize;
+    match (kind, direction) {
+        (Kind::O, _) => vec![(0, 0)],
+        (_, Direction::Half) => HALF_KICKS.to_vec(),
+        (kind, Direction::CW) => kick_table(kind)[from].to_vec(),
+        (kind, Direction::CCW) => kick_table(kind)[(from + 3) % 4]
+            .iter()
+            .map(|(x, y)| (-x, -y))
+            .collect(),
+    }
+}
+
+fn kick_table(kind: Kind) -> &'static [[(i32, i32); 5]; 4] {
+    match kind {
+        Kind::I => &I_KICKS,
+        _ => &JLSTZ_KICKS,
+    }
+}
+
+/// Three-corner t-spin check, done before the piece is locked. `last_kick` is the
+/// kick index of the rotation if the last successful action was a rotation.
+pub fn detect_tspin(
+    board: &Board,
+    tetromino: &Tetromino,
+    origin: Point,
+    last_kick: Option<usize>,
+) -> TSpin {
+    let kick = match (tetromino.kind, last_kick) {
+        (Kind::T, Some(kick)) => kick,
+        _ => return TSpin::None,
+    };
+    // Corners of the 3x3 box, clockwise from top-left
+    let corners = [(0, 0), (2, 0), (2, 2), (0, 2)];
+    let filled: Vec<bool> = corners
+        .iter()
+        .map(|(x, y)| board.occupied(origin.x + x, origin.y + y))
+        .collect();
+    if filled.iter().filter(|f| **f).count() < 3 {
+        return TSpin::None;
+    }
+    // The two corners on the side the T is pointing at
+    let r = tetromino.rotation as usize;
+    let front = filled[r] && filled[(r + 1) % 4];
+    if front || kick == 4 {
+        TSpin::Full
+    } else {
+        TSpin::Mini
+    }
+}
+
+/// Garbage sent for a clear, updating the streaks in `chain`.
+pub fn score_clear(
+    lines: usize,
+    tspin: TSpin,
+    perfect_clear: bool,
+    chain: &mut Chain,
+) -> LockResult {
+    if lines == 0 {
+        chain.combo = 0;
+        return LockResult {
+            tspin,
+            ..LockResult::default()
+        };
+    }
+    let difficult = lines == 4 || tspin != TSpin::None;
+    let back_to_back = difficult && chain.back_to_back;
+    chain.back_to_back = difficult;
+    chain.combo += 1;
+
+    let mut attack = match (tspin, lines) {
+        (TSpin::Full, n) => 2 * n,
+        (TSpin::Mini, n) => n - 1,
+        (TSpin::None, 4) => 4,
+        (TSpin::None, n) => n - 1,
+    };
+    if back_to_back {
+        attack += 1;
+    }
+    attack += COMBO_TABLE[(chain.combo - 1).min(COMBO_TABLE.len() - 1)];
+    if perfect_clear {
+        attack += PERFECT_CLEAR_BONUS;
+    }
+    LockResult {
+        lines,
+        tspin,
+        perfect_clear,
+        combo: chain.combo,
+        back_to_back,
+        attack,
+    }
+}
+
+/// Guideline points for a lock at level 1; modes that keep score multiply by the level.
+/// Drops are scored separately.
+pub fn points(result: &LockResult) -> u64 {
+    let clear = match (result.tspin, result.lines) {
+        (TSpin::None, 0) => 0,
+        (TSpin::None, 1) => 100,
+        (TSpin::None, 2) => 300,
+        (TSpin::None, 3) => 500,
+        (TSpin::None, _) => 800,
+        (TSpin::Mini, 0) => 100,
+        (TSpin::Mini, 1) => 200,
+        (TSpin::Mini, _) => 400,
+        (TSpin::Full, 0) => 400,
+        (TSpin::Full, 1) => 800,
+        (TSpin::Full, 2) => 1200,
+        (TSpin::Full, _) => 1600,
+    };
+    let mut points = if result.back_to_back {
+        clear * 3 / 2
+    } else {
+        clear
+    };
+    // The first clear of a combo isn't a combo yet
+    points += 50 * result.combo.saturating_sub(1) as u64;
+    if result.perfect_clear {
+        points += match result.lines {
+            1 => 800,
+            2 => 1200,
+            3 => 1800,
+            _ => 2000,
+        };
+    }
+    points
+}
+
+/// Locks a piece into the board, clears lines and scores the result.
+pub fn lock(
+    board: &mut Board,
+    tetromino: &Tetromino,
+    origin: Point,
+    last_kick: Option<usize>,
+    chain: &mut Chain,
+) -> LockResult {
+    let tspin = detect_tspin(board, tetromino, origin, last_kick);
+    board.lock_tetromino(tetromino, origin);
+    let lines = board.clear_lines();
+    score_clear(lines, tspin, lines > 0 && board.is_empty(), chain)
+}
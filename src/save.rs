@@ -0,0 +1,161 @@
+use crate::session::Session;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// A save is a whole session as JSON, down to the random number generators' state, so
+// a game picked up from one plays on exactly as it would have:
+//
+//     {"version": 1, "session": {"simulation": {...}, "mode": ..., ...}}
+//
+// The version goes up whenever the session changes shape, and saves from other
+// versions are turned away rather than read wrong.
+
+/// The version of the format written now.
+pub const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Save<'a> {
+    version: u32,
+    session: &'a Session,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Saved {
+    session: Session,
+}
+
+/// The text `decode` reads.
+pub fn encode(session: &Session) -> String {
+    let save = Save {
+        version: VERSION,
+        session,
+    };
+    serde_json::to_string(&save).expect("sessions are always representable")
+}
+
+pub fn decode(text: &str) -> Result<Session, String> {
+    let Version { version } =
+        serde_json::from_str(text).map_err(|e| format!("not a save: {}", e))?;
+    if version != VERSION {
+        return Err(format!(
+            "saved in version {} of the format, and this reads version {}",
+            version, VERSION
+        ));
+    }
+    let Saved { session } = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    Ok(session)
+}
+
+pub fn load(path: &Path) -> io::Result<Session> {
+    let text = fs::read_to_string(path)?;
+    decode(&text).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+pub fn save(path: &Path, session: &Session) -> io::Result<()> {
+    fs::write(path, encode(session))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mode::GameMode;
+    use crate::protocol::ClientMessage;
+    use crate::simulation::Input;
+    use crate::simulation::FRAMES_PER_SECOND;
+    use crate::tetromino::Kind;
+
+    // The same inputs for both copies, varied enough to move, spin, hold and drop
+    fn play(session: &mut Session, from: u64, frames: u64) {
+        let inputs = [
+            Input::Left,
+            Input::RotateCW,
+            Input::Hold,
+            Input::DasRight,
+            Input::RotateCCW,
+            Input::HardDrop,
+        ];
+        for frame in from..from + frames {
+            for id in [1, 2] {
+                if frame % 4 == id {
+                    let input = inputs[(frame / 4 + id) as usize % inputs.len()];
+                    session.receive(id as usize, ClientMessage::Input(input));
+                }
+            }
+            session.step();
+        }
+    }
+
+    fn queue(session: &Session, id: usize) -> Vec<Kind> {
+        let state = session.simulation.player(id).unwrap();
+        state.queue.preview(14).iter().map(|t| t.kind).collect()
+    }
+
+    #[test]
+    fn loaded_session_plays_on_the_same() {
+        for mode in [GameMode::Versus, GameMode::Survival] {
+            let mut session = Session::new(42, mode);
+            session.add_player(1);
+            if !mode.is_solo() {
+                session.add_player(2);
+            }
+            play(&mut session, 0, 120);
+            // Mid-game, with pieces down
+            assert!(!session.simulation.is_over());
+            assert!(session.simulation.player(1).unwrap().pieces > 0);
+            let mut loaded = decode(&encode(&session)).unwrap();
+            play(&mut session, 120, 600);
+            play(&mut loaded, 120, 600);
+            for id in session.simulation.players().keys() {
+                let (ours, theirs) = (
+                    session.simulation.player(*id).unwrap(),
+                    loaded.simulation.player(*id).unwrap(),
+                );
+                assert_eq!(ours.board, theirs.board);
+                assert_eq!(queue(&session, *id), queue(&loaded, *id));
+                assert_eq!(
+                    ours.hold.as_ref().map(|t| t.kind),
+                    theirs.hold.as_ref().map(|t| t.kind)
+                );
+            }
+            // Everything else, the random number generators included
+            assert_eq!(encode(&session), encode(&loaded));
+        }
+    }
+
+    #[test]
+    fn runs_keep_their_finesse() {
+        let mut session = Session::new(7, GameMode::Sprint { lines: 40 });
+        session.add_player(1);
+        // Wasted presses on every piece: left and back, then down
+        for frame in 0..FRAMES_PER_SECOND as u64 * 5 {
+            let input = match frame % 12 {
+                0 => Some(Input::Left),
+                4 => Some(Input::Right),
+                8 => Some(Input::HardDrop),
+                _ => None,
+            };
+            if let Some(input) = input {
+                session.receive(1, ClientMessage::Input(input));
+            }
+            session.step();
+        }
+        let loaded = decode(&encode(&session)).unwrap();
+        let (ours, theirs) = (&session.runs[&1].finesse, &loaded.runs[&1].finesse);
+        assert!(ours.pieces.len() > 1);
+        assert!(ours.faults() > 0);
+        assert_eq!(ours.pieces, theirs.pieces);
+        assert_eq!(ours.faults(), theirs.faults());
+    }
+}
@@ -0,0 +1,572 @@
+//! Games the search bot plays against itself (or alone), recorded for training.
+//!
+//! A dataset is the bytes `TZSP`, a version byte, then one record per game. Every
+//! record starts with its length as a u32 so readers can skip games they don't want.
+//! Numbers are little-endian, pieces are `Kind as u8` with 7 meaning none, and boards
+//! are 30 bytes of bits, row-major with the top row first.
+//!
+//! ```text
+//! game:      len u32, seed u64, players u8, outcome * players, moves u32, move * moves
+//! outcome:   ending u8 (0 lost, 1 won, 2 unfinished), pieces u32, lines u32, attack u32
+//! move:      player u8, board [u8; 30], current u8, hold u8, flags u8 (1 can hold,
+//!            2 back-to-back), combo u8, pending garbage u8, queue len u8, queue [u8],
+//!            visits u16, visit * visits, chosen u16 (0xffff for none)
+//! visit:     hold u8, kind u8, rotation u8, x i8, y i8, tspin u8, visits u32
+//! ```
+
+use crate::board::{Board, Point, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::bot::BotState;
+use crate::mcts::{Evaluator, Heuristic, Mcts};
+use crate::movegen::Placement;
+use crate::rules::TSpin;
+use crate::simulation::{Event, Input, Rules, Simulation};
+use crate::tetromino::{Color, Kind};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8; 4] = b"TZSP";
+const VERSION: u8 = 1;
+const NO_PIECE: u8 = 7;
+const BOARD_BYTES: usize = (BOARD_WIDTH * BOARD_HEIGHT).div_ceil(8);
+
+/// How a player's game ended.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Ending {
+    Lost,
+    Won,
+    /// Still going when the piece limit was hit.
+    Unfinished,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub ending: Ending,
+    pub pieces: u32,
+    pub lines: u32,
+    pub attack: u32,
+}
+
+/// How often the search went down one plan.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Visit {
+    pub hold: bool,
+    /// Read back without `last_kick`, which only the engine needs.
+    pub placement: Placement,
+    pub visits: u32,
+}
+
+/// One decision: what the player saw, what the search thought and what was played.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MoveRecord {
+    /// Index into the game's outcomes.
+    pub player: u8,
+    pub board: Board,
+    pub current: Option<Kind>,
+    pub hold: Option<Kind>,
+    pub can_hold: bool,
+    pub queue: Vec<Kind>,
+    pub combo: u8,
+    pub back_to_back: bool,
+    pub garbage: u8,
+    pub visits: Vec<Visit>,
+    /// Index into `visits` of the plan played, if the search found any.
+    pub chosen: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    pub seed: u64,
+    pub outcomes: Vec<Outcome>,
+    pub moves: Vec<MoveRecord>,
+}
+
+/// Starts a dataset.
+pub fn write_header(out: &mut impl Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&[VERSION])
+}
+
+impl GameRecord {
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&self.seed.to_le_bytes());
+        buf.push(self.outcomes.len() as u8);
+        for outcome in &self.outcomes {
+            buf.push(match outcome.ending {
+                Ending::Lost => 0,
+                Ending::Won => 1,
+                Ending::Unfinished => 2,
+            });
+            for n in [outcome.pieces, outcome.lines, outcome.attack] {
+                buf.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+        buf.extend_from_slice(&(self.moves.len() as u32).to_le_bytes());
+        for record in &self.moves {
+            record.write(&mut buf);
+        }
+        out.write_all(&(buf.len() as u32).to_le_bytes())?;
+        out.write_all(&buf)
+    }
+}
+
+impl MoveRecord {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.push(self.player);
+        buf.extend_from_slice(&pack_board(&self.board));
+        buf.push(piece_byte(self.current));
+        buf.push(piece_byte(self.hold));
+        buf.push(self.can_hold as u8 | (self.back_to_back as u8) << 1);
+        buf.push(self.combo);
+        buf.push(self.garbage);
+        buf.push(self.queue.len() as u8);
+        buf.extend(self.queue.iter().map(|k| *k as u8));
+        buf.extend_from_slice(&(self.visits.len() as u16).to_le_bytes());
+        for visit in &self.visits {
+            let p = &visit.placement;
+            buf.push(visit.hold as u8);
+            buf.push(p.kind as u8);
+            buf.push(p.rotation);
+            buf.push(p.position.x as i8 as u8);
+            buf.push(p.position.y as i8 as u8);
+            buf.push(p.tspin as u8);
+            buf.extend_from_slice(&visit.visits.to_le_bytes());
+        }
+        let chosen = self.chosen.map_or(u16::MAX, |i| i as u16);
+        buf.extend_from_slice(&chosen.to_le_bytes());
+    }
+}
+
+fn piece_byte(kind: Option<Kind>) -> u8 {
+    kind.map_or(NO_PIECE, |k| k as u8)
+}
+
+fn byte_piece(byte: u8) -> io::Result<Option<Kind>> {
+    match byte {
+        NO_PIECE => Ok(None),
+        _ => Kind::ALL
+            .get(byte as usize)
+            .map(|k| Some(*k))
+            .ok_or_else(|| invalid("bad piece")),
+    }
+}
+
+fn pack_board(board: &Board) -> [u8; BOARD_BYTES] {
+    let mut bytes = [0; BOARD_BYTES];
+    for y in 0..BOARD_HEIGHT {
+        for x in 0..BOARD_WIDTH {
+            if board.cell(x, y).is_some() {
+                let bit = y * BOARD_WIDTH + x;
+                bytes[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+    }
+    bytes
+}
+
+// Cells come back gray; the colors aren't worth the space
+fn unpack_board(bytes: &[u8]) -> Board {
+    let mut board = Board::new();
+    for y in 0..BOARD_HEIGHT {
+        for x in 0..BOARD_WIDTH {
+            let bit = y * BOARD_WIDTH + x;
+            if bytes[bit / 8] & (1 << (bit % 8)) != 0 {
+                board.set_cell(x, y, Some(Color::Gray));
+            }
+        }
+    }
+    board
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Streams the games in a dataset one at a time.
+pub struct Reader<R> {
+    input: R,
+}
+
+impl<R: Read> Reader<R> {
+    /// Checks the header.
+    pub fn new(mut input: R) -> io::Result<Self> {
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid("not a self-play dataset"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid("unsupported dataset version"));
+        }
+        Ok(Reader { input })
+    }
+
+    /// The next game, or None at the end of the data.
+    pub fn next_game(&mut self) -> io::Result<Option<GameRecord>> {
+        let mut len = [0; 4];
+        // Only ending before a game's length is the end of the data
+        let mut read = 0;
+        while read < len.len() {
+            match self.input.read(&mut len[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(invalid("record cut short")),
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let mut buf = vec![0; u32::from_le_bytes(len) as usize];
+        self.input.read_exact(&mut buf)?;
+        Cursor { buf: &buf, at: 0 }.game().map(Some)
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = io::Result<GameRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_game().transpose()
+    }
+}
+
+struct Cursor<'a> {
+    buf: &'a [u8],
+    at: usize,
+}
+
+impl Cursor<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        let bytes = self
+            .buf
+            .get(self.at..self.at + n)
+            .ok_or_else(|| invalid("record cut short"))?;
+        self.at += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn game(&mut self) -> io::Result<GameRecord> {
+        let seed = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        let players = self.u8()?;
+        let mut outcomes = Vec::with_capacity(players as usize);
+        for _ in 0..players {
+            let ending = match self.u8()? {
+                0 => Ending::Lost,
+                1 => Ending::Won,
+                2 => Ending::Unfinished,
+                _ => return Err(invalid("bad ending")),
+            };
+            outcomes.push(Outcome {
+                ending,
+                pieces: self.u32()?,
+                lines: self.u32()?,
+                attack: self.u32()?,
+            });
+        }
+        let count = self.u32()?;
+        let mut moves = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            moves.push(self.record()?);
+        }
+        Ok(GameRecord {
+            seed,
+            outcomes,
+            moves,
+        })
+    }
+
+    fn record(&mut self) -> io::Result<MoveRecord> {
+        let player = self.u8()?;
+        let board = unpack_board(self.take(BOARD_BYTES)?);
+        let current = byte_piece(self.u8()?)?;
+        let hold = byte_piece(self.u8()?)?;
+        let flags = self.u8()?;
+        let combo = self.u8()?;
+        let garbage = self.u8()?;
+        let len = self.u8()?;
+        let mut queue = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            queue.push(byte_piece(self.u8()?)?.ok_or_else(|| invalid("gap in queue"))?);
+        }
+        let count = self.u16()?;
+        let mut visits = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let hold = self.u8()? != 0;
+            let kind = byte_piece(self.u8()?)?.ok_or_else(|| invalid("placement of nothing"))?;
+            let rotation = self.u8()?;
+            let x = self.u8()? as i8 as i32;
+            let y = self.u8()? as i8 as i32;
+            let tspin = match self.u8()? {
+                0 => TSpin::None,
+                1 => TSpin::Mini,
+                2 => TSpin::Full,
+                _ => return Err(invalid("bad t-spin")),
+            };
+            visits.push(Visit {
+                hold,
+                placement: Placement {
+                    kind,
+                    rotation,
+                    position: Point { x, y },
+                    last_kick: None,
+                    tspin,
+                },
+                visits: self.u32()?,
+            });
+        }
+        let chosen = match self.u16()? {
+            u16::MAX => None,
+            i => Some(i as usize),
+        };
+        Ok(MoveRecord {
+            player,
+            board,
+            current,
+            hold,
+            can_hold: flags & 1 != 0,
+            queue,
+            combo,
+            back_to_back: flags & 2 != 0,
+            garbage,
+            visits,
+            chosen,
+        })
+    }
+}
+
+/// How games are played.
+#[derive(Debug, Clone)]
+pub struct SelfPlay<E = Heuristic> {
+    /// Give it an iteration limit for games that repeat from their seeds.
+    pub mcts: Mcts<E>,
+    pub rules: Rules,
+    /// 1 for solo games, more for everyone against everyone with garbage.
+    pub players: usize,
+    /// Games stop, unfinished, once any player has placed this many pieces.
+    pub max_pieces: usize,
+    /// For this many pieces per player, pick plans in proportion to their visits
+    /// rather than always the most visited, so games don't all open the same way.
+    pub sample_moves: usize,
+}
+
+impl Default for SelfPlay {
+    fn default() -> Self {
+        SelfPlay {
+            mcts: Mcts {
+                iterations: Some(200),
+                threads: 1,
+                ..Mcts::default()
+            },
+            rules: Rules::default(),
+            players: 1,
+            max_pieces: 500,
+            sample_moves: 10,
+        }
+    }
+}
+
+impl<E: Evaluator> SelfPlay<E> {
+    /// Plays one game from `seed`. Every player places one piece per frame.
+    pub fn play(&self, seed: u64) -> GameRecord {
+        let mut simulation = Simulation::new(seed, self.rules.clone());
+        let ids: Vec<usize> = (1..=self.players.max(1)).collect();
+        for id in &ids {
+            simulation.add_player(*id);
+        }
+        let mut rng = Pcg32::seed_from_u64(seed);
+        let mut moves = Vec::new();
+        let mut winner = None;
+        loop {
+            let mut inputs = Vec::new();
+            for (player, id) in ids.iter().enumerate() {
+                let Some(state) = simulation.player(*id).filter(|s| s.alive) else {
+                    continue;
+                };
+                let bot = BotState::from_player(state, &self.rules);
+                let choices = self.mcts.search(&bot, rng.gen());
+                let chosen = if state.pieces < self.sample_moves {
+                    sample(
+                        &choices.iter().map(|c| c.visits).collect::<Vec<_>>(),
+                        &mut rng,
+                    )
+                } else {
+                    (!choices.is_empty()).then_some(0)
+                };
+                match chosen {
+                    Some(i) => inputs.extend(choices[i].plan.inputs.iter().map(|i| (*id, *i))),
+                    None => inputs.push((*id, Input::HardDrop)),
+                }
+                moves.push(MoveRecord {
+                    player: player as u8,
+                    board: bot.board.clone(),
+                    current: bot.piece.as_ref().map(|(t, _)| t.kind),
+                    hold: bot.hold,
+                    can_hold: bot.can_hold,
+                    queue: bot.queue.clone(),
+                    combo: bot.chain.combo.min(u8::MAX as usize) as u8,
+                    back_to_back: bot.chain.back_to_back,
+                    garbage: state.pending_lines().min(u8::MAX as usize) as u8,
+                    visits: choices
+                        .iter()
+                        .map(|c| Visit {
+                            hold: c.plan.hold,
+                            placement: c.plan.placement,
+                            visits: c.visits,
+                        })
+                        .collect(),
+                    chosen,
+                });
+            }
+            for event in simulation.step(&inputs) {
+                if let Event::GameOver { winner: w } = event {
+                    winner = w;
+                }
+            }
+            let longest = simulation.players().values().map(|s| s.pieces).max();
+            if simulation.is_over() || longest >= Some(self.max_pieces) {
+                break;
+            }
+        }
+
+        let outcomes = ids
+            .iter()
+            .map(|id| {
+                let state = simulation.player(*id).unwrap();
+                let ending = if winner == Some(*id) {
+                    Ending::Won
+                } else if state.alive && !simulation.is_over() {
+                    Ending::Unfinished
+                } else {
+                    Ending::Lost
+                };
+                Outcome {
+                    ending,
+                    pieces: state.pieces as u32,
+                    lines: state.lines as u32,
+                    attack: state.attack as u32,
+                }
+            })
+            .collect();
+        GameRecord {
+            seed,
+            outcomes,
+            moves,
+        }
+    }
+}
+
+// An index picked with probability proportional to its weight
+fn sample(weights: &[u32], rng: &mut Pcg32) -> Option<usize> {
+    let total: u64 = weights.iter().map(|w| *w as u64).sum();
+    if total == 0 {
+        return (!weights.is_empty()).then_some(0);
+    }
+    let mut pick = rng.gen_range(0..total);
+    weights.iter().position(|w| {
+        if pick < *w as u64 {
+            true
+        } else {
+            pick -= *w as u64;
+            false
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn games() -> Vec<GameRecord> {
+        let selfplay = SelfPlay {
+            mcts: Mcts {
+                iterations: Some(4),
+                threads: 1,
+                ..Mcts::default()
+            },
+            players: 2,
+            max_pieces: 4,
+            ..SelfPlay::default()
+        };
+        vec![selfplay.play(1), selfplay.play(2)]
+    }
+
+    fn dataset(games: &[GameRecord]) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_header(&mut data).unwrap();
+        for game in games {
+            game.write(&mut data).unwrap();
+        }
+        data
+    }
+
+    // What reading back leaves: gray cells and no kicks
+    fn as_read(mut game: GameRecord) -> GameRecord {
+        for record in &mut game.moves {
+            let mut board = Board::new();
+            for y in 0..BOARD_HEIGHT {
+                for x in 0..BOARD_WIDTH {
+                    if record.board.cell(x, y).is_some() {
+                        board.set_cell(x, y, Some(Color::Gray));
+                    }
+                }
+            }
+            record.board = board;
+            for visit in &mut record.visits {
+                visit.placement.last_kick = None;
+            }
+        }
+        game
+    }
+
+    #[test]
+    fn games_read_back_as_written() {
+        let games = games();
+        assert!(games.iter().all(|g| !g.moves.is_empty()));
+        let read: Vec<GameRecord> = Reader::new(dataset(&games).as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(read, games.into_iter().map(as_read).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cut_short_datasets_are_refused() {
+        let games = games();
+        let data = dataset(&games);
+        let first = dataset(&games[..1]).len();
+        // Inside the second game's length, then inside the game itself
+        for end in [first + 2, first + 20, data.len() - 1] {
+            let mut reader = Reader::new(&data[..end]).unwrap();
+            assert!(reader.next_game().unwrap().is_some());
+            let error = reader.next_game().unwrap_err();
+            assert!(matches!(
+                error.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ));
+        }
+        assert!(Reader::new(&data[..3]).is_err());
+    }
+
+    #[test]
+    fn other_files_are_refused() {
+        let mut data = dataset(&games()[..1]);
+        data[4] = VERSION + 1;
+        let error = Reader::new(data.as_slice()).err().unwrap();
+        assert!(error.to_string().contains("version"));
+        data[..4].copy_from_slice(b"PK\x03\x04");
+        let error = Reader::new(data.as_slice()).err().unwrap();
+        assert!(error.to_string().contains("not a self-play dataset"));
+    }
+}
@@ -1,122 +1,580 @@
-pub mod server3 {
-    use crate::game::game::Game;
-    use crate::player::player::Player;
-    use crate::tetromino::tetromino::Direction;
-    use anyhow::Result;
-    use std::{collections::HashMap, sync::Arc};
-    use tokio::{
-        io::{AsyncReadExt, AsyncWriteExt},
-        net::{TcpListener, TcpStream},
-        sync::{Notify, RwLock},
-        time::{sleep, Duration},
+use crate::accounts::{self, Accounts, HISTORY_SHOWN};
+use crate::board::Point;
+use crate::bot::{Bot, BotState, Brain};
+use crate::game::Game;
+use crate::mcts::MctsBot;
+use crate::mode::{GameMode, Match, Summary};
+use crate::opener::OpenerBot;
+use crate::participant::{BotKind, Participant};
+use crate::player::Player;
+use crate::protocol::{ClientMessage, Request, ServerMessage};
+use crate::save;
+use crate::session::Session;
+use crate::simulation::{Event, Input, FRAMES_PER_SECOND};
+use crate::tbp::External;
+use crate::tetromino::Tetromino;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpListener, TcpStream,
+    },
+    sync::{mpsc, Mutex, RwLock},
+    time::{interval, Duration, MissedTickBehavior},
+};
+
+type Inputs = (usize, ClientMessage);
+
+// What every connection's task shares
+struct Server {
+    game: Arc<RwLock<Game>>,
+    // Only ever locked briefly: passwords are hashed outside it, and the file is written
+    // from blocking threads
+    accounts: Option<Arc<Mutex<Accounts>>>,
+    input_tx: mpsc::UnboundedSender<Inputs>,
+    max_players: usize,
+    lobby: Mutex<Lobby>,
+}
+
+// Seats taken so far, and the game loop's end of the input channel until the last one is
+struct Lobby {
+    seated: usize,
+    input_rx: Option<mpsc::UnboundedReceiver<Inputs>>,
+}
+
+/// Runs one match in `session` for `max_players`. The `bots` take the first seats and
+/// people fill the rest; the match starts once every seat is taken. With `accounts`,
+/// people can sign in to keep their results and ask about anyone's. Anyone connected can
+/// ask for a snapshot of the match, as a save.
+pub async fn start_game_server(
+    socket_addr: &str,
+    max_players: usize,
+    bots: &[BotKind],
+    session: Session,
+    accounts: Option<Accounts>,
+) -> Result<()> {
+    let listener = TcpListener::bind(socket_addr).await?;
+    println!("[+] Server is listening on {}", socket_addr);
+    let game = Arc::new(RwLock::new(Game::new(session)));
+    // Every connection forwards its messages here; the game loop drains it once per frame
+    let (input_tx, input_rx) = mpsc::unbounded_channel();
+    let mut lobby = Lobby {
+        seated: 0,
+        input_rx: Some(input_rx),
     };
+    for kind in bots.iter().take(max_players) {
+        lobby.seated += 1;
+        (*(game.write().await)).add_player(lobby.seated, Participant::bot(kind.clone()));
+    }
+    let server = Arc::new(Server {
+        game,
+        accounts: accounts.map(|accounts| Arc::new(Mutex::new(accounts))),
+        input_tx,
+        max_players,
+        lobby: Mutex::new(lobby),
+    });
+    server.start(&mut *server.lobby.lock().await);
 
-    pub async fn start_game_server(socket_addr: &str, max_players: usize) -> Result<()> {
-        let listener = TcpListener::bind(socket_addr).await.unwrap();
-        println!("[+] Server is listening on {}", socket_addr);
-        let connections = Arc::new(RwLock::new(HashMap::new()));
-        let game = Arc::new(RwLock::new(Game::default()));
-        let notifier = Arc::new(Notify::new());
-        let player_count = Arc::new(RwLock::new(0));
-
-        // listener.accept() is the one that keeps returning shit;
-        // while just continuously pattern matches
-        'accept_while: while let Ok((stream, _)) = listener.accept().await {
-            *player_count.write().await += 1;
-
-            if *player_count.read().await > max_players {
-                send(
-                    Arc::new(RwLock::new(stream)),
-                    String::from("Lobby is currently full"),
-                )
-                .await?;
-                break 'accept_while;
+    // listener.accept() is the one that keeps returning shit;
+    // while just continuously pattern matches
+    while let Ok((stream, addr)) = listener.accept().await {
+        println!("[+] New connection: {}", addr);
+        // Each connection in a task of its own, so one that's slow to join or goes wrong
+        // holds up nobody else
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = connect(&server, stream).await {
+                eprintln!("[-] {}: {}", addr, e);
             }
+        });
+    }
+    Ok(())
+}
 
-            println!("[+] New connection: {}", stream.peer_addr()?);
-            let shared_stream = Arc::new(RwLock::new(stream));
-            let cloned_stream = shared_stream.clone();
-            let new_game = game.clone();
-            let new_notifier = notifier.clone();
-            let id = *player_count.read().await + 1; // 1-based
-            (*(connections.write().await)).insert(id, cloned_stream);
-            send(
-                shared_stream.clone(),
-                String::from("Enter your name below:"),
-            )
-            .await?;
-            let name = read(shared_stream.clone()).await.unwrap();
-            (*(game.write().await)).add_player(id, Player::new(name, shared_stream.clone()));
-            if *player_count.read().await == max_players {
-                (*(game.write().await)).start();
-                notifier.notify_one();
+impl Server {
+    // Starts the match if every seat is taken
+    fn start(self: &Arc<Self>, lobby: &mut Lobby) {
+        if lobby.seated < self.max_players {
+            return;
+        }
+        if let Some(input_rx) = lobby.input_rx.take() {
+            tokio::spawn(run_game(
+                self.game.clone(),
+                self.accounts.clone(),
+                self.input_tx.clone(),
+                input_rx,
+            ));
+        }
+    }
+
+    // Sits a player down, returning their id, or why they can't be
+    async fn seat(
+        self: &Arc<Self>,
+        name: &str,
+        signed_in: bool,
+        writer: &Arc<RwLock<OwnedWriteHalf>>,
+    ) -> Result<Result<usize, String>> {
+        let mut lobby = self.lobby.lock().await;
+        if lobby.seated >= self.max_players {
+            return Ok(Err(String::from("Lobby is currently full")));
+        }
+        let mut game = self.game.write().await;
+        // An account sits in one seat at a time, so its matches are recorded once
+        if signed_in && game.players.values().any(|p| p.account() == Some(name)) {
+            return Ok(Err(format!("{} is already playing", name)));
+        }
+        lobby.seated += 1;
+        let id = lobby.seated; // 1-based
+
+        send(writer.clone(), ServerMessage::Welcome { id }.encode()).await?;
+        let player = match signed_in {
+            true => {
+                let account = ServerMessage::Account {
+                    id,
+                    name: name.to_string(),
+                };
+                send(writer.clone(), account.encode()).await?;
+                Player::signed_in(name.to_string(), writer.clone())
             }
+            false => Player::new(name.to_string(), writer.clone()),
+        };
+        game.add_player(id, Participant::Human(player));
+        drop(game);
+        self.start(&mut lobby);
+        Ok(Ok(id))
+    }
+}
 
-            tokio::spawn(async move {
-                new_notifier.notified().await;
-                'alive: loop {
-                    let msg = read(shared_stream.clone())
-                        .await
-                        .unwrap()
-                        .trim()
-                        .to_string();
-                    process_message(&id, &msg, new_game.clone()).await;
-                    if !(*(new_game.read().await)).survivors[&id] {
-                        break 'alive;
-                    }
-                    sleep(Duration::from_millis(100)).await;
+// One connection from start to end: it says who it is, is seated, and then has its
+// messages passed on to the match. From a full lobby it can only ask about accounts
+// and for snapshots.
+async fn connect(server: &Arc<Server>, stream: TcpStream) -> Result<()> {
+    let (reader, writer) = stream.into_split();
+    let writer = Arc::new(RwLock::new(writer));
+    let mut lines = BufReader::new(reader).lines();
+
+    let full = server.lobby.lock().await.seated >= server.max_players;
+    if full {
+        send(writer.clone(), String::from("Lobby is currently full")).await?;
+        // Spectators watch from the sidelines with snapshots
+        while let Some(line) = lines.next_line().await? {
+            if let Some(request) = Request::decode(line.trim()) {
+                for reply in answer(server, &request, None).await {
+                    send(writer.clone(), reply.encode()).await?;
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let prompt = match server.accounts {
+        Some(_) => "Enter your name below, or Login NAME PASSWORD or Register NAME PASSWORD:",
+        None => "Enter your name below:",
+    };
+    send(writer.clone(), String::from(prompt)).await?;
+    // Gone before saying who they are, so no seat is taken
+    let (id, own) = loop {
+        let Some((name, signed_in)) = identify(server, &mut lines, &writer).await? else {
+            return Ok(());
+        };
+        match server.seat(&name, signed_in, &writer).await? {
+            Ok(id) => break (id, signed_in.then_some(name)),
+            Err(reply) => send(writer.clone(), reply).await?,
+        }
+    };
+
+    while let Some(msg) = lines.next_line().await? {
+        let msg = msg.trim();
+        if let Some(request) = Request::decode(msg) {
+            for reply in answer(server, &request, own.as_deref()).await {
+                send(writer.clone(), reply.encode()).await?;
+            }
+        } else if let Some(msg) = ClientMessage::decode(msg) {
+            if server.input_tx.send((id, msg)).is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Reads lines until a connection says who it is: a name to play under, or an account
+// to log in to or register. Queries are answered along the way. Returns the name and
+// whether it's an account, or None if the connection hangs up first.
+async fn identify(
+    server: &Server,
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    writer: &Arc<RwLock<OwnedWriteHalf>>,
+) -> Result<Option<(String, bool)>> {
+    while let Some(line) = lines.next_line().await? {
+        let line = line.trim();
+        let request = Request::decode(line);
+        let reply = match (request, &server.accounts) {
+            (Some(Request::Register { name, password }), Some(accounts)) => {
+                register(accounts.clone(), name, password)
+                    .await
+                    .map_err(|e| format!("Couldn't sign in: {}", e))
+            }
+            (Some(Request::Login { name, password }), Some(accounts)) => {
+                login(accounts, name, password)
+                    .await
+                    .map_err(|e| format!("Couldn't sign in: {}", e))
+            }
+            (Some(request), _) => {
+                for reply in answer(server, &request, None).await {
+                    send(writer.clone(), reply.encode()).await?;
                 }
-            });
+                continue;
+            }
+            // A client whose sign in failed carries on sending inputs, and none are names
+            (None, _) if ClientMessage::decode(line).is_some() => continue,
+            (None, _) if matches!(line.split(' ').next(), Some("Register" | "Login")) => Err(
+                String::from("Usage: Login NAME PASSWORD or Register NAME PASSWORD"),
+            ),
+            // Names with an account are for whoever holds it
+            (None, Some(accounts)) if accounts.lock().await.is_registered(line) => Err(format!(
+                "{0} has an account; sign in with Login {0} PASSWORD",
+                line
+            )),
+            (None, _) => return Ok(Some((line.to_string(), false))),
+        };
+        match reply {
+            Ok(name) => return Ok(Some((name, true))),
+            Err(e) => send(writer.clone(), e).await?,
         }
-        Ok(())
     }
+    Ok(None)
+}
+
+// Makes an account, hashing its password on a blocking thread and saving it on another,
+// and returns its name
+async fn register(
+    accounts: Arc<Mutex<Accounts>>,
+    name: String,
+    password: String,
+) -> Result<String, String> {
+    accounts.lock().await.check_new(&name, &password)?;
+    let hash = blocking(move || accounts::hash_password(&password)).await?;
+    blocking(move || accounts.blocking_lock().add(&name, hash).map(|_| name)).await
+}
+
+// Checks a password on a blocking thread, returning the account's name
+async fn login(
+    accounts: &Mutex<Accounts>,
+    name: String,
+    password: String,
+) -> Result<String, String> {
+    let hash = accounts.lock().await.password(&name)?;
+    blocking(move || accounts::verify_password(&hash, &password)).await?;
+    Ok(name)
+}
+
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, String> + Send + 'static,
+) -> Result<T, String> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|e| Err(e.to_string()))
+}
 
-    async fn process_message(id: &usize, msg: &str, game: Arc<RwLock<Game>>) -> bool {
-        // TODO: Check if dead
-        match msg {
-            "Left" => (*(game.write().await)).move_tetromino(id, -1, 0),
-            "Right" => (*(game.write().await)).move_tetromino(id, 1, 0),
-            "CCW" => (*(game.write().await)).rotate_tetromino(id, Direction::CCW),
-            "RotateRight" => (*(game.write().await)).rotate_tetromino(id, Direction::CW),
-            "Space" => {
-                (*(game.write().await)).drop_tetromino(id);
-                *((*(game.write().await))
-                    .current_tetrominoes
-                    .get_mut(id)
-                    .unwrap()) += 1;
-
-                // locks the tetromino and clears lines
-                let mut cleared_lines = 0usize;
-                let alive = (*(game.write().await)).advance_game(id, &mut cleared_lines);
-                if !alive {
-                    *((*(game.write().await)).survivors.get_mut(id).unwrap()) = false;
-                } else if cleared_lines > 0 {
-                    for (pid, _) in (*(game.read().await)).survivors.clone() {
-                        (*(game.write().await))
-                            .boards
-                            .get_mut(&pid)
-                            .unwrap()
-                            .add_lines(pid);
+// The replies to a request: a snapshot of the match, or what's asked about an account,
+// the named one or `own` if none is named
+async fn answer(server: &Server, request: &Request, own: Option<&str>) -> Vec<ServerMessage> {
+    let text = |text: String| vec![ServerMessage::Text(text)];
+    if *request == Request::Snapshot {
+        let game = server.game.read().await;
+        return vec![ServerMessage::Snapshot(save::encode(&game.session))];
+    }
+    let Some(accounts) = server.accounts.as_deref() else {
+        return text(String::from("This server keeps no accounts"));
+    };
+    let name = match request {
+        Request::History(name) | Request::Bests(name) | Request::Stats(name) => {
+            name.as_deref().or(own)
+        }
+        Request::Register { .. } | Request::Login { .. } => {
+            return text(String::from("Sign in when joining, in place of a name"));
+        }
+        Request::Snapshot => unreachable!("answered above"),
+    };
+    let Some(name) = name else {
+        return text(String::from("Sign in, or ask about an account by name"));
+    };
+    let accounts = accounts.lock().await;
+    let Some(account) = accounts.get(name) else {
+        return text(format!("There's no account {}", name));
+    };
+    let replies: Vec<ServerMessage> = match request {
+        Request::History(_) => {
+            let skip = account.history.len().saturating_sub(HISTORY_SHOWN);
+            account.history[skip..]
+                .iter()
+                .map(|entry| ServerMessage::Match {
+                    name: name.to_string(),
+                    entry: *entry,
+                })
+                .collect()
+        }
+        Request::Bests(_) => account
+            .bests
+            .values()
+            .map(|summary| ServerMessage::Best {
+                name: name.to_string(),
+                summary: *summary,
+            })
+            .collect(),
+        _ => vec![ServerMessage::Stats {
+            name: name.to_string(),
+            stats: account.stats,
+        }],
+    };
+    if replies.is_empty() {
+        return text(format!("{} hasn't played yet", name));
+    }
+    replies
+}
+
+// Adds a match to an account's history in the background, so the game loop never waits
+// on the file
+fn record(accounts: &Option<Arc<Mutex<Accounts>>>, name: &str, entry: Match) {
+    let Some(accounts) = accounts.clone() else {
+        return;
+    };
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = accounts.blocking_lock().record(&name, entry) {
+            eprintln!("[-] Couldn't record {}'s match: {}", name, e);
+        }
+    });
+}
+
+// Steps the simulation at a fixed frame rate with whatever inputs arrived since the last frame
+async fn run_game(
+    game: Arc<RwLock<Game>>,
+    accounts: Option<Arc<Mutex<Accounts>>>,
+    input_tx: mpsc::UnboundedSender<Inputs>,
+    mut input_rx: mpsc::UnboundedReceiver<Inputs>,
+) {
+    {
+        let mut game = game.write().await;
+        let mut names: Vec<(usize, String)> = game
+            .players
+            .iter()
+            .map(|(id, player)| (*id, player.name().to_string()))
+            .collect();
+        names.sort();
+        for (id, name) in names {
+            game.broadcast(&ServerMessage::Player { id, name }.encode())
+                .await;
+        }
+        let greeting = game.session.greeting();
+        game.broadcast(&greeting).await;
+    }
+    let bots: Vec<(usize, BotKind)> = game
+        .read()
+        .await
+        .players
+        .iter()
+        .filter_map(|(id, player)| match player {
+            Participant::Bot { kind, .. } => Some((*id, kind.clone())),
+            Participant::Human(_) => None,
+        })
+        .collect();
+    for (id, kind) in bots {
+        tokio::spawn(run_bot(id, kind, game.clone(), input_tx.clone()));
+    }
+    // When each versus player topped out, for their pace
+    let mut topped_out: HashMap<usize, u64> = HashMap::new();
+    let mut ticker = interval(Duration::from_secs(1) / FRAMES_PER_SECOND);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Burst);
+    loop {
+        ticker.tick().await;
+        let mut game = game.write().await;
+        while let Ok((id, msg)) = input_rx.try_recv() {
+            game.session.receive(id, msg);
+        }
+
+        let (events, messages) = game.session.step();
+
+        for msg in messages {
+            if let ServerMessage::Result { id, summary } = &msg {
+                let name = game.players.get(id).map_or("", |p| p.name()).to_string();
+                println!("[+] {}: {}", name, summary.describe());
+                if let Some(account) = game.players.get(id).and_then(|p| p.account()) {
+                    record(&accounts, account, Match::new(game.players.len(), *summary));
+                }
+            }
+            game.broadcast(&msg.encode()).await;
+        }
+
+        for event in events {
+            match event {
+                Event::ToppedOut { id } => {
+                    topped_out.insert(id, game.session.simulation.frame());
+                    game.broadcast(&ServerMessage::ToppedOut { id }.encode())
+                        .await;
+                    game.send(id, "You topped out").await;
+                }
+                Event::GameOver { winner } => {
+                    let msg = match winner.and_then(|id| game.players.get(&id)) {
+                        Some(player) => format!("Game over: {} wins", player.name()),
+                        None => String::from("Game over"),
+                    };
+                    println!("[+] {}", msg);
+                    game.broadcast(&msg).await;
+                    if game.session.mode == GameMode::Versus {
+                        record_versus(&game, &accounts, winner, &topped_out);
                     }
+                    return;
                 }
-                true
+                _ => {}
             }
-            _ => false,
+        }
+        if game.session.is_over() {
+            println!("[+] Game over");
+            game.broadcast("Game over").await;
+            return;
         }
     }
+}
 
-    pub async fn send(arc_stream: Arc<RwLock<TcpStream>>, msg: String) -> Result<()> {
-        let mut stream = arc_stream.write().await;
-        stream.write_all(msg.as_bytes()).await?;
-        stream.flush().await?;
-        Ok(())
+// Records a finished versus match for everyone in it who signed in
+fn record_versus(
+    game: &Game,
+    accounts: &Option<Arc<Mutex<Accounts>>>,
+    winner: Option<usize>,
+    topped_out: &HashMap<usize, u64>,
+) {
+    let simulation = &game.session.simulation;
+    for (id, player) in &game.players {
+        let (Some(account), Some(state)) = (player.account(), simulation.player(*id)) else {
+            continue;
+        };
+        let summary = Summary {
+            mode: GameMode::Versus,
+            completed: winner == Some(*id),
+            frames: topped_out.get(id).copied().unwrap_or(simulation.frame()),
+            pieces: state.pieces,
+            lines: state.lines,
+            keys: 0,
+            attack: state.attack,
+            score: 0,
+            faults: 0,
+        };
+        record(accounts, account, Match::new(game.players.len(), summary));
     }
+}
 
-    pub async fn read(arc_stream: Arc<RwLock<TcpStream>>) -> Result<String> {
-        let stream = arc_stream.clone(); // Acquire read lock
-        let mut stream_lock = stream.write().await;
-        let mut buffer = Vec::new();
-        stream_lock.read_to_end(&mut buffer).await?;
-        Ok(String::from_utf8(buffer).unwrap())
+// Plays for a bot, feeding its inputs into the game loop the same way a client's arrive
+async fn run_bot(
+    id: usize,
+    kind: BotKind,
+    game: Arc<RwLock<Game>>,
+    input_tx: mpsc::UnboundedSender<Inputs>,
+) {
+    let mut bot: Box<dyn Brain> = match kind {
+        BotKind::Builtin {
+            difficulty,
+            weights,
+            opener,
+        } => {
+            let mut bot = Bot::new(difficulty, rand::random());
+            bot.search.weights = weights;
+            match opener {
+                Some(opener) => Box::new(OpenerBot::new(opener, bot)),
+                None => Box::new(bot),
+            }
+        }
+        BotKind::Network {
+            difficulty,
+            network,
+            threads,
+        } => Box::new(MctsBot::new(
+            difficulty,
+            (*network).clone(),
+            threads,
+            rand::random(),
+        )),
+        BotKind::Tbp(command) => {
+            match tokio::task::spawn_blocking(move || External::spawn(&command)).await {
+                Ok(Ok(external)) => Box::new(external),
+                failed => {
+                    if let Ok(Err(e)) = failed {
+                        eprintln!("[-] Couldn't start bot {}: {}", id, e);
+                    }
+                    // A seat nobody plays would hold the match up forever
+                    let _ = input_tx.send((id, ClientMessage::Input(Input::Forfeit)));
+                    return;
+                }
+            }
+        }
+    };
+    let mut ticker = interval(Duration::from_secs(1) / FRAMES_PER_SECOND);
+    let mut next_frame = 0;
+    // Pieces placed when we last sent a plan, so the same piece isn't played twice
+    let mut planned_at = None;
+    loop {
+        ticker.tick().await;
+        let (frame, pieces, state) = {
+            let game = game.read().await;
+            let simulation = &game.session.simulation;
+            match simulation.player(id) {
+                Some(player) if player.alive && !simulation.is_over() => (
+                    simulation.frame(),
+                    player.pieces,
+                    BotState::from_player(player, &simulation.rules),
+                ),
+                _ => return,
+            }
+        };
+        if frame < next_frame || planned_at == Some(pieces) {
+            continue;
+        }
+        let piece_at = |piece: Option<(&Tetromino, Point)>| {
+            piece.map(|(tetromino, position)| (tetromino.kind, tetromino.rotation, position))
+        };
+        let planned_from = piece_at(state.piece.as_ref().map(|(t, p)| (t, *p)));
+        let Ok((thought, plan)) = tokio::task::spawn_blocking(move || {
+            let plan = bot.think(&state);
+            (bot, plan)
+        })
+        .await
+        else {
+            return;
+        };
+        bot = thought;
+        // With nothing that survives, just drop the piece
+        let inputs = plan.map_or(vec![Input::HardDrop], |plan| plan.inputs);
+        // Holding the lock keeps the game loop from splitting a plan across frames
+        let game = game.read().await;
+        // Gravity may have moved or locked the piece while we were thinking, leaving the
+        // plan for a position that's gone: think again
+        let current = game.session.simulation.player(id).map(|player| {
+            (
+                player.pieces,
+                piece_at(player.alive.then_some((&player.tetromino, player.position))),
+            )
+        });
+        if current != Some((pieces, planned_from)) {
+            continue;
+        }
+        for input in inputs {
+            if input_tx.send((id, ClientMessage::Input(input))).is_err() {
+                return;
+            }
+        }
+        planned_at = Some(pieces);
+        next_frame = frame + bot.frames_per_piece();
     }
 }
+
+pub async fn send(arc_stream: Arc<RwLock<OwnedWriteHalf>>, msg: String) -> Result<()> {
+    let mut stream = arc_stream.write().await;
+    stream.write_all(msg.as_bytes()).await?;
+    stream.write_all(b"\n").await?;
+    stream.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use crate::board::{Board, BOARD_WIDTH};
+use crate::handling::RepeatGuard;
+use crate::mode::{GameMode, Run};
+use crate::opener;
+use crate::protocol::{self, ClientMessage, ServerMessage};
+use crate::puzzle::Puzzle;
+use crate::rules::LockResult;
+use crate::simulation::{Event, Input, Simulation, FRAMES_PER_SECOND};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+#[cfg(feature = "save")]
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// One match's frame loop, apart from how players are connected: client messages go in,
+/// the simulation steps, and out come the messages describing what changed. The server
+/// drives one of these, and so does local play.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Session {
+    pub simulation: Simulation,
+    pub mode: GameMode,
+    /// Each player's run, in solo modes.
+    pub runs: BTreeMap<usize, Run>,
+    /// The field and pieces every player starts from, in puzzle mode.
+    pub puzzle: Option<Puzzle>,
+    seed: u64,
+    // Ordered, as are the openers, so the same session always saves as the same text
+    guards: BTreeMap<usize, RepeatGuard>,
+    // What clients were last sent about each player, so only changes go out. Whoever
+    // picks up a saved session hasn't been sent anything.
+    #[cfg_attr(feature = "save", serde(skip))]
+    sent: HashMap<usize, Vec<String>>,
+    // Inputs for the next step, and whether each was a key press rather than a repeat
+    inputs: Vec<(usize, Input, bool)>,
+    // Holes for the garbage modes put in
+    rng: Pcg32,
+    // Players whose opener has been announced
+    openers: BTreeSet<usize>,
+}
+
+impl Session {
+    pub fn new(seed: u64, mode: GameMode) -> Session {
+        Session {
+            simulation: Simulation::new(seed, mode.rules()),
+            mode,
+            runs: BTreeMap::new(),
+            puzzle: None,
+            seed,
+            guards: BTreeMap::new(),
+            sent: HashMap::new(),
+            inputs: Vec::new(),
+            rng: Pcg32::seed_from_u64(seed ^ 0xc4ee_5e00_c4ee_5e00),
+            openers: BTreeSet::new(),
+        }
+    }
+
+    /// A session of `puzzle`, played as its mode.
+    pub fn with_puzzle(seed: u64, puzzle: Puzzle) -> Session {
+        let mut session = Session::new(seed, puzzle.mode());
+        session.puzzle = Some(puzzle);
+        session
+    }
+
+    pub fn add_player(&mut self, id: usize) {
+        self.simulation.add_player(id);
+        if let Some(puzzle) = &self.puzzle {
+            self.simulation.setup_player(
+                id,
+                puzzle.board.clone(),
+                puzzle.queue(self.seed),
+                puzzle.hold,
+            );
+        }
+        let holes = self.mode.setup(&mut self.rng);
+        self.simulation.raise(id, &holes);
+        if self.mode.is_solo() {
+            self.runs
+                .insert(id, Run::new(self.mode, self.simulation.frame()));
+        }
+    }
+
+    /// Takes a message from player `id`, to be applied on the next step.
+    pub fn receive(&mut self, id: usize, msg: ClientMessage) {
+        if let Some(run) = self.runs.get_mut(&id) {
+            // A finished run is frozen where it ended
+            if run.result.is_some() {
+                return;
+            }
+            if matches!(msg, ClientMessage::Input(_) | ClientMessage::Press(_)) {
+                run.key();
+            }
+        }
+        let frame = self.simulation.frame() + 1;
+        let gravity = self.simulation.rules.gravity;
+        let guard = self.guards.entry(id).or_default();
+        match msg {
+            ClientMessage::Input(input) => self.inputs.push((id, input, true)),
+            ClientMessage::Press(input) => {
+                guard.press(input, frame);
+                self.inputs.push((id, input, true));
+            }
+            ClientMessage::Release(input) => guard.release(input),
+            // Repeats faster than the announced handling allows are dropped
+            ClientMessage::Repeat(input) => {
+                if guard.allow(input, frame, gravity) {
+                    self.inputs.push((id, input, false));
+                }
+            }
+            ClientMessage::Handling(handling) => guard.handling = handling,
+        }
+    }
+
+    /// Advances one frame with everything received since the last. Returns the
+    /// simulation's events and the messages to broadcast.
+    pub fn step(&mut self) -> (Vec<Event>, Vec<ServerMessage>) {
+        let inputs = std::mem::take(&mut self.inputs);
+        let mut events = Vec::new();
+        let next = self.simulation.frame() + 1;
+        for (id, run) in self.runs.iter_mut() {
+            if run.rise_due(next) {
+                let hole = self.rng.gen_range(0..BOARD_WIDTH);
+                events.extend(self.simulation.raise(*id, &[hole]));
+            }
+        }
+        // The field each piece in a run locks on, to judge how it was put there
+        let before: HashMap<usize, (Board, bool)> = self
+            .runs
+            .keys()
+            .filter_map(|id| self.simulation.player(*id).map(|state| (*id, state)))
+            .map(|(id, state)| {
+                let can_hold = self.simulation.rules.hold && !state.hold_used;
+                (id, (state.board.clone(), can_hold))
+            })
+            .collect();
+        let applied: Vec<(usize, Input)> =
+            inputs.iter().map(|(id, input, _)| (*id, *input)).collect();
+        events.extend(self.simulation.step(&applied));
+        let frame = self.simulation.frame();
+
+        let mut messages = Vec::new();
+        let mut reports = Vec::new();
+        let mut results = Vec::new();
+        let mut gravity = None;
+        for (id, state) in self.simulation.players() {
+            if let Some(run) = self.runs.get_mut(id) {
+                let mut locks: Vec<LockResult> = Vec::new();
+                let mut placed = None;
+                for event in &events {
+                    if let Event::PieceLocked {
+                        id: locked,
+                        result,
+                        placement,
+                    } = event
+                    {
+                        if locked == id {
+                            locks.push(*result);
+                            placed = Some(placement);
+                        }
+                    }
+                }
+                let running = run.result.is_none();
+                if let Some((board, can_hold)) = before.get(id).filter(|_| running) {
+                    let pressed: Vec<(Input, bool)> = inputs
+                        .iter()
+                        .filter(|(player, _, _)| player == id)
+                        .map(|(_, input, pressed)| (*input, *pressed))
+                        .collect();
+                    let locked = placed.map(|placement| (board, placement));
+                    if let Some(finesse) = run.finesse.frame(&pressed, *can_hold, locked) {
+                        reports.push(ServerMessage::Finesse { id: *id, finesse });
+                    }
+                }
+                if let Some(summary) = run.update(frame, state, &locks).filter(|_| running) {
+                    results.push(ServerMessage::Result { id: *id, summary });
+                }
+                gravity = gravity.or(self.mode.gravity(self.mode.level(state.lines)));
+            }
+        }
+        // Openers are built in the first two bags, so they're only looked for that long
+        for (id, state) in self.simulation.players() {
+            let locked = events
+                .iter()
+                .any(|e| matches!(e, Event::PieceLocked { id: locked, .. } if locked == id));
+            if !locked || state.pieces > 14 || self.openers.contains(id) {
+                continue;
+            }
+            if let Some((opener, mirrored)) = opener::recognize(&state.board) {
+                self.openers.insert(*id);
+                reports.push(ServerMessage::Opener {
+                    id: *id,
+                    name: opener.title(mirrored),
+                });
+            }
+        }
+        // Levelling up speeds the game up from the next frame
+        if let Some(gravity) = gravity {
+            self.simulation.rules.gravity = gravity;
+        }
+
+        for (id, state) in self.simulation.players() {
+            let mut snapshot = protocol::snapshot(*id, state, &self.simulation.rules);
+            if let Some(run) = self.runs.get(id) {
+                snapshot.push(ServerMessage::Run {
+                    id: *id,
+                    summary: run.summary(frame, state),
+                });
+            }
+            let lines: Vec<String> = snapshot.iter().map(|m| m.encode()).collect();
+            let last = self.sent.entry(*id).or_default();
+            for (i, msg) in snapshot.into_iter().enumerate() {
+                if last.get(i) != Some(&lines[i]) {
+                    messages.push(msg);
+                }
+            }
+            *last = lines;
+        }
+        messages.extend(reports);
+        messages.extend(results);
+        (events, messages)
+    }
+
+    /// What players are told as the match starts.
+    pub fn greeting(&self) -> String {
+        match (&self.puzzle, self.mode) {
+            (Some(puzzle), mode) if !puzzle.name.is_empty() => {
+                format!("{}: {}", puzzle.name, mode.title())
+            }
+            (Some(_), mode) => mode.title(),
+            (None, GameMode::Versus) => String::from("Game started"),
+            (None, mode) => format!("{} started", mode.title()),
+        }
+    }
+
+    /// Whether the match is over: the simulation has ended, or every solo run has.
+    pub fn is_over(&self) -> bool {
+        self.simulation.is_over()
+            || (!self.runs.is_empty() && self.runs.values().all(|run| run.result.is_some()))
+    }
+}
+
+/// Plays `session` alone against the engine on a thread of its own, paced at 60 fps.
+/// The channels carry the same messages a server connection would. Once the player
+/// hangs up, the thread hands back the session as it was left.
+pub fn spawn_local(
+    session: Session,
+    name: &str,
+) -> (
+    Sender<ClientMessage>,
+    Receiver<ServerMessage>,
+    JoinHandle<Session>,
+) {
+    let (input_tx, input_rx) = mpsc::channel();
+    let (tx, rx) = mpsc::channel();
+    let name = name.to_string();
+    let handle = thread::spawn(move || run_local(session, &name, input_rx, tx));
+    (input_tx, rx, handle)
+}
+
+fn run_local(
+    mut session: Session,
+    name: &str,
+    input_rx: Receiver<ClientMessage>,
+    tx: Sender<ServerMessage>,
+) -> Session {
+    let id = 1;
+    // A session picked up from a save already has us
+    if session.simulation.player(id).is_none() {
+        session.add_player(id);
+    }
+    let greeting = [
+        ServerMessage::Welcome { id },
+        ServerMessage::Player {
+            id,
+            name: name.to_string(),
+        },
+        ServerMessage::Text(session.greeting()),
+    ];
+    for msg in greeting {
+        let _ = tx.send(msg);
+    }
+    let frame_time = Duration::from_secs(1) / FRAMES_PER_SECOND;
+    let mut next_frame = Instant::now() + frame_time;
+    loop {
+        thread::sleep(next_frame.saturating_duration_since(Instant::now()));
+        next_frame += frame_time;
+        loop {
+            match input_rx.try_recv() {
+                Ok(msg) => session.receive(id, msg),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return session,
+            }
+        }
+        let (_, messages) = session.step();
+        for msg in messages {
+            if tx.send(msg).is_err() {
+                return session;
+            }
+        }
+        if session.is_over() {
+            let _ = tx.send(ServerMessage::Text(String::from("Game over")));
+            break;
+        }
+    }
+    // Hanging up now would read as a lost connection; wait for the player to leave
+    while input_rx.recv().is_ok() {}
+    session
+}
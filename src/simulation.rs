@@ -0,0 +1,660 @@
+use crate::board::{Board, Point, BOARD_WIDTH, HIDDEN_ROWS};
+use crate::movegen::Placement;
+use crate::rules::{self, Chain, LockResult};
+use crate::tetromino::{Direction, Kind, Tetromino, TetrominoQueue};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+#[cfg(feature = "save")]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+/// A single action a player can take during a frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub enum Input {
+    Left,
+    Right,
+    /// Shift as far left as possible, like a fully charged DAS with instant ARR.
+    DasLeft,
+    DasRight,
+    SoftDrop,
+    /// Soft drop all the way down without locking.
+    SonicDrop,
+    HardDrop,
+    RotateCW,
+    RotateCCW,
+    Rotate180,
+    Hold,
+    /// Give up and top out.
+    Forfeit,
+    /// Send garbage to the next surviving opponent instead.
+    NextTarget,
+}
+
+/// Things that happened during a frame, in the order they happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    PieceLocked {
+        id: usize,
+        result: LockResult,
+        /// Where the piece went.
+        placement: Placement,
+    },
+    GarbageSent {
+        from: usize,
+        to: usize,
+        lines: usize,
+    },
+    GarbageReceived {
+        id: usize,
+        lines: usize,
+    },
+    ToppedOut {
+        id: usize,
+    },
+    GameOver {
+        winner: Option<usize>,
+    },
+}
+
+/// Timings are in frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Rules {
+    /// Frames per row of gravity; 0 drops pieces straight to the floor.
+    pub gravity: u32,
+    pub lock_delay: u32,
+    /// How many times moving or rotating on the ground may restart the lock delay.
+    pub lock_resets: u32,
+    pub previews: usize,
+    pub hold: bool,
+}
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            gravity: 60,
+            lock_delay: 30,
+            lock_resets: 15,
+            previews: 5,
+            hold: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct PlayerState {
+    pub board: Board,
+    pub queue: TetrominoQueue,
+    pub tetromino: Tetromino,
+    pub position: Point,
+    pub hold: Option<Tetromino>,
+    pub hold_used: bool,
+    pub chain: Chain,
+    /// Incoming garbage as (lines, hole column), applied on the next lock that doesn't clear.
+    pub pending_garbage: Vec<(usize, usize)>,
+    pub alive: bool,
+    /// Who our garbage goes to.
+    pub target: Option<usize>,
+    pub pieces: usize,
+    pub lines: usize,
+    pub attack: usize,
+    /// Rows pieces have been moved down by soft and by hard drops, for scoring.
+    pub soft_dropped: usize,
+    pub hard_dropped: usize,
+    gravity_timer: u32,
+    lock_timer: u32,
+    lock_resets: u32,
+    lowest_y: i32,
+    last_kick: Option<usize>,
+}
+
+impl PlayerState {
+    fn new(mut queue: TetrominoQueue) -> Self {
+        let tetromino = queue.pop();
+        let mut state = PlayerState {
+            board: Board::new(),
+            queue,
+            tetromino,
+            position: Point::default(),
+            hold: None,
+            hold_used: false,
+            chain: Chain::default(),
+            pending_garbage: Vec::new(),
+            alive: true,
+            target: None,
+            pieces: 0,
+            lines: 0,
+            attack: 0,
+            soft_dropped: 0,
+            hard_dropped: 0,
+            gravity_timer: 0,
+            lock_timer: 0,
+            lock_resets: 0,
+            lowest_y: 0,
+            last_kick: None,
+        };
+        state.place_new_tetromino();
+        state
+    }
+
+    /// Where the current tetromino would land if hard dropped.
+    pub fn ghost_position(&self) -> Point {
+        let mut ghost = self.position;
+        while !self.board.collision_test(
+            &self.tetromino,
+            Point {
+                x: ghost.x,
+                y: ghost.y + 1,
+            },
+        ) {
+            ghost.y += 1;
+        }
+        ghost
+    }
+
+    pub fn pending_lines(&self) -> usize {
+        self.pending_garbage.iter().map(|(lines, _)| lines).sum()
+    }
+
+    // Places current tetromino at the top of the board; returns false if it doesn't fit
+    fn place_new_tetromino(&mut self) -> bool {
+        self.position = spawn_position(&self.tetromino);
+        self.gravity_timer = 0;
+        self.lock_timer = 0;
+        self.lock_resets = 0;
+        self.lowest_y = self.position.y;
+        self.last_kick = None;
+        !self.board.collision_test(&self.tetromino, self.position)
+    }
+
+    fn grounded(&self) -> bool {
+        self.board.collision_test(
+            &self.tetromino,
+            Point {
+                x: self.position.x,
+                y: self.position.y + 1,
+            },
+        )
+    }
+
+    fn move_tetromino(&mut self, x: i32, y: i32) -> bool {
+        let new_position = Point {
+            x: self.position.x + x,
+            y: self.position.y + y,
+        };
+        let collided = self.board.collision_test(&self.tetromino, new_position);
+        if !collided {
+            self.position = new_position;
+            self.last_kick = None;
+            if self.position.y > self.lowest_y {
+                self.lowest_y = self.position.y;
+                self.lock_resets = 0;
+            }
+        }
+        !collided
+    }
+
+    fn rotate_tetromino(&mut self, direction: Direction) -> bool {
+        match rules::try_rotate(&self.board, &self.tetromino, self.position, direction) {
+            Some((tetromino, position, kick)) => {
+                self.tetromino = tetromino;
+                self.position = position;
+                self.last_kick = Some(kick);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Moving or rotating a grounded piece restarts the lock delay, a limited number of times
+    fn reset_lock(&mut self, rules: &Rules) {
+        if self.lock_resets < rules.lock_resets {
+            self.lock_timer = 0;
+            self.lock_resets += 1;
+        }
+    }
+
+    fn hold(&mut self) -> bool {
+        // Held pieces go back to their spawn rotation
+        let current = Tetromino::new(self.tetromino.kind);
+        match self.hold.replace(current) {
+            Some(held) => self.tetromino = held,
+            None => self.tetromino = self.queue.pop(),
+        }
+        self.hold_used = true;
+        self.place_new_tetromino()
+    }
+}
+
+/// Where a new piece appears: centered, just above the visible field.
+pub fn spawn_position(tetromino: &Tetromino) -> Point {
+    Point {
+        x: ((BOARD_WIDTH - tetromino.shape.len()) / 2) as i32,
+        y: HIDDEN_ROWS as i32 - 2,
+    }
+}
+
+/// The rules engine: advances every player's game one frame at a time.
+/// Given the same seed, rules and inputs it always produces the same events.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Simulation {
+    pub rules: Rules,
+    seed: u64,
+    frame: u64,
+    players: BTreeMap<usize, PlayerState>,
+    rng: Pcg32,
+    started_with: usize,
+    over: bool,
+}
+
+impl Simulation {
+    pub fn new(seed: u64, rules: Rules) -> Self {
+        Simulation {
+            rules,
+            seed,
+            frame: 0,
+            players: BTreeMap::new(),
+            // Garbage holes come from their own stream so they don't disturb the pieces
+            rng: Pcg32::seed_from_u64(seed ^ 0x9e37_79b9_7f4a_7c15),
+            started_with: 0,
+            over: false,
+        }
+    }
+
+    /// Every player is dealt the same piece sequence.
+    pub fn add_player(&mut self, id: usize) {
+        let queue = TetrominoQueue::with_seed(self.seed);
+        self.players.insert(id, PlayerState::new(queue));
+        self.started_with = self.players.len();
+        // Everyone starts out attacking whoever comes after them
+        for state in self.players.values_mut() {
+            state.target = None;
+        }
+        self.retarget();
+    }
+
+    /// Starts a player from a prepared field, pieces and hold instead of an empty board,
+    /// for puzzles and drills. Meant for before the first step.
+    pub fn setup_player(
+        &mut self,
+        id: usize,
+        board: Board,
+        queue: TetrominoQueue,
+        hold: Option<Kind>,
+    ) {
+        let Some(state) = self.players.get_mut(&id) else {
+            return;
+        };
+        let target = state.target;
+        *state = PlayerState::new(queue);
+        state.target = target;
+        state.board = board;
+        state.hold = hold.map(Tetromino::new);
+        if !state.place_new_tetromino() {
+            state.alive = false;
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.over
+    }
+
+    pub fn player(&self, id: usize) -> Option<&PlayerState> {
+        self.players.get(&id)
+    }
+
+    pub fn players(&self) -> &BTreeMap<usize, PlayerState> {
+        &self.players
+    }
+
+    /// Queues garbage for a player from outside the match, with a hole from the same
+    /// stream as garbage players send each other.
+    pub fn queue_garbage(&mut self, id: usize, lines: usize) {
+        let hole = self.rng.gen_range(0..BOARD_WIDTH);
+        if let Some(state) = self.players.get_mut(&id) {
+            state.pending_garbage.push((lines, hole));
+        }
+    }
+
+    /// Pushes garbage straight into a player's board instead of queueing it, one line
+    /// per hole from the bottom up. The falling piece is lifted clear of the stack if it
+    /// can be, and a player pushed out of the field tops out.
+    pub fn raise(&mut self, id: usize, holes: &[usize]) -> Vec<Event> {
+        let mut events = Vec::new();
+        let Some(state) = self.players.get_mut(&id) else {
+            return events;
+        };
+        if self.over || !state.alive {
+            return events;
+        }
+        for hole in holes {
+            state.board.add_lines(1, *hole);
+        }
+        let mut lifted = 0;
+        while lifted < holes.len() && state.board.collision_test(&state.tetromino, state.position) {
+            state.position.y -= 1;
+            lifted += 1;
+        }
+        if state.board.lost() || state.board.collision_test(&state.tetromino, state.position) {
+            state.alive = false;
+            events.push(Event::ToppedOut { id });
+            self.check_game_over(&mut events);
+        }
+        events
+    }
+
+    /// Advances one frame. Inputs are applied per player in id order, and in the
+    /// order given for each player.
+    pub fn step(&mut self, inputs: &[(usize, Input)]) -> Vec<Event> {
+        let mut events = Vec::new();
+        if self.over {
+            return events;
+        }
+        self.frame += 1;
+
+        let mut attacks = Vec::new();
+        let ids: Vec<usize> = self.players.keys().copied().collect();
+        let alive = self.alive_ids();
+        for id in &ids {
+            let rules = self.rules.clone();
+            let state = self.players.get_mut(id).unwrap();
+            if !state.alive {
+                continue;
+            }
+            let mut locked = None;
+            for (_, input) in inputs.iter().filter(|(pid, _)| pid == id) {
+                if *input == Input::NextTarget {
+                    let after = state.target.unwrap_or(*id);
+                    state.target = next_alive(&alive, after, *id);
+                    continue;
+                }
+                locked = apply_input(state, &rules, *input);
+                if locked.is_some() || !state.alive {
+                    break;
+                }
+            }
+            if !state.alive {
+                events.push(Event::ToppedOut { id: *id });
+                continue;
+            }
+            if locked.is_none() {
+                locked = apply_gravity(state, &rules);
+            }
+            if let Some(kick) = locked {
+                let attack = self.lock(*id, kick, &mut events);
+                if attack > 0 {
+                    attacks.push((*id, attack));
+                }
+            }
+        }
+        self.retarget();
+        self.send_garbage(attacks, &mut events);
+        self.check_game_over(&mut events);
+        events
+    }
+
+    fn alive_ids(&self) -> Vec<usize> {
+        self.players
+            .iter()
+            .filter(|(_, state)| state.alive)
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    // Anyone without a living target goes after the next surviving player after them
+    fn retarget(&mut self) {
+        let alive = self.alive_ids();
+        for (id, state) in self.players.iter_mut() {
+            let valid = state.target.is_some_and(|t| t != *id && alive.contains(&t));
+            if !valid {
+                state.target = next_alive(&alive, *id, *id);
+            }
+        }
+    }
+
+    // Locks the current piece, applies or cancels garbage and spawns the next piece.
+    // Returns the attack left over after cancelling.
+    fn lock(&mut self, id: usize, last_kick: Option<usize>, events: &mut Vec<Event>) -> usize {
+        let state = self.players.get_mut(&id).unwrap();
+        let result = rules::lock(
+            &mut state.board,
+            &state.tetromino,
+            state.position,
+            last_kick,
+            &mut state.chain,
+        );
+        state.pieces += 1;
+        state.lines += result.lines;
+        state.attack += result.attack;
+        let placement = Placement {
+            kind: state.tetromino.kind,
+            rotation: state.tetromino.rotation,
+            position: state.position,
+            last_kick,
+            tspin: result.tspin,
+        };
+        events.push(Event::PieceLocked {
+            id,
+            result,
+            placement,
+        });
+
+        let mut attack = result.attack;
+        if result.lines > 0 {
+            // Outgoing attack cancels incoming garbage first
+            while attack > 0 && !state.pending_garbage.is_empty() {
+                let cancelled = attack.min(state.pending_garbage[0].0);
+                attack -= cancelled;
+                state.pending_garbage[0].0 -= cancelled;
+                if state.pending_garbage[0].0 == 0 {
+                    state.pending_garbage.remove(0);
+                }
+            }
+        } else if !state.pending_garbage.is_empty() {
+            let lines = state.pending_lines();
+            for (n, hole) in state.pending_garbage.drain(..) {
+                state.board.add_lines(n, hole);
+            }
+            events.push(Event::GarbageReceived { id, lines });
+        }
+
+        state.tetromino = state.queue.pop();
+        state.hold_used = false;
+        if state.board.lost() || !state.place_new_tetromino() {
+            state.alive = false;
+            events.push(Event::ToppedOut { id });
+        }
+        attack
+    }
+
+    fn send_garbage(&mut self, attacks: Vec<(usize, usize)>, events: &mut Vec<Event>) {
+        for (from, lines) in attacks {
+            let Some(to) = self.players[&from].target else {
+                continue;
+            };
+            let hole = self.rng.gen_range(0..BOARD_WIDTH);
+            self.players
+                .get_mut(&to)
+                .unwrap()
+                .pending_garbage
+                .push((lines, hole));
+            events.push(Event::GarbageSent { from, to, lines });
+        }
+    }
+
+    fn check_game_over(&mut self, events: &mut Vec<Event>) {
+        let alive = self.alive_ids();
+        let last_standing = self.started_with > 1 && alive.len() <= 1;
+        if last_standing || alive.is_empty() {
+            self.over = true;
+            events.push(Event::GameOver {
+                winner: if self.started_with > 1 {
+                    alive.first().copied()
+                } else {
+                    None
+                },
+            });
+        }
+    }
+}
+
+// The first surviving player after `after` in id order, wrapping around and skipping `skip`
+fn next_alive(alive: &[usize], after: usize, skip: usize) -> Option<usize> {
+    let others = alive.iter().copied().filter(|id| *id != skip);
+    others
+        .clone()
+        .find(|id| *id > after)
+        .or_else(|| others.clone().next())
+}
+
+// Applies a single input. Returns Some(last kick) if the piece should lock now.
+fn apply_input(state: &mut PlayerState, rules: &Rules, input: Input) -> Option<Option<usize>> {
+    let moved = match input {
+        Input::Left => state.move_tetromino(-1, 0),
+        Input::Right => state.move_tetromino(1, 0),
+        Input::DasLeft => {
+            let mut moved = false;
+            while state.move_tetromino(-1, 0) {
+                moved = true;
+            }
+            moved
+        }
+        Input::DasRight => {
+            let mut moved = false;
+            while state.move_tetromino(1, 0) {
+                moved = true;
+            }
+            moved
+        }
+        Input::SoftDrop => {
+            let moved = state.move_tetromino(0, 1);
+            if moved {
+                state.gravity_timer = 0;
+                state.soft_dropped += 1;
+            }
+            return None;
+        }
+        Input::SonicDrop => {
+            while state.move_tetromino(0, 1) {
+                state.gravity_timer = 0;
+                state.soft_dropped += 1;
+            }
+            return None;
+        }
+        Input::HardDrop => {
+            while state.move_tetromino(0, 1) {
+                state.hard_dropped += 1;
+            }
+            return Some(state.last_kick);
+        }
+        Input::RotateCW => state.rotate_tetromino(Direction::CW),
+        Input::RotateCCW => state.rotate_tetromino(Direction::CCW),
+        Input::Rotate180 => state.rotate_tetromino(Direction::Half),
+        Input::Hold => {
+            if rules.hold && !state.hold_used && !state.hold() {
+                // The swapped in piece doesn't fit
+                state.alive = false;
+            }
+            return None;
+        }
+        Input::Forfeit => {
+            state.alive = false;
+            return None;
+        }
+        // Handled by the simulation, which knows who is still alive
+        Input::NextTarget => return None,
+    };
+    if moved && state.grounded() {
+        state.reset_lock(rules);
+    }
+    None
+}
+
+// Gravity and lock delay. Returns Some(last kick) if the piece should lock now.
+fn apply_gravity(state: &mut PlayerState, rules: &Rules) -> Option<Option<usize>> {
+    if rules.gravity == 0 {
+        while state.move_tetromino(0, 1) {}
+    } else {
+        state.gravity_timer += 1;
+        if state.gravity_timer >= rules.gravity {
+            state.gravity_timer = 0;
+            state.move_tetromino(0, 1);
+        }
+    }
+    if state.grounded() {
+        state.lock_timer += 1;
+        if state.lock_timer >= rules.lock_delay {
+            return Some(state.last_kick);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two players trading garbage on inputs drawn from `seed`
+    fn play(seed: u64) -> (Simulation, Vec<Event>) {
+        let mut simulation = Simulation::new(seed, Rules::default());
+        simulation.add_player(1);
+        simulation.add_player(2);
+        let mut rng = Pcg32::seed_from_u64(0);
+        let choices = [
+            Input::Left,
+            Input::Right,
+            Input::SoftDrop,
+            Input::RotateCW,
+            Input::RotateCCW,
+            Input::Hold,
+            Input::HardDrop,
+        ];
+        let mut events = Vec::new();
+        for _ in 0..2000 {
+            let mut inputs = Vec::new();
+            for id in [1, 2] {
+                if rng.gen_bool(0.3) {
+                    inputs.push((id, choices[rng.gen_range(0..choices.len())]));
+                }
+            }
+            events.extend(simulation.step(&inputs));
+        }
+        (simulation, events)
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let (ours, our_events) = play(5);
+        let (theirs, their_events) = play(5);
+        assert!(our_events
+            .iter()
+            .any(|e| matches!(e, Event::PieceLocked { .. })));
+        assert_eq!(our_events, their_events);
+        assert_eq!(ours.frame(), theirs.frame());
+        for (id, state) in ours.players() {
+            let other = theirs.player(*id).unwrap();
+            let kinds = |s: &PlayerState| {
+                s.queue
+                    .preview(14)
+                    .iter()
+                    .map(|t| t.kind)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(state.board, other.board);
+            assert_eq!(kinds(state), kinds(other));
+            assert_eq!(state.pending_garbage, other.pending_garbage);
+            assert_eq!(
+                (state.pieces, state.lines, state.attack),
+                (other.pieces, other.lines, other.attack)
+            );
+        }
+    }
+}
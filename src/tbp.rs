@@ -0,0 +1,563 @@
+use crate::board::{Board, Point, BOARD_HEIGHT, BOARD_WIDTH};
+use crate::bot::{BotState, Brain, Plan};
+use crate::movegen::{sorted_cells, Placement};
+use crate::protocol::{char_color, color_char};
+use crate::rules::{Chain, TSpin};
+use crate::tetromino::Kind;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+// The Tetris Bot Protocol: one JSON message per line, frontend to bot on the bot's
+// stdin and bot to frontend on its stdout. TBP boards are 40 rows with row 0 at the
+// bottom, and pieces are placed by their rotation center.
+
+const TBP_ROWS: usize = 40;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Piece {
+    I,
+    O,
+    T,
+    L,
+    J,
+    S,
+    Z,
+}
+
+impl From<Kind> for Piece {
+    fn from(kind: Kind) -> Self {
+        match kind {
+            Kind::I => Piece::I,
+            Kind::O => Piece::O,
+            Kind::T => Piece::T,
+            Kind::L => Piece::L,
+            Kind::J => Piece::J,
+            Kind::S => Piece::S,
+            Kind::Z => Piece::Z,
+        }
+    }
+}
+
+impl From<Piece> for Kind {
+    fn from(piece: Piece) -> Self {
+        match piece {
+            Piece::I => Kind::I,
+            Piece::O => Kind::O,
+            Piece::T => Kind::T,
+            Piece::L => Kind::L,
+            Piece::J => Kind::J,
+            Piece::S => Kind::S,
+            Piece::Z => Kind::Z,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    North,
+    East,
+    South,
+    West,
+}
+
+const ORIENTATIONS: [Orientation; 4] = [
+    Orientation::North,
+    Orientation::East,
+    Orientation::South,
+    Orientation::West,
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Spin {
+    None,
+    Mini,
+    Full,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    #[serde(rename = "type")]
+    pub piece: Piece,
+    pub orientation: Orientation,
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Move {
+    pub location: Location,
+    pub spin: Spin,
+}
+
+/// The game a bot is asked to play from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Start {
+    pub hold: Option<Piece>,
+    /// The piece in play first, then the previews.
+    pub queue: Vec<Piece>,
+    pub combo: u32,
+    pub back_to_back: bool,
+    pub board: Vec<Vec<Option<char>>>,
+}
+
+/// Frontend to bot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FrontendMessage {
+    Rules {},
+    Start(Start),
+    Stop,
+    Suggest,
+    Play {
+        #[serde(rename = "move")]
+        mv: Move,
+    },
+    NewPiece {
+        piece: Piece,
+    },
+    Quit,
+}
+
+/// Bot to frontend.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotMessage {
+    Info {
+        name: String,
+        version: String,
+        author: String,
+        features: Vec<String>,
+    },
+    Ready,
+    Error {
+        reason: String,
+    },
+    Suggestion {
+        moves: Vec<Move>,
+    },
+}
+
+// Cells relative to the rotation center facing north, with y up
+fn north_cells(kind: Kind) -> [(i32, i32); 4] {
+    match kind {
+        Kind::I => [(-1, 0), (0, 0), (1, 0), (2, 0)],
+        Kind::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        Kind::T => [(-1, 0), (0, 0), (1, 0), (0, 1)],
+        Kind::L => [(-1, 0), (0, 0), (1, 0), (1, 1)],
+        Kind::J => [(-1, 0), (0, 0), (1, 0), (-1, 1)],
+        Kind::S => [(-1, 0), (0, 0), (0, 1), (1, 1)],
+        Kind::Z => [(-1, 1), (0, 1), (0, 0), (1, 0)],
+    }
+}
+
+// Board cells (y down) a piece covers with its center at (x, y) in TBP coordinates
+fn location_cells(location: &Location) -> [Point; 4] {
+    let rotations = ORIENTATIONS
+        .iter()
+        .position(|o| *o == location.orientation)
+        .unwrap();
+    north_cells(location.piece.into()).map(|(mut x, mut y)| {
+        for _ in 0..rotations {
+            (x, y) = (y, -x);
+        }
+        Point {
+            x: location.x + x,
+            y: BOARD_HEIGHT as i32 - 1 - (location.y + y),
+        }
+    })
+}
+
+/// A placement as TBP describes it.
+pub fn encode_move(placement: &Placement) -> Move {
+    let cells = sorted_cells(placement.cells());
+    let mut location = Location {
+        piece: placement.kind.into(),
+        orientation: ORIENTATIONS[placement.rotation as usize],
+        x: 0,
+        y: 0,
+    };
+    // Line the first cell up with the same cell of the piece centered at the origin
+    let offset = sorted_cells(location_cells(&location))[0];
+    location.x = cells[0].x - offset.x;
+    location.y = offset.y - cells[0].y;
+    let spin = match placement.tspin {
+        TSpin::None => Spin::None,
+        TSpin::Mini => Spin::Mini,
+        TSpin::Full => Spin::Full,
+    };
+    Move { location, spin }
+}
+
+/// The plan among `plans` that ends where `mv` says, preferring one with the same spin.
+pub fn find_plan(plans: Vec<Plan>, mv: &Move) -> Option<Plan> {
+    let target = sorted_cells(location_cells(&mv.location));
+    let kind: Kind = mv.location.piece.into();
+    let mut matching: Vec<Plan> = plans
+        .into_iter()
+        .filter(|plan| {
+            plan.placement.kind == kind && sorted_cells(plan.placement.cells()) == target
+        })
+        .collect();
+    let same_spin = matching
+        .iter()
+        .position(|plan| encode_move(&plan.placement).spin == mv.spin);
+    match same_spin {
+        Some(i) => Some(matching.swap_remove(i)),
+        None => matching.into_iter().next(),
+    }
+}
+
+pub fn encode_board(board: &Board) -> Vec<Vec<Option<char>>> {
+    (0..TBP_ROWS)
+        .map(|row| {
+            (0..BOARD_WIDTH)
+                .map(|x| {
+                    let cell = (row < BOARD_HEIGHT)
+                        .then(|| board.cell(x, BOARD_HEIGHT - 1 - row))
+                        .flatten();
+                    // Anything that isn't a piece color counts as garbage
+                    cell.map(|color| match color_char(Some(color)) {
+                        '#' => 'G',
+                        c => c,
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Rows above our board's height must be empty.
+pub fn decode_board(rows: &[Vec<Option<char>>]) -> Option<Board> {
+    let mut board = Board::new();
+    for (row, cells) in rows.iter().enumerate() {
+        for (x, cell) in cells.iter().enumerate().take(BOARD_WIDTH) {
+            let Some(c) = cell else { continue };
+            if row >= BOARD_HEIGHT {
+                return None;
+            }
+            let color = char_color(*c).flatten().or(char_color('G').flatten());
+            board.set_cell(x, BOARD_HEIGHT - 1 - row, color);
+        }
+    }
+    Some(board)
+}
+
+/// The start message for a bot about to play from `state`.
+pub fn start_message(state: &BotState) -> Start {
+    let current = state.piece.as_ref().map(|(t, _)| t.kind);
+    Start {
+        hold: state.hold.map(Piece::from),
+        queue: current
+            .into_iter()
+            .chain(state.queue.iter().copied())
+            .map(Piece::from)
+            .collect(),
+        combo: state.chain.combo as u32,
+        back_to_back: state.chain.back_to_back,
+        board: encode_board(&state.board),
+    }
+}
+
+/// The state a start message describes, with the first queued piece at its spawn.
+pub fn start_state(start: &Start) -> Option<BotState> {
+    let mut state = BotState {
+        board: decode_board(&start.board)?,
+        chain: Chain {
+            combo: start.combo as usize,
+            back_to_back: start.back_to_back,
+        },
+        piece: None,
+        hold: start.hold.map(Kind::from),
+        hold_enabled: true,
+        can_hold: true,
+        queue: Vec::new(),
+        // Nothing says where the bag stands, so assume a fresh one
+        bag: Vec::new(),
+    };
+    for piece in &start.queue {
+        state.deal((*piece).into());
+    }
+    Some(state)
+}
+
+fn write_message(out: &mut impl Write, msg: &impl Serialize) -> io::Result<()> {
+    let line = serde_json::to_string(msg).map_err(io::Error::other)?;
+    writeln!(out, "{}", line)?;
+    out.flush()
+}
+
+/// Serves `brain` over TBP until told to quit or the input ends.
+pub fn serve(brain: &mut dyn Brain, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+    write_message(
+        &mut output,
+        &BotMessage::Info {
+            name: String::from("TetrisZero"),
+            version: String::from(env!("CARGO_PKG_VERSION")),
+            author: String::from("TetrisZero contributors"),
+            features: Vec::new(),
+        },
+    )?;
+    let mut state: Option<BotState> = None;
+    for line in input.lines() {
+        // Messages we don't understand are ignored, as the protocol asks
+        let Ok(msg) = serde_json::from_str::<FrontendMessage>(&line?) else {
+            continue;
+        };
+        match msg {
+            FrontendMessage::Rules {} => write_message(&mut output, &BotMessage::Ready)?,
+            FrontendMessage::Start(start) => state = start_state(&start),
+            FrontendMessage::Stop => state = None,
+            FrontendMessage::Suggest => {
+                let moves = state
+                    .as_ref()
+                    .and_then(|state| brain.think(state))
+                    .map(|plan| vec![encode_move(&plan.placement)])
+                    .unwrap_or_default();
+                write_message(&mut output, &BotMessage::Suggestion { moves })?;
+            }
+            FrontendMessage::Play { mv } => {
+                state = state.and_then(|s| find_plan(s.plans(), &mv).map(|plan| s.play(&plan).0));
+            }
+            FrontendMessage::NewPiece { piece } => {
+                if let Some(state) = state.as_mut() {
+                    state.deal(piece.into());
+                }
+            }
+            FrontendMessage::Quit => break,
+        }
+    }
+    Ok(())
+}
+
+/// A TBP bot running as a child process, playing through our `Brain` interface.
+pub struct External {
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    // What the bot thinks the game looks like, if it has been started
+    believed: Option<BotState>,
+}
+
+impl External {
+    /// Starts `command` (split on whitespace) and waits until it is ready to play.
+    pub fn spawn(command: &str) -> io::Result<External> {
+        let mut parts = command.split_whitespace();
+        let program = parts
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty bot command"))?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut bot = External {
+            name: String::from(program),
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            believed: None,
+        };
+        if let BotMessage::Info { name, .. } = bot.receive()? {
+            bot.name = name;
+        }
+        bot.send(&FrontendMessage::Rules {})?;
+        match bot.receive()? {
+            BotMessage::Ready => Ok(bot),
+            BotMessage::Error { reason } => Err(io::Error::other(reason)),
+            other => Err(io::Error::other(format!("expected ready, got {:?}", other))),
+        }
+    }
+
+    fn send(&mut self, msg: &FrontendMessage) -> io::Result<()> {
+        write_message(&mut self.stdin, msg)
+    }
+
+    fn receive(&mut self) -> io::Result<BotMessage> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "bot closed its output",
+                ));
+            }
+            if let Ok(msg) = serde_json::from_str(&line) {
+                return Ok(msg);
+            }
+        }
+    }
+
+    // Brings the bot up to date: new pieces if that's all that changed, else a restart
+    fn sync(&mut self, state: &BotState) -> io::Result<()> {
+        let current = |s: &BotState| s.piece.as_ref().map(|(t, _)| t.kind);
+        let new_pieces = self.believed.as_ref().and_then(|believed| {
+            let same = believed.board == state.board
+                && believed.hold == state.hold
+                && believed.chain == state.chain
+                && current(believed) == current(state)
+                && state.queue.starts_with(&believed.queue);
+            same.then(|| state.queue[believed.queue.len()..].to_vec())
+        });
+        match new_pieces {
+            Some(pieces) => {
+                for piece in pieces {
+                    self.send(&FrontendMessage::NewPiece {
+                        piece: piece.into(),
+                    })?;
+                }
+            }
+            None => {
+                if self.believed.is_some() {
+                    self.send(&FrontendMessage::Stop)?;
+                }
+                self.send(&FrontendMessage::Start(start_message(state)))?;
+            }
+        }
+        self.believed = Some(state.clone());
+        Ok(())
+    }
+
+    fn suggest(&mut self, state: &BotState) -> io::Result<Option<Plan>> {
+        self.sync(state)?;
+        self.send(&FrontendMessage::Suggest)?;
+        let BotMessage::Suggestion { moves } = self.receive()? else {
+            return Ok(None);
+        };
+        // The first suggestion we can actually reach
+        let plan = moves
+            .iter()
+            .find_map(|mv| find_plan(state.plans(), mv).map(|plan| (*mv, plan)));
+        let Some((mv, plan)) = plan else {
+            return Ok(None);
+        };
+        self.send(&FrontendMessage::Play { mv })?;
+        self.believed = Some(state.play(&plan).0);
+        Ok(Some(plan))
+    }
+}
+
+impl Brain for External {
+    fn think(&mut self, state: &BotState) -> Option<Plan> {
+        match self.suggest(state) {
+            Ok(plan) => plan,
+            Err(e) => {
+                eprintln!("[-] {}: {}", self.name, e);
+                None
+            }
+        }
+    }
+}
+
+impl Drop for External {
+    fn drop(&mut self) {
+        let _ = self.send(&FrontendMessage::Quit);
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tetromino::Color;
+
+    fn state(rows: &[&str], kind: Kind) -> BotState {
+        let mut board = Board::new();
+        let top = BOARD_HEIGHT - rows.len();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                if c == 'G' {
+                    board.set_cell(x, top + y, Some(Color::Gray));
+                }
+            }
+        }
+        start_state(&Start {
+            hold: None,
+            queue: vec![kind.into()],
+            combo: 0,
+            back_to_back: false,
+            board: encode_board(&board),
+        })
+        .unwrap()
+    }
+
+    // Every placement, sent as TBP JSON and read back, names the same cells and spin
+    fn round_trip(state: &BotState) -> Vec<Plan> {
+        let plans = state.plans();
+        assert!(!plans.is_empty());
+        for plan in &plans {
+            let json = serde_json::to_string(&encode_move(&plan.placement)).unwrap();
+            let mv: Move = serde_json::from_str(&json).unwrap();
+            let found = find_plan(state.plans(), &mv).unwrap();
+            assert_eq!(
+                sorted_cells(found.placement.cells()),
+                sorted_cells(plan.placement.cells()),
+                "{}",
+                json
+            );
+            assert_eq!(found.placement.tspin, plan.placement.tspin, "{}", json);
+        }
+        plans
+    }
+
+    #[test]
+    fn i_placements_round_trip() {
+        let state = state(&[], Kind::I);
+        round_trip(&state);
+        // Rotations with the same cells as another only come up once among the plans, so
+        // try every rotation's drops here
+        for rotation in 0..4 {
+            let mut dropped = 0;
+            for x in -2..BOARD_WIDTH as i32 + 2 {
+                for y in BOARD_HEIGHT as i32 - 4..BOARD_HEIGHT as i32 {
+                    let placement = Placement {
+                        kind: Kind::I,
+                        rotation,
+                        position: Point { x, y },
+                        last_kick: None,
+                        tspin: TSpin::None,
+                    };
+                    let cells = placement.cells();
+                    let inside = cells.iter().all(|p| {
+                        (0..BOARD_WIDTH as i32).contains(&p.x) && p.y < BOARD_HEIGHT as i32
+                    });
+                    if !inside || cells.iter().all(|p| p.y != BOARD_HEIGHT as i32 - 1) {
+                        continue;
+                    }
+                    let json = serde_json::to_string(&encode_move(&placement)).unwrap();
+                    let mv: Move = serde_json::from_str(&json).unwrap();
+                    let found = find_plan(state.plans(), &mv).unwrap();
+                    assert_eq!(
+                        sorted_cells(found.placement.cells()),
+                        sorted_cells(cells),
+                        "{}",
+                        json
+                    );
+                    dropped += 1;
+                }
+            }
+            // Seven columns flat, ten standing
+            assert_eq!(dropped, [7, 10][rotation as usize % 2]);
+        }
+    }
+
+    #[test]
+    fn t_spins_round_trip() {
+        let plans = round_trip(&state(&["...G......", "GGG...GGGG", "GGGG.GGGGG"], Kind::T));
+        let tsd = plans
+            .iter()
+            .find(|p| p.placement.tspin == TSpin::Full)
+            .unwrap();
+        let mv = encode_move(&tsd.placement);
+        assert_eq!(mv.spin, Spin::Full);
+        assert_eq!(mv.location.orientation, Orientation::South);
+        // The center sits over the hole, a row up from the bottom
+        assert_eq!((mv.location.x, mv.location.y), (4, 1));
+    }
+}
@@ -1,188 +1,309 @@
-pub mod tetromino {
-    use rand::Rng;
-
-    #[derive(Debug, PartialEq, Copy, Clone)]
-    pub enum Color {
-        Black,
-        Cyan,
-        Purple,
-        Green,
-        Red,
-        Blue,
-        Orange,
-    }
-
-    #[derive(PartialEq, Copy, Clone)]
-    pub enum Direction {
-        CCW,
-        CW,
-    }
-    #[derive(Debug)]
-    pub struct Tetromino {
-        pub color: Color,
-        pub shape: Vec<Vec<u8>>,
-    }
-    impl Clone for Tetromino {
-        fn clone(&self) -> Tetromino {
-            let mut t = Tetromino {
-                color: self.color,
-                shape: Vec::with_capacity(self.shape.len()),
-            };
-
-            for row in &self.shape {
-                t.shape.push(row.clone());
-            }
-            t
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+#[cfg(feature = "save")]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub enum Color {
+    Black,
+    Cyan,
+    Yellow,
+    Purple,
+    Green,
+    Red,
+    Blue,
+    Orange,
+    Gray,
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Direction {
+    CCW,
+    CW,
+    Half,
+}
+
+/// Which of the seven tetrominoes a piece is, independent of its rotation.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Copy, Clone)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub enum Kind {
+    O,
+    L,
+    J,
+    T,
+    S,
+    Z,
+    I,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 7] = [
+        Kind::O,
+        Kind::L,
+        Kind::J,
+        Kind::T,
+        Kind::S,
+        Kind::Z,
+        Kind::I,
+    ];
+
+    pub fn letter(self) -> char {
+        match self {
+            Kind::O => 'O',
+            Kind::L => 'L',
+            Kind::J => 'J',
+            Kind::T => 'T',
+            Kind::S => 'S',
+            Kind::Z => 'Z',
+            Kind::I => 'I',
         }
     }
 
-    impl Tetromino {
-        pub fn new_o() -> Tetromino {
-            Tetromino {
-                color: Color::Cyan,
-                shape: vec![vec![1, 1], vec![1, 1]],
-            }
+    pub fn from_letter(letter: char) -> Option<Kind> {
+        Kind::ALL
+            .iter()
+            .copied()
+            .find(|k| k.letter() == letter.to_ascii_uppercase())
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct Tetromino {
+    pub kind: Kind,
+    pub color: Color,
+    pub shape: Vec<Vec<u8>>,
+    // 0 = spawn, 1 = right, 2 = upside down, 3 = left
+    pub rotation: u8,
+}
+impl Clone for Tetromino {
+    fn clone(&self) -> Tetromino {
+        let mut t = Tetromino {
+            kind: self.kind,
+            color: self.color,
+            shape: Vec::with_capacity(self.shape.len()),
+            rotation: self.rotation,
+        };
+
+        for row in &self.shape {
+            t.shape.push(row.clone());
         }
+        t
+    }
+}
 
-        pub fn new_l() -> Tetromino {
-            Tetromino {
-                color: Color::Orange,
-                shape: vec![vec![0, 0, 1], vec![1, 1, 1], vec![0, 0, 0]],
-            }
+impl Tetromino {
+    pub fn new(kind: Kind) -> Tetromino {
+        match kind {
+            Kind::O => Tetromino::new_o(),
+            Kind::L => Tetromino::new_l(),
+            Kind::J => Tetromino::new_j(),
+            Kind::T => Tetromino::new_t(),
+            Kind::S => Tetromino::new_s(),
+            Kind::Z => Tetromino::new_z(),
+            Kind::I => Tetromino::new_i(),
         }
+    }
 
-        pub fn new_j() -> Tetromino {
-            Tetromino {
-                color: Color::Blue,
-                shape: vec![vec![1, 0, 0], vec![1, 1, 1], vec![0, 0, 0]],
-            }
+    pub fn new_o() -> Tetromino {
+        Tetromino {
+            kind: Kind::O,
+            color: Color::Yellow,
+            shape: vec![vec![1, 1], vec![1, 1]],
+            rotation: 0,
         }
+    }
 
-        pub fn new_t() -> Tetromino {
-            Tetromino {
-                color: Color::Purple,
-                shape: vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 0, 0]],
-            }
+    pub fn new_l() -> Tetromino {
+        Tetromino {
+            kind: Kind::L,
+            color: Color::Orange,
+            shape: vec![vec![0, 0, 1], vec![1, 1, 1], vec![0, 0, 0]],
+            rotation: 0,
         }
+    }
 
-        pub fn new_s() -> Tetromino {
-            Tetromino {
-                color: Color::Green,
-                shape: vec![vec![0, 1, 1], vec![1, 1, 0], vec![0, 0, 0]],
-            }
+    pub fn new_j() -> Tetromino {
+        Tetromino {
+            kind: Kind::J,
+            color: Color::Blue,
+            shape: vec![vec![1, 0, 0], vec![1, 1, 1], vec![0, 0, 0]],
+            rotation: 0,
         }
+    }
 
-        pub fn new_z() -> Tetromino {
-            Tetromino {
-                color: Color::Red,
-                shape: vec![vec![1, 1, 0], vec![0, 1, 1], vec![0, 0, 0]],
-            }
+    pub fn new_t() -> Tetromino {
+        Tetromino {
+            kind: Kind::T,
+            color: Color::Purple,
+            shape: vec![vec![0, 1, 0], vec![1, 1, 1], vec![0, 0, 0]],
+            rotation: 0,
         }
+    }
 
-        pub fn new_i() -> Tetromino {
-            Tetromino {
-                color: Color::Black,
-                shape: vec![
-                    vec![0, 0, 0, 0],
-                    vec![1, 1, 1, 1],
-                    vec![0, 0, 0, 0],
-                    vec![0, 0, 0, 0],
-                ],
-            }
+    pub fn new_s() -> Tetromino {
+        Tetromino {
+            kind: Kind::S,
+            color: Color::Green,
+            shape: vec![vec![0, 1, 1], vec![1, 1, 0], vec![0, 0, 0]],
+            rotation: 0,
         }
+    }
 
-        pub fn rotate(&mut self, direction: Direction) {
-            let size = self.shape.len();
-            match direction {
-                Direction::CCW => {
-                    for row in 0..size / 2 {
-                        for col in row..(size - row - 1) {
-                            let t = self.shape[row][col];
-                            self.shape[row][col] = self.shape[col][size - row - 1];
-                            self.shape[col][size - row - 1] =
-                                self.shape[size - row - 1][size - col - 1];
-                            self.shape[size - row - 1][size - col - 1] =
-                                self.shape[size - col - 1][row];
-                            self.shape[size - col - 1][row] = t;
-                        }
+    pub fn new_z() -> Tetromino {
+        Tetromino {
+            kind: Kind::Z,
+            color: Color::Red,
+            shape: vec![vec![1, 1, 0], vec![0, 1, 1], vec![0, 0, 0]],
+            rotation: 0,
+        }
+    }
+
+    pub fn new_i() -> Tetromino {
+        Tetromino {
+            kind: Kind::I,
+            color: Color::Cyan,
+            shape: vec![
+                vec![0, 0, 0, 0],
+                vec![1, 1, 1, 1],
+                vec![0, 0, 0, 0],
+                vec![0, 0, 0, 0],
+            ],
+            rotation: 0,
+        }
+    }
+
+    pub fn rotate(&mut self, direction: Direction) {
+        let size = self.shape.len();
+        match direction {
+            Direction::CCW => {
+                for row in 0..size / 2 {
+                    for col in row..(size - row - 1) {
+                        let t = self.shape[row][col];
+                        self.shape[row][col] = self.shape[col][size - row - 1];
+                        self.shape[col][size - row - 1] =
+                            self.shape[size - row - 1][size - col - 1];
+                        self.shape[size - row - 1][size - col - 1] =
+                            self.shape[size - col - 1][row];
+                        self.shape[size - col - 1][row] = t;
                     }
                 }
-                Direction::CW => {
-                    for row in 0..size / 2 {
-                        for col in row..(size - row - 1) {
-                            let t = self.shape[row][col];
-                            self.shape[row][col] = self.shape[size - col - 1][row];
-                            self.shape[size - col - 1][row] =
-                                self.shape[size - row - 1][size - col - 1];
-                            self.shape[size - row - 1][size - col - 1] =
-                                self.shape[col][size - row - 1];
-                            self.shape[col][size - row - 1] = t;
-                        }
+                self.rotation = (self.rotation + 3) % 4;
+            }
+            Direction::CW => {
+                for row in 0..size / 2 {
+                    for col in row..(size - row - 1) {
+                        let t = self.shape[row][col];
+                        self.shape[row][col] = self.shape[size - col - 1][row];
+                        self.shape[size - col - 1][row] =
+                            self.shape[size - row - 1][size - col - 1];
+                        self.shape[size - row - 1][size - col - 1] =
+                            self.shape[col][size - row - 1];
+                        self.shape[col][size - row - 1] = t;
                     }
                 }
+                self.rotation = (self.rotation + 1) % 4;
+            }
+            Direction::Half => {
+                self.rotate(Direction::CW);
+                self.rotate(Direction::CW);
             }
         }
+    }
 
-        // Rust needs the size of the type at compile time; otherwise it needs dyn or Box<>
-        pub fn each_point(&self, f: &mut dyn FnMut(i32, i32)) {
-            let n = self.shape.len() as i32;
-            for row in 0..n {
-                for col in 0..n {
-                    if self.shape[row as usize][col as usize] != 0 {
-                        f(row, col);
-                    }
+    // Rust needs the size of the type at compile time; otherwise it needs dyn or Box<>
+    pub fn each_point(&self, f: &mut dyn FnMut(i32, i32)) {
+        let n = self.shape.len() as i32;
+        for row in 0..n {
+            for col in 0..n {
+                if self.shape[row as usize][col as usize] != 0 {
+                    f(row, col);
                 }
             }
         }
     }
+}
 
-    // We use random permutations within groups of 7 to prevent certain variant droughts
-    #[derive(Debug)]
-    pub struct TetrominoQueue {
-        pub tetrominoes: Vec<Tetromino>,
+// We use random permutations within groups of 7 to prevent certain variant droughts.
+// The queue owns its own seeded rng so that the same seed always deals the same pieces.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "save", derive(Serialize, Deserialize))]
+pub struct TetrominoQueue {
+    pub tetrominoes: Vec<Tetromino>,
+    rng: Pcg32,
+}
+impl Default for TetrominoQueue {
+    fn default() -> Self {
+        TetrominoQueue::new()
     }
-    impl Default for TetrominoQueue {
-        fn default() -> Self {
-            TetrominoQueue::new()
-        }
+}
+impl TetrominoQueue {
+    pub fn new() -> TetrominoQueue {
+        TetrominoQueue::with_seed(rand::random())
     }
-    impl TetrominoQueue {
-        pub fn new() -> TetrominoQueue {
-            let mut q = TetrominoQueue {
-                tetrominoes: Vec::new(),
-            };
-            q.add_seven();
-            q
-        }
 
-        /// Returns a copy of the next tetromino in the queue.
-        pub fn peek(&mut self) -> Tetromino {
-            match self.tetrominoes.first() {
-                Some(t) => t.clone(),
-                None => {
-                    self.add_seven();
-                    self.peek()
-                }
-            }
+    pub fn with_seed(seed: u64) -> TetrominoQueue {
+        let mut q = TetrominoQueue {
+            tetrominoes: Vec::new(),
+            rng: Pcg32::seed_from_u64(seed),
+        };
+        q.add_seven();
+        q
+    }
+
+    /// A queue that deals `kinds` in order first, then carries on with bags from `seed`.
+    pub fn with_pieces(kinds: &[Kind], seed: u64) -> TetrominoQueue {
+        let mut q = TetrominoQueue {
+            tetrominoes: kinds.iter().map(|k| Tetromino::new(*k)).collect(),
+            rng: Pcg32::seed_from_u64(seed),
+        };
+        q.add_seven();
+        q
+    }
+
+    /// Removes and returns the next tetromino, refilling so at least 7 stay visible.
+    pub fn pop(&mut self) -> Tetromino {
+        if self.tetrominoes.len() <= 7 {
+            self.add_seven();
         }
+        self.tetrominoes.remove(0)
+    }
 
-        /// Generates & adds random ordering of all variants to queue.
-        pub fn add_seven(&mut self) {
-            let mut all_variants: Vec<Tetromino> = vec![
-                Tetromino::new_o(),
-                Tetromino::new_l(),
-                Tetromino::new_j(),
-                Tetromino::new_t(),
-                Tetromino::new_s(),
-                Tetromino::new_z(),
-                Tetromino::new_i(),
-            ];
-
-            while !self.tetrominoes.is_empty() {
-                // gen_range(start..=end) is inclusive
-                let i = rand::thread_rng().gen_range(0..=all_variants.len());
-                self.tetrominoes.push(all_variants.swap_remove(i));
-            }
+    /// Returns the next tetromino in the queue without removing it.
+    pub fn peek(&self) -> &Tetromino {
+        &self.tetrominoes[0]
+    }
+
+    /// Returns up to `n` upcoming tetrominoes, in order.
+    pub fn preview(&self, n: usize) -> &[Tetromino] {
+        &self.tetrominoes[..n.min(self.tetrominoes.len())]
+    }
+
+    /// The kinds still to come in the bag that the piece after the first `n` belongs to,
+    /// not counting those first `n`. Empty when that piece starts a fresh bag.
+    pub fn bag_after(&self, n: usize) -> Vec<Kind> {
+        // The queue always ends on a bag boundary
+        let n = n.min(self.tetrominoes.len());
+        let left = (self.tetrominoes.len() - n) % 7;
+        let mut kinds: Vec<Kind> = self.tetrominoes[n..n + left]
+            .iter()
+            .map(|t| t.kind)
+            .collect();
+        kinds.sort();
+        kinds
+    }
+
+    /// Generates & adds random ordering of all variants to queue.
+    pub fn add_seven(&mut self) {
+        let mut all_variants: Vec<Tetromino> =
+            Kind::ALL.iter().map(|k| Tetromino::new(*k)).collect();
+
+        while !all_variants.is_empty() {
+            // gen_range(start..end) is exclusive
+            let i = self.rng.gen_range(0..all_variants.len());
+            self.tetrominoes.push(all_variants.swap_remove(i));
         }
     }
 }
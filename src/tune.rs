@@ -0,0 +1,431 @@
+use crate::beam::BeamSearch;
+use crate::bot::BotState;
+use crate::eval::Weights;
+use crate::simulation::{Event, Input, Rules, Simulation};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+
+/// What a set of weights is scored on, per game.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Objective {
+    /// Lines cleared before topping out under steady garbage.
+    Survival,
+    /// Attack sent per piece; topping out early counts as sending nothing after.
+    Attack,
+    /// 1 for beating the baseline weights, 0 for losing, 0.5 if neither tops out.
+    Versus,
+}
+
+impl Objective {
+    pub fn parse(name: &str) -> Option<Objective> {
+        match name {
+            "survival" => Some(Objective::Survival),
+            "attack" => Some(Objective::Attack),
+            "versus" => Some(Objective::Versus),
+            _ => None,
+        }
+    }
+}
+
+/// How each game is played.
+#[derive(Debug, Clone)]
+pub struct Trial {
+    pub objective: Objective,
+    /// The search every player uses, with its weights swapped for the ones on trial.
+    pub search: BeamSearch,
+    pub rules: Rules,
+    pub max_pieces: usize,
+    /// Survival games queue a line of garbage every this many pieces.
+    pub garbage_every: usize,
+    /// Who versus games are played against.
+    pub baseline: Weights,
+}
+
+impl Default for Trial {
+    fn default() -> Self {
+        Trial {
+            objective: Objective::Survival,
+            // Shallow and time-unlimited, so games are quick and repeat from their seeds
+            search: BeamSearch {
+                width: 4,
+                depth: 2,
+                think_time: std::time::Duration::from_secs(3600),
+                weights: Weights::default(),
+            },
+            rules: Rules::default(),
+            max_pieces: 500,
+            garbage_every: 3,
+            baseline: Weights::default(),
+        }
+    }
+}
+
+impl Trial {
+    /// Plays one game from `seed` and scores `weights` on it.
+    pub fn score(&self, weights: &Weights, seed: u64) -> f32 {
+        let mut simulation = Simulation::new(seed, self.rules.clone());
+        let mut players = vec![(1, *weights)];
+        if self.objective == Objective::Versus {
+            players.push((2, self.baseline));
+        }
+        for (id, _) in &players {
+            simulation.add_player(*id);
+        }
+        let mut winner = None;
+        while !simulation.is_over() {
+            let mut inputs = Vec::new();
+            for (id, weights) in &players {
+                let Some(state) = simulation.player(*id).filter(|s| s.alive) else {
+                    continue;
+                };
+                let search = BeamSearch {
+                    weights: *weights,
+                    ..self.search.clone()
+                };
+                let plan = search.search(&BotState::from_player(state, &self.rules));
+                let plan = plan.map_or(vec![Input::HardDrop], |plan| plan.inputs);
+                inputs.extend(plan.into_iter().map(|input| (*id, input)));
+            }
+            for event in simulation.step(&inputs) {
+                match event {
+                    Event::PieceLocked { id, .. } if self.objective == Objective::Survival => {
+                        let pieces = simulation.player(id).unwrap().pieces;
+                        if pieces.is_multiple_of(self.garbage_every.max(1)) {
+                            simulation.queue_garbage(id, 1);
+                        }
+                    }
+                    Event::GameOver { winner: w } => winner = w,
+                    _ => {}
+                }
+            }
+            if simulation.player(1).unwrap().pieces >= self.max_pieces {
+                break;
+            }
+        }
+
+        let player = simulation.player(1).unwrap();
+        match self.objective {
+            Objective::Survival => player.lines as f32,
+            Objective::Attack => player.attack as f32 / self.max_pieces.max(1) as f32,
+            Objective::Versus => match winner {
+                Some(1) => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            },
+        }
+    }
+}
+
+/// Searches for the weight vector with the best score, a generation at a time:
+/// `ask` for candidates, score them, `tell` the scores in the same order.
+pub trait Optimizer {
+    fn ask(&mut self) -> Vec<Vec<f32>>;
+    fn tell(&mut self, scores: &[f32]);
+}
+
+// Candidates are searched in units of each weight's own size, so a step of 1 means
+// about as much for a weight near 20 as for one near 0.5
+fn scales(start: &[f32]) -> Vec<f32> {
+    start.iter().map(|w| w.abs().max(0.5)).collect()
+}
+
+fn normal(rng: &mut Pcg32) -> f32 {
+    // Box-Muller
+    let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+    let v: f32 = rng.gen();
+    (-2.0 * u.ln()).sqrt() * (std::f32::consts::TAU * v).cos()
+}
+
+/// A plain genetic algorithm: the best quarter survive, the rest are children of
+/// two tournament winners with uniform crossover and gaussian mutation.
+#[derive(Debug, Clone)]
+pub struct Genetic {
+    pub population: Vec<Vec<f32>>,
+    /// Standard deviation of mutations, relative to each weight's size.
+    pub mutation: f32,
+    scales: Vec<f32>,
+    rng: Pcg32,
+}
+
+impl Genetic {
+    pub fn new(start: &[f32], size: usize, seed: u64) -> Self {
+        let mut genetic = Genetic {
+            population: Vec::new(),
+            mutation: 0.2,
+            scales: scales(start),
+            rng: Pcg32::seed_from_u64(seed),
+        };
+        genetic.population.push(start.to_vec());
+        while genetic.population.len() < size.max(2) {
+            let child = genetic.mutate(start.to_vec());
+            genetic.population.push(child);
+        }
+        genetic
+    }
+
+    fn mutate(&mut self, mut genes: Vec<f32>) -> Vec<f32> {
+        for (gene, scale) in genes.iter_mut().zip(&self.scales) {
+            *gene += self.mutation * scale * normal(&mut self.rng);
+        }
+        genes
+    }
+}
+
+impl Optimizer for Genetic {
+    fn ask(&mut self) -> Vec<Vec<f32>> {
+        self.population.clone()
+    }
+
+    fn tell(&mut self, scores: &[f32]) {
+        let size = self.population.len();
+        let mut ranked: Vec<(Vec<f32>, f32)> = self
+            .population
+            .drain(..)
+            .zip(scores.iter().copied())
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let elite = (size / 4).max(1);
+        self.population = ranked[..elite].iter().map(|(g, _)| g.clone()).collect();
+        while self.population.len() < size {
+            let mut pick = || {
+                let a = self.rng.gen_range(0..ranked.len());
+                let b = self.rng.gen_range(0..ranked.len());
+                if ranked[a].1 >= ranked[b].1 {
+                    a
+                } else {
+                    b
+                }
+            };
+            let (a, b) = (pick(), pick());
+            let child = ranked[a]
+                .0
+                .iter()
+                .zip(&ranked[b].0)
+                .map(|(x, y)| if self.rng.gen() { *x } else { *y })
+                .collect();
+            let child = self.mutate(child);
+            self.population.push(child);
+        }
+    }
+}
+
+/// Separable CMA-ES: an evolution strategy that adapts its step size and a
+/// per-weight variance from which candidates did best. Keeping only the diagonal of
+/// the covariance is cheap and learns faster with this few weights.
+#[derive(Debug, Clone)]
+pub struct Cmaes {
+    start: Vec<f32>,
+    scales: Vec<f32>,
+    // Everything below is in scaled units, relative to `start`
+    mean: Vec<f32>,
+    sigma: f32,
+    variance: Vec<f32>,
+    path_sigma: Vec<f32>,
+    path_c: Vec<f32>,
+    lambda: usize,
+    weights: Vec<f32>,
+    mu_eff: f32,
+    c_sigma: f32,
+    d_sigma: f32,
+    c_c: f32,
+    c_1: f32,
+    c_mu: f32,
+    generation: i32,
+    // The steps sampled for the current generation
+    steps: Vec<Vec<f32>>,
+    rng: Pcg32,
+}
+
+impl Cmaes {
+    /// `size` of 0 picks the usual 4 + 3 ln(n).
+    pub fn new(start: &[f32], size: usize, seed: u64) -> Self {
+        let n = start.len() as f32;
+        let lambda = if size == 0 {
+            4 + (3.0 * n.ln()) as usize
+        } else {
+            size.max(2)
+        };
+        let mu = lambda / 2;
+        let raw: Vec<f32> = (0..mu)
+            .map(|i| (mu as f32 + 0.5).ln() - (i as f32 + 1.0).ln())
+            .collect();
+        let total: f32 = raw.iter().sum();
+        let weights: Vec<f32> = raw.iter().map(|w| w / total).collect();
+        let mu_eff = 1.0 / weights.iter().map(|w| w * w).sum::<f32>();
+        let c_sigma = (mu_eff + 2.0) / (n + mu_eff + 5.0);
+        let d_sigma = 1.0 + 2.0 * (((mu_eff - 1.0) / (n + 1.0)).sqrt() - 1.0).max(0.0) + c_sigma;
+        let c_c = (4.0 + mu_eff / n) / (n + 4.0 + 2.0 * mu_eff / n);
+        // The diagonal can afford faster learning rates than the full matrix
+        let speedup = (n + 2.0) / 3.0;
+        let c_1 = (speedup * 2.0 / ((n + 1.3).powi(2) + mu_eff)).min(1.0);
+        let c_mu = (speedup * 2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((n + 2.0).powi(2) + mu_eff))
+            .min(1.0 - c_1);
+        Cmaes {
+            start: start.to_vec(),
+            scales: scales(start),
+            mean: vec![0.0; start.len()],
+            sigma: 0.3,
+            variance: vec![1.0; start.len()],
+            path_sigma: vec![0.0; start.len()],
+            path_c: vec![0.0; start.len()],
+            lambda,
+            weights,
+            mu_eff,
+            c_sigma,
+            d_sigma,
+            c_c,
+            c_1,
+            c_mu,
+            generation: 0,
+            steps: Vec::new(),
+            rng: Pcg32::seed_from_u64(seed),
+        }
+    }
+
+    fn unscale(&self, point: &[f32]) -> Vec<f32> {
+        point
+            .iter()
+            .zip(&self.start)
+            .zip(&self.scales)
+            .map(|((x, start), scale)| start + x * scale)
+            .collect()
+    }
+
+    /// The current best guess.
+    pub fn mean(&self) -> Vec<f32> {
+        self.unscale(&self.mean)
+    }
+}
+
+impl Optimizer for Cmaes {
+    fn ask(&mut self) -> Vec<Vec<f32>> {
+        let n = self.mean.len();
+        self.steps = (0..self.lambda)
+            .map(|_| {
+                (0..n)
+                    .map(|i| self.variance[i].sqrt() * normal(&mut self.rng))
+                    .collect()
+            })
+            .collect();
+        self.steps
+            .iter()
+            .map(|step| {
+                let point: Vec<f32> = self
+                    .mean
+                    .iter()
+                    .zip(step)
+                    .map(|(m, y)| m + self.sigma * y)
+                    .collect();
+                self.unscale(&point)
+            })
+            .collect()
+    }
+
+    fn tell(&mut self, scores: &[f32]) {
+        let n = self.mean.len();
+        let mut order: Vec<usize> = (0..self.steps.len()).collect();
+        order.sort_by(|a, b| scores[*b].total_cmp(&scores[*a]));
+        let best: Vec<&Vec<f32>> = order
+            .iter()
+            .take(self.weights.len())
+            .map(|i| &self.steps[*i])
+            .collect();
+        let step: Vec<f32> = (0..n)
+            .map(|i| best.iter().zip(&self.weights).map(|(y, w)| w * y[i]).sum())
+            .collect();
+        for (m, y) in self.mean.iter_mut().zip(&step) {
+            *m += self.sigma * y;
+        }
+
+        self.generation += 1;
+        let norm = self.c_sigma * (2.0 - self.c_sigma) * self.mu_eff;
+        for ((p, y), v) in self.path_sigma.iter_mut().zip(&step).zip(&self.variance) {
+            *p = (1.0 - self.c_sigma) * *p + norm.sqrt() * y / v.sqrt();
+        }
+        let length = self.path_sigma.iter().map(|p| p * p).sum::<f32>().sqrt();
+        let nf = n as f32;
+        let expected = nf.sqrt() * (1.0 - 1.0 / (4.0 * nf) + 1.0 / (21.0 * nf * nf));
+        let decay = 1.0 - (1.0 - self.c_sigma).powi(2 * self.generation);
+        let h_sigma = length / decay.sqrt() < (1.4 + 2.0 / (nf + 1.0)) * expected;
+        let h = h_sigma as u8 as f32;
+        let norm = self.c_c * (2.0 - self.c_c) * self.mu_eff;
+        for i in 0..n {
+            self.path_c[i] = (1.0 - self.c_c) * self.path_c[i] + h * norm.sqrt() * step[i];
+            let rank_mu: f32 = best
+                .iter()
+                .zip(&self.weights)
+                .map(|(y, w)| w * y[i] * y[i])
+                .sum();
+            self.variance[i] = (1.0 - self.c_1 - self.c_mu) * self.variance[i]
+                + self.c_1
+                    * (self.path_c[i].powi(2)
+                        + (1.0 - h) * self.c_c * (2.0 - self.c_c) * self.variance[i])
+                + self.c_mu * rank_mu;
+        }
+        self.sigma *= ((self.c_sigma / self.d_sigma) * (length / expected - 1.0)).exp();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: [f32; 4] = [3.0, -2.0, 0.5, 8.0];
+
+    // Higher is better, at most 0 on the target
+    fn toy(point: &[f32]) -> f32 {
+        -point
+            .iter()
+            .zip(&TARGET)
+            .map(|(x, t)| (x - t).powi(2))
+            .sum::<f32>()
+    }
+
+    // The best score each of `generations` asks for
+    fn run(optimizer: &mut impl Optimizer, generations: usize) -> Vec<f32> {
+        (0..generations)
+            .map(|_| {
+                let scores: Vec<f32> = optimizer.ask().iter().map(|c| toy(c)).collect();
+                optimizer.tell(&scores);
+                scores.into_iter().fold(f32::MIN, f32::max)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn genetic_improves_on_the_start() {
+        let start = [1.0; 4];
+        let mut genetic = Genetic::new(&start, 16, 7);
+        assert_eq!(genetic.ask().len(), 16);
+        assert_eq!(genetic.ask()[0], start);
+        let best = run(&mut genetic, 60);
+        // The elite survive, so the best never gets worse
+        assert!(best.windows(2).all(|w| w[1] >= w[0]));
+        assert!(best[59] > toy(&start) / 10.0, "{:?}", best);
+        assert_eq!(run(&mut Genetic::new(&start, 16, 7), 60), best);
+    }
+
+    #[test]
+    fn cmaes_moves_its_mean_to_the_best() {
+        let start = [1.0; 4];
+        let mut cmaes = Cmaes::new(&start, 0, 7);
+        assert_eq!(cmaes.mean(), start);
+        let best = run(&mut cmaes, 60);
+        // 4 + 3 ln 4
+        assert_eq!(cmaes.ask().len(), 8);
+        assert!(
+            toy(&cmaes.mean()) > toy(&start) / 100.0,
+            "{:?}",
+            cmaes.mean()
+        );
+        assert!(best[59] > best[0]);
+        assert_eq!(run(&mut Cmaes::new(&start, 0, 7), 60), best);
+    }
+
+    #[test]
+    fn objectives_parse_by_name() {
+        assert_eq!(Objective::parse("versus"), Some(Objective::Versus));
+        assert_eq!(Objective::parse("attack"), Some(Objective::Attack));
+        assert_eq!(Objective::parse("Survival"), None);
+    }
+}